@@ -1,144 +1,574 @@
-use chrono::{NaiveDateTime, TimeZone};
+mod caldav;
+mod countdown;
+mod feed;
+mod filter;
+mod recurrence;
+mod reminder;
+mod timezone;
 
+pub use countdown::Countdown;
+
+use countdown::CountdownConfig;
+use filter::{CalendarConfig, Filter};
+use recurrence::{RRule, Until};
+use reminder::{ReminderRule, Trigger};
+use timezone::{Zone, Zones};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+use std::collections::BTreeMap;
 use std::io::BufReader;
 use ical::IcalParser;
-use reqwest::header::{HeaderMap, USER_AGENT};
-use reqwest::Client;
+use ical::parser::ical::component::IcalEvent;
+use ical::property::Property;
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
 use tokio::fs::File as tFile;
 use tokio::io::AsyncReadExt;
-use serde_json;
 use futures::future::join_all;
-use log::info;
+use crate::status;
+use log::{info, warn};
 use std::time::Instant;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CalendarEvent {
     pub start_time: DateTime<chrono::Utc>,
+    pub end_time: DateTime<chrono::Utc>,
     pub name: String,
+    pub location: Option<String>,
+    pub all_day: bool,
+    /* The display name and marker style of the calendar it came from */
+    pub calendar: Option<String>,
+    pub marker: Option<String>,
+    /* When to start showing it as a reminder, from its VALARMs or a reminder rule */
+    pub alarm: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AgendaDay {
+    /* In local time, an event that started on an earlier day (but is still going) is listed today */
+    pub date: NaiveDate,
+    pub events: Vec<CalendarEvent>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Agenda {
+    pub days: Vec<AgendaDay>,
+    /* Calendars that could not be fetched, their last good copy (if any) is still shown */
+    pub failed: Vec<String>,
+    /*
+        Every local day with something on over the next few weeks (however many events the
+        agenda lists), with the marker of each event's calendar, for the month grid
+    */
+    pub busy_days: BTreeMap<NaiveDate, Vec<Option<String>>>,
+    /* Soonest first, see calendar/countdown.rs */
+    pub countdowns: Vec<Countdown>,
+    /* Events whose reminder is due (or soon will be) before they start, see calendar/reminder.rs */
+    pub reminders: Vec<CalendarEvent>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CalendarState<'a> {
+    /* A (timed) event has started and not yet ended, the one ending first if several have */
+    InProgress(&'a CalendarEvent),
+    /* Free until this event, later today */
+    NextUp(&'a CalendarEvent),
+    /* Nothing else on today, with the next event on a later day if there is one */
+    FreeToday(Option<&'a CalendarEvent>),
+}
+
+impl Agenda {
+    fn events(&self) -> impl Iterator<Item = &CalendarEvent> {
+        self.days.iter().flat_map(|day| &day.events)
+    }
+
+    pub fn reminder(&self, now: DateTime<Utc>) -> Option<&CalendarEvent> {
+        // The next event to start that is in its reminder window
+        self.reminders.iter()
+            .filter(|event| event.alarm.is_some_and(|alarm| alarm <= now) && event.start_time > now)
+            .min_by_key(|event| event.start_time)
+    }
+
+    pub fn state(&self, now: DateTime<Utc>) -> CalendarState<'_> {
+        // All day events (holidays, birthdays) do not make anyone busy
+        let in_progress = self.events()
+            .filter(|event| !event.all_day && event.start_time <= now && event.end_time > now)
+            .min_by_key(|event| event.end_time);
+        if let Some(event) = in_progress {
+            return CalendarState::InProgress(event)
+        }
+
+        let today = now.with_timezone(&chrono::Local).date_naive();
+        let next_today = self.events()
+            .find(|event| !event.all_day && event.start_time > now && event.start_time.with_timezone(&chrono::Local).date_naive() == today);
+
+        match next_today {
+            Some(event) => CalendarState::NextUp(event),
+            None => CalendarState::FreeToday(self.events().find(|event| event.start_time.with_timezone(&chrono::Local).date_naive() > today))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AgendaConfig {
+    /* Whichever runs out first, the next `events` events or everything in the next `days` days */
+    #[serde(default = "default_agenda_events")]
+    events: usize,
+    #[serde(default = "default_agenda_days")]
+    days: i64,
+}
+
+fn default_agenda_events() -> usize {10}
+fn default_agenda_days() -> i64 {7}
+
+impl Default for AgendaConfig {
+    fn default() -> AgendaConfig {
+        AgendaConfig { events: default_agenda_events(), days: default_agenda_days() }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct CalendarUrls {
+    /* Plain urls, or calendars with their own settings (see calendar/filter.rs) */
+    #[serde(default)]
     urls: Vec<String>,
+    #[serde(default)]
+    calendars: Vec<CalendarConfig>,
+    #[serde(default)]
+    agenda: AgendaConfig,
+    #[serde(default)]
+    countdown: CountdownConfig,
+    #[serde(default)]
+    reminders: Vec<ReminderRule>,
 }
 
-async fn fetch_ics(url: &str) -> Result<String, reqwest::Error> {
-    let custom_user_agent = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/79.0.3945.79 Safari/537.36";
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, custom_user_agent.parse().unwrap());
-    
-    let response = Client::new().get(url).headers(headers).send().await?;
-    response.text().await
+impl CalendarUrls {
+    fn calendars(&self) -> Vec<CalendarConfig> {
+        self.urls.iter().map(|url| CalendarConfig::from_url(url)).chain(self.calendars.iter().cloned()).collect()
+    }
 }
 
 fn parse_datetime(s: &str) -> Result<DateTime<Utc>, String> {
     let utc_formats = [
-        "%Y%m%dT%H%M%SZ",
         "%Y-%m-%dT%H:%M:%S%z",
         "%Y-%m-%dT%H:%M:%S%:z",
     ];
 
-    let local_formats = [
+    let local_date_formats = [
         "%Y-%m-%d",
         "%Y%m%d",
         "%Y-%m-%d (%a)",
         "%Y-W%W-%u",
-        "%H:%M:%S",
-        "%H:%M:%S%.f",
-        "%I:%M:%S %p",
-        
+    ];
+
+    let local_formats = [
         "%Y%m%dT%H%M%S",
         "%Y-%m-%dT%H:%M:%S%.f",
         "%Y-W%W-%uT%H:%M:%S",
         "%a, %d %b %Y %H:%M:%S",
-        "%d %B %Y %H:%M:%S",     
+        "%d %B %Y %H:%M:%S",
         "%Y/%m/%d %H-%M-%S",
         "%Y-%j %H:%M:%S",
     ];
 
-    for format in local_formats.iter() {
+    if let Ok(time) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%SZ") {
+        return Ok(Utc.from_utc_datetime(&time));
+    }
 
-        let naive_date = chrono::NaiveDate::parse_from_str(s, format);
-        
-        if let Ok(naive_date) = naive_date {
-            let local_date_time = chrono::Local.from_local_datetime(&naive_date.and_hms_opt(0, 0, 0).unwrap())
-                .single()
-                .expect("Failed to convert to local DateTime");
-            return Ok(local_date_time.into());
+    for format in utc_formats.iter() {
+        if let Ok(time) = DateTime::parse_from_str(s, format) {
+            return Ok(time.with_timezone(&Utc));
         }
     }
 
+    for format in local_formats.iter() {
+        if let Ok(time) = NaiveDateTime::parse_from_str(s, format) {
+            return Zone::Local.to_utc(time).ok_or("Time does not exist locally".to_string());
+        }
+    }
 
-    for format in utc_formats.iter() {
-        let naive_date = chrono::NaiveDate::parse_from_str(s, format);
-        if let Ok(naive_date) = naive_date {
-            let naive_date_time: NaiveDateTime = naive_date.into();
-            let time = Utc.from_utc_datetime(&naive_date_time);
-            return Ok(time);
+    for format in local_date_formats.iter() {
+        if let Ok(date) = NaiveDate::parse_from_str(s, format) {
+            return Zone::Local.to_utc(date.and_time(NaiveTime::MIN)).ok_or("Time does not exist locally".to_string());
         }
     }
-    
+
     Err("Failed to parse datetime".to_string())
 }
 
-fn parse_ics(data: &str) -> Vec<CalendarEvent> {
+#[derive(Debug, Clone)]
+struct IcalTime {
+    /* As written in the ics file, on the wall clock of its zone */
+    time: NaiveDateTime,
+    zone: Zone,
+    all_day: bool,
+}
+
+impl IcalTime {
+    fn to_utc(&self) -> Option<DateTime<Utc>> {
+        self.zone.to_utc(self.time)
+    }
+}
+
+fn param<'a>(prop: &'a Property, name: &str) -> Option<&'a str> {
+    let (_, values) = prop.params.as_ref()?.iter().find(|(key, _)| key.eq_ignore_ascii_case(name))?;
+    values.first().map(|value| value.as_str())
+}
+
+fn parse_ical_time(value: &str, tzid: Option<&str>, zones: &Zones) -> Option<IcalTime> {
+    let (value, utc) = match value.strip_suffix('Z') {
+        Some(value) => (value, true),
+        None => (value, false)
+    };
+
+    if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        let zone = match (utc, tzid) {
+            (true, _) => Zone::Utc,
+            (false, Some(tzid)) => zones.get(tzid),
+            // Floating time, the same wall clock time wherever the dashboard is
+            (false, None) => Zone::Local,
+        };
+        return Some(IcalTime { time, zone, all_day: false })
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        // A whole day, which starts at midnight wherever the dashboard is
+        return Some(IcalTime { time: date.and_time(NaiveTime::MIN), zone: Zone::Local, all_day: true })
+    }
+
+    // Anything more exotic
+    parse_datetime(value).ok().map(|time| IcalTime { time: time.naive_utc(), zone: Zone::Utc, all_day: false })
+}
+
+fn read_ical_times(prop: &Property, zones: &Zones) -> Vec<IcalTime> {
+    /*
+        DTSTART, EXDATE etc, honouring TZID and VALUE=DATE.
+        EXDATE and RDATE can hold several comma separated times.
+    */
+    let value = match &prop.value {
+        Some(value) => value,
+        None => return vec![]
+    };
+    let tzid = param(prop, "TZID");
+    let date_only = param(prop, "VALUE").is_some_and(|v| v.eq_ignore_ascii_case("DATE"));
+
+    value.split(',')
+        .map(|v| v.trim())
+        .map(|v| if date_only {v.get(..8).unwrap_or(v)} else {v})
+        .filter_map(|v| parse_ical_time(v, tzid, zones))
+        .collect()
+}
+
+fn read_ical_time(prop: &Property, zones: &Zones) -> Option<IcalTime> {
+    read_ical_times(prop, zones).into_iter().next()
+}
+
+fn parse_duration(value: &str) -> Option<chrono::Duration> {
+    // DURATION and TRIGGER values, eg P1D, PT1H30M, P2W, -PT15M
+    let (sign, value) = match value.trim().strip_prefix('-') {
+        Some(value) => (-1, value),
+        None => (1, value.trim().trim_start_matches('+'))
+    };
+    let value = value.strip_prefix('P')?;
+
+    let mut duration = chrono::Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' if number.is_empty() => in_time = true,
+            _ => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                duration += match (c, in_time) {
+                    ('W', false) => chrono::Duration::weeks(n),
+                    ('D', false) => chrono::Duration::days(n),
+                    ('H', true) => chrono::Duration::hours(n),
+                    ('M', true) => chrono::Duration::minutes(n),
+                    ('S', true) => chrono::Duration::seconds(n),
+                    _ => return None
+                };
+            }
+        }
+    }
+
+    if !number.is_empty() {
+        return None
+    }
+    Some(duration * sign)
+}
+
+fn unescape(value: &str) -> String {
+    // TEXT values escape commas, semicolons, backslashes and newlines
+    value.replace("\\n", " ")
+        .replace("\\N", " ")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
+
+#[derive(Debug, Default)]
+struct VEvent {
+    uid: Option<String>,
+    name: Option<String>,
+    location: Option<String>,
+    start: Option<IcalTime>,
+    end: Option<IcalTime>,
+    duration: Option<chrono::Duration>,
+    categories: Vec<String>,
+    transparent: bool,
+    declined: bool,
+    rrule: Option<String>,
+    exdates: Vec<IcalTime>,
+    rdates: Vec<IcalTime>,
+    recurrence_id: Option<IcalTime>,
+    cancelled: bool,
+    alarms: Vec<Trigger>,
+}
+
+fn mailto(value: &str) -> String {
+    let value = value.trim();
+    let address = if value.to_lowercase().starts_with("mailto:") {&value[7..]} else {value};
+    address.to_lowercase()
+}
+
+fn read_event(event: &IcalEvent, zones: &Zones, owner: Option<&str>) -> VEvent {
+    let mut result = VEvent {
+        alarms: event.alarms.iter().filter_map(|alarm| reminder::read_alarm(alarm, zones)).collect(),
+        ..Default::default()
+    };
+
+    for prop in &event.properties {
+        let value = match &prop.value {
+            Some(value) => value,
+            None => continue
+        };
+
+        match prop.name.as_str() {
+            "DTSTART" => result.start = read_ical_time(prop, zones),
+            "DTEND" => result.end = read_ical_time(prop, zones),
+            "DURATION" => result.duration = parse_duration(value),
+            "SUMMARY" => result.name = Some(unescape(value)),
+            "LOCATION" => result.location = Some(unescape(value)).filter(|location| !location.trim().is_empty()),
+            "UID" => result.uid = Some(value.clone()),
+            "RRULE" => result.rrule = Some(value.clone()),
+            "EXDATE" => result.exdates.extend(read_ical_times(prop, zones)),
+            "RDATE" => result.rdates.extend(read_ical_times(prop, zones)),
+            "RECURRENCE-ID" => result.recurrence_id = read_ical_time(prop, zones),
+            "STATUS" => result.cancelled = value == "CANCELLED",
+            "CATEGORIES" => result.categories.extend(value.split(',').map(|c| unescape(c.trim()))),
+            "TRANSP" => result.transparent = value == "TRANSPARENT",
+            "ATTENDEE" => {
+                let declined = param(prop, "PARTSTAT").is_some_and(|p| p.eq_ignore_ascii_case("DECLINED"));
+                if declined && owner.is_some_and(|owner| mailto(value) == mailto(owner)) {
+                    result.declined = true
+                }
+            },
+            _ => {}
+        }
+    }
+
+    result
+}
+
+fn occurrences(event: &VEvent, start: &IcalTime, window_end: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+    let rule = event.rrule.as_ref().map(|rrule| RRule::parse(rrule));
+
+    let mut times = match rule {
+        Some(Ok(rule)) => {
+            let until = rule.until.as_ref().map(|until| match until {
+                Until::Local(until) => *until,
+                Until::Utc(until) => start.zone.wall_clock(Utc.from_utc_datetime(until)),
+            });
+            let window_end = start.zone.wall_clock(window_end);
+
+            rule.expand(start.time, until, window_end)
+                .into_iter()
+                .filter_map(|time| start.zone.to_utc(time))
+                .collect()
+        },
+        Some(Err(e)) => {
+            warn!("Could not understand {:?}, only using its first occurrence: {e}", event.rrule);
+            start.to_utc().into_iter().collect()
+        },
+        None => start.to_utc().into_iter().collect::<Vec<_>>()
+    };
+
+    times.extend(event.rdates.iter().filter_map(|t| t.to_utc()));
+
+    let exdates: Vec<_> = event.exdates.iter().filter_map(|t| t.to_utc()).collect();
+    times.retain(|t| !exdates.contains(t));
+
+    times.sort();
+    times.dedup();
+    times
+}
+
+/* How far ahead events are read, from both ics feeds and CalDAV */
+const WINDOW_DAYS: i64 = 400;
+
+fn parse_ics(data: &str, now: DateTime<Utc>, filter: &Filter) -> Vec<CalendarEvent> {
+    /*
+        Recurring events are expanded up to a year (and a bit) ahead, since that is as far as a
+        yearly event (birthdays etc) can be from its next occurrence.
+    */
     let buf = BufReader::new(data.as_bytes());
     let reader = IcalParser::new(buf);
+    let window_end = now + chrono::Duration::days(WINDOW_DAYS);
+
+    let vevents: Vec<VEvent> = reader
+        .flatten()
+        .flat_map(|calendar| {
+            let zones = Zones::new(&calendar.timezones);
+
+            // Google names a person's own calendar after their address
+            let calendar_name = calendar.properties.iter()
+                .find(|p| p.name == "X-WR-CALNAME")
+                .and_then(|p| p.value.clone())
+                .filter(|name| name.contains('@'));
+            let owner = filter.config.email.clone().or(calendar_name);
+
+            calendar.events.iter().map(|event| read_event(event, &zones, owner.as_deref())).collect::<Vec<_>>()
+        })
+        .collect();
+
+    // Single occurrences that were moved, renamed or cancelled, keyed by the series and original start
+    let overrides: Vec<(&str, DateTime<Utc>)> = vevents.iter()
+        .filter_map(|event| Some((event.uid.as_deref()?, event.recurrence_id.as_ref()?.to_utc()?)))
+        .collect();
+
     let mut events = Vec::new();
-    let now = Utc::now();
-
-    for line in reader {
-        if let Ok(line) = line {
-            for cal in line.events {
-                let mut start_time = None;
-                let mut name = None;
-
-                for prop in cal.properties {
-                    match prop.name.as_str() {
-                        
-                        // Get the start of the event
-                        // Note! We assume that if a "z" is not present, then it is in local time
-
-                        "DTSTART" => {
-                            if let Some(value) = prop.value {
-
-                                let utc_time = parse_datetime(&value);
-                                
-                                if let Ok(utc_time) = utc_time {
-                                    start_time = Some(utc_time)
-                                }
-                            }
-                        }
-                        "SUMMARY" => {
-                            name = prop.value.clone();
-                        }
-                        _ => {}
-                    }
-                }
-                
-                if let (Some(start_time), Some(name)) = (start_time, name) {
 
-                    if start_time > now {
-                        events.push(CalendarEvent { start_time, name });
-                    }
-                }
+    for event in &vevents {
+        let start = match &event.start {
+            Some(start) => start,
+            None => continue
+        };
+        let length = event_length(event, start);
 
-                
+        if event.recurrence_id.is_some() {
+            // An override of one occurrence, it replaces that occurrence of the series
+            if event.cancelled {
+                continue
             }
 
+            let master = vevents.iter()
+                .find(|master| master.recurrence_id.is_none() && master.uid.is_some() && master.uid == event.uid);
+            let name = event.name.clone().or_else(|| master.and_then(|master| master.name.clone()));
+            let location = event.location.clone().or_else(|| master.and_then(|master| master.location.clone()));
+            let alarms = match master {
+                Some(master) if event.alarms.is_empty() => &master.alarms,
+                _ => &event.alarms
+            };
+
+            if let (Some(start_time), Some(name)) = (start.to_utc(), name) {
+                if filter.keep(event, &name) {
+                    events.push(CalendarEvent {
+                        start_time,
+                        end_time: start_time + length,
+                        name,
+                        location,
+                        all_day: start.all_day,
+                        calendar: filter.config.name.clone(),
+                        marker: filter.config.marker.clone(),
+                        alarm: reminder::alarm_time(alarms, start_time, start_time + length, false)
+                    });
+                }
+            }
+            continue
+        }
+
+        let name = match &event.name {
+            Some(name) if filter.keep(event, name) => name,
+            _ => continue
+        };
+
+        let recurring = event.rrule.is_some() || !event.rdates.is_empty();
+
+        for start_time in occurrences(event, start, window_end) {
+            let overridden = event.uid.as_deref()
+                .is_some_and(|uid| overrides.contains(&(uid, start_time)));
+
+            if !overridden && !event.cancelled {
+                events.push(CalendarEvent {
+                    start_time,
+                    end_time: start_time + length,
+                    name: name.clone(),
+                    location: event.location.clone(),
+                    all_day: start.all_day,
+                    calendar: filter.config.name.clone(),
+                    marker: filter.config.marker.clone(),
+                    alarm: reminder::alarm_time(&event.alarms, start_time, start_time + length, recurring)
+                });
+            }
         }
     }
 
+    // Events stay on the screen until they end, all day events for as long as their day(s) last
+    events.retain(|event| event.start_time > now || event.end_time > now);
     events
 }
 
+fn event_length(event: &VEvent, start: &IcalTime) -> chrono::Duration {
+    // Without a DTEND or DURATION, a timed event takes no time at all and an all day event takes the day
+    let end = event.end.as_ref().and_then(|end| end.to_utc());
+
+    match (start.to_utc(), end, event.duration) {
+        (Some(start), Some(end), _) if end > start => end - start,
+        (_, _, Some(duration)) if duration > chrono::Duration::zero() => duration,
+        _ if start.all_day => chrono::Duration::days(1),
+        _ => chrono::Duration::zero()
+    }
+}
+
+const BUSY_DAYS: i64 = 42;
+
+fn build_agenda(events: Vec<CalendarEvent>, config: &AgendaConfig, now: DateTime<Utc>) -> Agenda {
+    // Expects events sorted by start time
+    let today = now.with_timezone(&chrono::Local).date_naive();
+    let last_day = today + chrono::Duration::days(config.days.max(1) - 1);
 
+    let mut agenda = Agenda::default();
 
+    // Enough for any month grid that includes today
+    let last_busy_day = today + chrono::Duration::days(BUSY_DAYS);
+    for event in &events {
+        let first = event.start_time.with_timezone(&chrono::Local).date_naive().max(today);
+        // An event ending at midnight does not take up the next day
+        let last = (event.end_time.max(event.start_time + chrono::Duration::seconds(1)) - chrono::Duration::seconds(1))
+            .with_timezone(&chrono::Local).date_naive()
+            .min(last_busy_day);
+
+        for date in first.iter_days().take_while(|date| *date <= last) {
+            agenda.busy_days.entry(date).or_default().push(event.marker.clone());
+        }
+    }
+
+    // Reminders due before the next fetch (which is a day away at most)
+    agenda.reminders = events.iter()
+        .filter(|event| event.start_time > now && event.alarm.is_some_and(|alarm| alarm <= now + chrono::Duration::days(1)))
+        .cloned()
+        .collect();
+
+    for event in events.into_iter().take(config.events) {
+        let date = event.start_time.with_timezone(&chrono::Local).date_naive().max(today);
+        if date > last_day {
+            break
+        }
 
-pub async fn fetch_event() -> Result<Option<CalendarEvent>, String> {
+        match agenda.days.last_mut() {
+            Some(day) if day.date == date => day.events.push(event),
+            _ => agenda.days.push(AgendaDay { date, events: vec![event] })
+        }
+    }
+
+    agenda
+}
+
+pub async fn fetch_agenda() -> Result<Agenda, String> {
     info!("Fetching calendar..");
     let now = Instant::now();
 
@@ -154,37 +584,385 @@ pub async fn fetch_event() -> Result<Option<CalendarEvent>, String> {
     let calendar_urls: CalendarUrls = serde_json::from_slice(&contents)
         .map_err(|e| e.to_string())?;
     
-    // Fetch all ICS data concurrently
-    let fetch_futures: Vec<_> = calendar_urls.urls
-        .iter()
-        .map(|url| fetch_ics(url))
-        .collect();
-    
-    let fetched_ics_data = join_all(fetch_futures)
-        .await
-        .into_iter()
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| e.to_string())?;
-    
-    // Parse all ICS data concurrently
-    let parse_futures: Vec<_> = fetched_ics_data
-        .into_iter()
-        .map(|ics_data| async move { parse_ics(&ics_data) })
-        .collect();
-    
-    let mut all_events: Vec<_> = join_all(parse_futures)
-        .await
-        .into_iter()
-        .flatten()
-        .collect();
-    
+    let configured = calendar_urls.calendars();
+
+    let mut all_events = Vec::new();
+    let mut failed = Vec::new();
+    let mut errors = Vec::new();
+    let mut read = 0;
+
+    // A calendar with an invalid include/exclude is left out rather than shown unfiltered
+    let mut calendars = Vec::new();
+    let mut filters = Vec::new();
+    for (calendar, label) in configured.iter().zip(filter::labels(&configured)) {
+        match Filter::new(calendar) {
+            Ok(filter) => {
+                calendars.push((calendar, label));
+                filters.push(filter);
+            },
+            Err(e) => {
+                warn!("Calendar {label} skipped: {e}");
+                errors.push(format!("{label}: {e}"));
+                failed.push(label);
+            }
+        }
+    }
+
+    // Fetch all ICS data concurrently, each calendar succeeding or failing on its own
+    let cache = feed::Cache::new("cache/calendar");
+    let feeds = join_all(calendars.iter().map(|(calendar, _)| status::timed(feed::fetch(calendar, &cache)))).await;
+
+    for (((_, label), filter), (feed, took)) in calendars.into_iter().zip(&filters).zip(feeds) {
+        let result = match &feed.error {Some(e) => Err(e.clone()), None => Ok(())};
+        status::record(&format!("calendar/{label}"), took, &result);
+
+        if let Some(e) = feed.error {
+            warn!("Calendar {label} failed: {e}");
+            errors.push(format!("{label}: {e}"));
+            failed.push(label);
+        }
+
+        if let Some(body) = feed.body {
+            all_events.extend(parse_ics(&body, Utc::now(), filter));
+            read += 1;
+        }
+    }
+
+    if !configured.is_empty() && read == 0 {
+        return Err(errors.join(", "))
+    }
+
     all_events.sort_by_key(|d| d.start_time);
-    all_events.reverse();
-        
-    let next_event = all_events.pop();
+    reminder::apply(&calendar_urls.reminders, &mut all_events);
+    let countdowns = countdown::upcoming(&calendar_urls.countdown, &all_events, chrono::Local::now().date_naive());
+
+    let mut agenda = build_agenda(all_events, &calendar_urls.agenda, Utc::now());
+    agenda.failed = failed;
+    agenda.countdowns = countdowns;
 
     let elapsed = format!("{:.2?}", now.elapsed());
     info!("Calendar took {elapsed}");
 
-    Ok(next_event)
+    Ok(agenda)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%SZ").unwrap())
+    }
+
+    fn parse_sorted(data: &str, now: &str) -> Vec<CalendarEvent> {
+        parse_filtered(data, now, &CalendarConfig::default())
+    }
+
+    fn parse_filtered(data: &str, now: &str, config: &CalendarConfig) -> Vec<CalendarEvent> {
+        let mut events = parse_ics(data, utc(now), &Filter::new(config).unwrap());
+        events.sort_by_key(|event| event.start_time);
+        events
+    }
+
+    #[test]
+    fn expands_recurring_events() {
+        let events = parse_sorted(include_str!("fixtures/calendar/recurring.ics"), "20240605T000000Z");
+
+        let first: Vec<_> = events.iter().take(4).map(|e| (e.start_time, e.name.as_str())).collect();
+        assert_eq!(first, [
+            (utc("20240606T120000Z"), "Lunch"),
+            (utc("20240611T010000Z"), "Standup (moved)"),
+            (utc("20240617T230000Z"), "Standup"),
+            (utc("20240619T230000Z"), "Standup"),
+        ]);
+    }
+
+    #[test]
+    fn applies_exdates_and_overrides() {
+        let events = parse_sorted(include_str!("fixtures/calendar/recurring.ics"), "20240605T000000Z");
+        let standups: Vec<_> = events.iter().filter(|e| e.name.starts_with("Standup")).map(|e| e.start_time).collect();
+
+        assert!(!standups.contains(&utc("20240605T230000Z")), "excluded by EXDATE");
+        assert!(!standups.contains(&utc("20240610T230000Z")), "moved by RECURRENCE-ID");
+        assert!(!standups.contains(&utc("20240612T230000Z")), "cancelled by RECURRENCE-ID");
+    }
+
+    #[test]
+    fn includes_rdates_and_drops_finished_series() {
+        let events = parse_sorted(include_str!("fixtures/calendar/recurring.ics"), "20240605T000000Z");
+
+        let dentist: Vec<_> = events.iter().filter(|e| e.name == "Dentist").map(|e| e.start_time).collect();
+        assert_eq!(dentist, [utc("20240620T100000Z"), utc("20240720T100000Z")]);
+
+        assert!(events.iter().all(|e| e.name != "Pay bills" && e.name != "Old"));
+    }
+
+    #[test]
+    fn yearly_events_from_long_ago() {
+        let events = parse_sorted(include_str!("fixtures/calendar/recurring.ics"), "20240605T000000Z");
+
+        let birthdays: Vec<_> = events.iter()
+            .filter(|e| e.name == "Mum's birthday")
+            .map(|e| e.start_time.with_timezone(&chrono::Local).date_naive())
+            .collect();
+        assert_eq!(birthdays, [chrono::NaiveDate::from_ymd_opt(2025, 5, 27).unwrap()]);
+    }
+
+    fn starts(events: &[CalendarEvent], name: &str) -> Vec<DateTime<Utc>> {
+        events.iter().filter(|e| e.name == name).map(|e| e.start_time).collect()
+    }
+
+    fn local_date(event: &CalendarEvent) -> NaiveDate {
+        event.start_time.with_timezone(&chrono::Local).date_naive()
+    }
+
+    #[test]
+    fn keeps_the_time_of_utc_values() {
+        assert_eq!(parse_datetime("20240527T093000Z"), Ok(utc("20240527T093000Z")));
+        assert_eq!(parse_datetime("2024-05-27T09:30:00+10:00"), Ok(utc("20240526T233000Z")));
+    }
+
+    #[test]
+    fn google_export() {
+        let events = parse_sorted(include_str!("fixtures/calendar/google.ics"), "20240301T000000Z");
+
+        assert_eq!(starts(&events, "Oral defense"), [utc("20240526T233000Z")]);
+        assert_eq!(starts(&events, "Call with London"), [utc("20240528T093000Z")]);
+
+        // Weekly at 9am Sydney time, across the end of daylight saving on the 7th of April
+        assert_eq!(starts(&events, "Team meeting"), [
+            utc("20240324T220000Z"),
+            utc("20240331T220000Z"),
+            utc("20240407T230000Z"),
+            utc("20240414T230000Z"),
+        ]);
+
+        let due = events.iter().find(|e| e.name == "Assignment due").unwrap();
+        assert!(due.all_day);
+        assert_eq!(local_date(due), NaiveDate::from_ymd_opt(2024, 6, 1).unwrap());
+        assert!(events.iter().filter(|e| e.name != "Assignment due").all(|e| !e.all_day));
+    }
+
+    #[test]
+    fn outlook_export_with_windows_zone_names() {
+        let events = parse_sorted(include_str!("fixtures/calendar/outlook.ics"), "20240501T000000Z");
+
+        assert_eq!(starts(&events, "Dentist"), [utc("20240526T233000Z")]);
+        assert_eq!(starts(&events, "Summer BBQ"), [utc("20250115T070000Z")]);
+        assert_eq!(starts(&events, "Sync with Bangalore"), [utc("20240529T043000Z")]);
+
+        let holiday = events.iter().find(|e| e.name == "Public holiday").unwrap();
+        assert!(holiday.all_day);
+        assert_eq!(local_date(holiday), NaiveDate::from_ymd_opt(2024, 6, 10).unwrap());
+    }
+
+    #[test]
+    fn apple_export() {
+        let events = parse_sorted(include_str!("fixtures/calendar/apple.ics"), "20240501T000000Z");
+
+        assert_eq!(starts(&events, "Train to Edinburgh"), [utc("20240701T130000Z")]);
+        // 1:30am happens twice that night, the first (still summer time) one is used
+        assert_eq!(starts(&events, "Clocks go back"), [utc("20241027T003000Z")]);
+
+        let christmas: Vec<_> = events.iter().filter(|e| e.name == "Christmas").collect();
+        assert!(christmas.iter().all(|e| e.all_day));
+        assert_eq!(christmas.iter().map(|e| local_date(e)).collect::<Vec<_>>(), [NaiveDate::from_ymd_opt(2024, 12, 25).unwrap()]);
+    }
+
+    #[test]
+    fn agenda_groups_by_day_with_limits() {
+        let now = utc("20240301T000000Z");
+        let events = parse_sorted(include_str!("fixtures/calendar/google.ics"), "20240301T000000Z");
+        let config = AgendaConfig { events: 10, days: 60 };
+
+        let agenda = build_agenda(events, &config, now);
+        let days: Vec<_> = agenda.days.iter().map(|day| (day.date, day.events.len())).collect();
+        assert_eq!(days.len(), 4, "one day per weekly meeting, {days:?}");
+        assert!(agenda.days.iter().all(|day| day.events.iter().all(|e| e.name == "Team meeting")));
+
+        let meeting = &agenda.days[0].events[0];
+        assert_eq!(meeting.end_time - meeting.start_time, chrono::Duration::minutes(30));
+
+        let config = AgendaConfig { events: 2, days: 60 };
+        let events = parse_sorted(include_str!("fixtures/calendar/google.ics"), "20240301T000000Z");
+        assert_eq!(build_agenda(events, &config, now).days.iter().map(|day| day.events.len()).sum::<usize>(), 2);
+    }
+
+    #[test]
+    fn all_day_events_stay_until_their_day_ends() {
+        // Whatever zone the machine is in, the 1st of june has not ended by midnight utc
+        let events = parse_sorted(include_str!("fixtures/calendar/google.ics"), "20240601T000000Z");
+        let due = events.iter().find(|e| e.name == "Assignment due").expect("still on today");
+
+        assert_eq!(due.end_time - due.start_time, chrono::Duration::days(1));
+        assert!(events.iter().all(|e| e.all_day || e.start_time > utc("20240601T000000Z")));
+    }
+
+    #[test]
+    fn reads_locations_and_unescapes_text() {
+        let events = parse_sorted(include_str!("fixtures/calendar/apple.ics"), "20240501T000000Z");
+        let train = events.iter().find(|e| e.name == "Train to Edinburgh").unwrap();
+
+        assert_eq!(train.location.as_deref(), Some("Kings Cross, London"));
+        assert_eq!(train.end_time, utc("20240701T140000Z"));
+    }
+
+    fn names(events: &[CalendarEvent]) -> Vec<&str> {
+        let mut names: Vec<_> = events.iter().map(|e| e.name.as_str()).collect();
+        names.dedup();
+        names
+    }
+
+    #[test]
+    fn filters_by_summary_and_categories() {
+        let google = include_str!("fixtures/calendar/google.ics");
+
+        let config = CalendarConfig { include: Some("(?i)finance|meeting".to_string()), ..Default::default() };
+        assert_eq!(names(&parse_filtered(google, "20240301T000000Z", &config)), ["Team meeting", "Budget review"]);
+
+        let config = CalendarConfig { exclude: Some("^(Team|Oral)".to_string()), ..Default::default() };
+        assert_eq!(names(&parse_filtered(google, "20240301T000000Z", &config)), ["Call with London", "Budget review", "Assignment due"]);
+
+        assert!(Filter::new(&CalendarConfig { include: Some("(".to_string()), ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn hides_declined_and_transparent_events() {
+        let google = include_str!("fixtures/calendar/google.ics");
+
+        let config = CalendarConfig { hide_declined: true, hide_transparent: true, email: Some("ME@example.com".to_string()), ..Default::default() };
+        let events = parse_filtered(google, "20240501T000000Z", &config);
+        assert_eq!(names(&events), ["Oral defense", "Call with London"]);
+
+        // Without knowing whose calendar it is, nothing counts as declined
+        let config = CalendarConfig { hide_declined: true, ..Default::default() };
+        assert!(names(&parse_filtered(google, "20240501T000000Z", &config)).contains(&"Budget review"));
+    }
+
+    #[test]
+    fn events_carry_their_calendar() {
+        let config: CalendarUrls = serde_json::from_str(r#"{
+            "urls": ["https://example.com/a.ics"],
+            "calendars": [{"url": "https://example.com/b.ics", "name": "Uni", "marker": "hatch"}]
+        }"#).unwrap();
+        let calendars = config.calendars();
+        assert_eq!(calendars.iter().map(|c| c.url.as_str()).collect::<Vec<_>>(), ["https://example.com/a.ics", "https://example.com/b.ics"]);
+
+        let events = parse_filtered(include_str!("fixtures/calendar/google.ics"), "20240501T000000Z", &calendars[1]);
+        assert!(events.iter().all(|e| e.calendar.as_deref() == Some("Uni") && e.marker.as_deref() == Some("hatch")));
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(chrono::Duration::minutes(90)));
+        assert_eq!(parse_duration("P1DT12H"), Some(chrono::Duration::hours(36)));
+        assert_eq!(parse_duration("P2W"), Some(chrono::Duration::days(14)));
+        assert_eq!(parse_duration("-PT15M"), Some(chrono::Duration::minutes(-15)));
+        assert_eq!(parse_duration("PT5"), None);
+        assert_eq!(parse_duration("1H"), None);
+    }
+
+    const MEETINGS: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n\
+        BEGIN:VEVENT\r\nUID:review\r\nDTSTART:20240603T090000Z\r\nDURATION:PT1H30M\r\nSUMMARY:Design review\r\nEND:VEVENT\r\n\
+        BEGIN:VEVENT\r\nUID:sync\r\nDTSTART:20240603T100000Z\r\nDTEND:20240603T101500Z\r\nSUMMARY:Quick sync\r\nEND:VEVENT\r\n\
+        BEGIN:VEVENT\r\nUID:lunch\r\nDTSTART:20240603T120000Z\r\nDTEND:20240603T130000Z\r\nSUMMARY:Lunch\r\nEND:VEVENT\r\n\
+        BEGIN:VEVENT\r\nUID:holiday\r\nDTSTART;VALUE=DATE:20240610\r\nSUMMARY:Holiday\r\nEND:VEVENT\r\n\
+        END:VCALENDAR\r\n";
+
+    #[test]
+    fn reads_durations_and_keeps_running_events() {
+        let events = parse_sorted(MEETINGS, "20240603T100500Z");
+
+        let review = events.iter().find(|e| e.name == "Design review").expect("still running");
+        assert_eq!(review.end_time, utc("20240603T103000Z"));
+        assert_eq!(names(&events), ["Design review", "Quick sync", "Lunch", "Holiday"]);
+
+        assert!(parse_sorted(MEETINGS, "20240603T103000Z").iter().all(|e| e.name != "Design review"));
+    }
+
+    #[test]
+    fn calendar_state() {
+        let agenda = |now: &str| build_agenda(parse_sorted(MEETINGS, now), &AgendaConfig { events: 10, days: 30 }, utc(now));
+        let state = |now: &str| match agenda(now).state(utc(now)) {
+            CalendarState::InProgress(event) => format!("in progress: {}", event.name),
+            CalendarState::NextUp(event) => format!("next up: {}", event.name),
+            CalendarState::FreeToday(event) => format!("free today, then: {:?}", event.map(|e| e.name.clone())),
+        };
+
+        // The overlapping sync ends first
+        assert_eq!(state("20240603T100500Z"), "in progress: Quick sync");
+        assert_eq!(state("20240603T103000Z"), "next up: Lunch");
+        assert_eq!(state("20240603T210000Z"), "free today, then: Some(\"Holiday\")");
+    }
+
+    #[test]
+    fn marks_busy_days() {
+        let now = utc("20240603T100500Z");
+        let calendar = CalendarConfig { marker: Some("hatch".to_string()), ..Default::default() };
+        let agenda = build_agenda(parse_filtered(MEETINGS, "20240603T100500Z", &calendar), &AgendaConfig { events: 1, days: 1 }, now);
+
+        let meetings = local_date(&parse_sorted(MEETINGS, "20240603T100500Z")[0]);
+        let holiday = NaiveDate::from_ymd_opt(2024, 6, 10).unwrap();
+        let hatch = Some("hatch".to_string());
+
+        // However few events the agenda lists
+        assert_eq!(agenda.busy_days.keys().copied().collect::<Vec<_>>(), [meetings, holiday]);
+        assert_eq!(agenda.busy_days[&meetings], [hatch.clone(), hatch.clone(), hatch.clone()]);
+        assert_eq!(agenda.busy_days[&holiday], [hatch]);
+    }
+
+    const ALARMS: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n\
+        BEGIN:VEVENT\r\nUID:dentist\r\nDTSTART:20240603T100000Z\r\nDTEND:20240603T110000Z\r\nSUMMARY:Dentist\r\n\
+        BEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER:-PT30M\r\nEND:VALARM\r\n\
+        BEGIN:VALARM\r\nACTION:EMAIL\r\nTRIGGER:-P1D\r\nEND:VALARM\r\n\
+        END:VEVENT\r\n\
+        BEGIN:VEVENT\r\nUID:parking\r\nDTSTART:20240603T120000Z\r\nDTEND:20240603T140000Z\r\nSUMMARY:Parking runs out\r\n\
+        BEGIN:VALARM\r\nACTION:AUDIO\r\nTRIGGER;RELATED=END:-PT2H30M\r\nEND:VALARM\r\n\
+        END:VEVENT\r\n\
+        BEGIN:VEVENT\r\nUID:gym\r\nDTSTART:20240603T180000Z\r\nRRULE:FREQ=DAILY;COUNT=3\r\nSUMMARY:Gym\r\n\
+        BEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER;VALUE=DATE-TIME:20240603T170000Z\r\nEND:VALARM\r\n\
+        END:VEVENT\r\n\
+        BEGIN:VEVENT\r\nUID:interview\r\nDTSTART:20240604T090000Z\r\nSUMMARY:Job interview\r\nEND:VEVENT\r\n\
+        END:VCALENDAR\r\n";
+
+    fn alarm(events: &[CalendarEvent], name: &str) -> Option<DateTime<Utc>> {
+        events.iter().find(|e| e.name == name).unwrap().alarm
+    }
+
+    #[test]
+    fn reads_alarms() {
+        let events = parse_sorted(ALARMS, "20240603T000000Z");
+
+        // The email alarm a day before does not count
+        assert_eq!(alarm(&events, "Dentist"), Some(utc("20240603T093000Z")));
+        assert_eq!(alarm(&events, "Parking runs out"), Some(utc("20240603T113000Z")));
+        // A fixed time means nothing to a daily event
+        assert_eq!(alarm(&events, "Gym"), None);
+        assert_eq!(alarm(&events, "Job interview"), None);
+    }
+
+    #[test]
+    fn reminder_rules_and_windows() {
+        let config: CalendarUrls = serde_json::from_str(r#"{"reminders": [{"match": "(?i)interview", "minutes": 60}]}"#).unwrap();
+        let mut events = parse_sorted(ALARMS, "20240603T000000Z");
+        reminder::apply(&config.reminders, &mut events);
+        assert_eq!(alarm(&events, "Job interview"), Some(utc("20240604T080000Z")));
+
+        let now = utc("20240603T094500Z");
+        let agenda = build_agenda(events, &AgendaConfig::default(), now);
+        let reminder = |now: &str| agenda.reminder(utc(now)).map(|e| e.name.as_str());
+
+        // Not yet due, due, and gone once the event starts
+        assert_eq!(reminder("20240603T092000Z"), None);
+        assert_eq!(reminder("20240603T094500Z"), Some("Dentist"));
+        assert_eq!(reminder("20240603T100000Z"), None);
+        assert_eq!(reminder("20240603T113500Z"), Some("Parking runs out"));
+
+        // An invalid rule is skipped, and the others still apply
+        let bad: CalendarUrls = serde_json::from_str(r#"{"reminders": [{"match": "(", "minutes": 5}, {"match": "(?i)gym", "minutes": 30}]}"#).unwrap();
+        let mut events = parse_sorted(ALARMS, "20240603T000000Z");
+        reminder::apply(&bad.reminders, &mut events);
+        assert!(alarm(&events, "Gym").is_some());
+        assert_eq!(alarm(&events, "Job interview"), None);
+    }
 }
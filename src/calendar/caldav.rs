@@ -0,0 +1,148 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Method};
+
+/*
+    Just enough CalDAV (RFC 4791) to read events: a REPORT calendar-query on a calendar
+    collection for the events overlapping a time range, whose calendar-data are joined
+    back together into one ics feed.
+*/
+
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+
+fn calendar_query(start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    format!(r#"<?xml version="1.0" encoding="utf-8" ?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="{CALDAV}">
+  <D:prop>
+    <C:calendar-data />
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        <C:time-range start="{}" end="{}" />
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#, start.format("%Y%m%dT%H%M%SZ"), end.format("%Y%m%dT%H%M%SZ"))
+}
+
+fn calendar_data(multistatus: &str) -> Result<String, String> {
+    let document = roxmltree::Document::parse(multistatus).map_err(|e| format!("Invalid CalDAV response: {e}"))?;
+
+    // XML turns the CRLFs ics needs into plain newlines, so they are put back
+    let calendars: Vec<String> = document.descendants()
+        .filter(|node| node.has_tag_name((CALDAV, "calendar-data")))
+        .filter_map(|node| node.text())
+        .map(|text| text.trim())
+        .filter(|text| !text.is_empty())
+        .map(|text| text.lines().collect::<Vec<_>>().join("\r\n"))
+        .collect();
+
+    Ok(calendars.join("\r\n"))
+}
+
+pub async fn fetch(url: &str, username: Option<&str>, password: Option<&str>, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<String, String> {
+    let report = Method::from_bytes(b"REPORT").map_err(|e| e.to_string())?;
+    let mut request = Client::new().request(report, url)
+        .header("Depth", "1")
+        .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(calendar_query(start, end));
+
+    if let Some(username) = username {
+        request = request.basic_auth(username, password);
+    }
+
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("Request failed with status: {status}"))
+    }
+
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    calendar_data(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, Reply};
+    use chrono::TimeZone;
+
+    const MULTISTATUS: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <d:response>
+    <d:href>/dav/calendars/me/home/standup.ics</d:href>
+    <d:propstat>
+      <d:prop>
+        <cal:calendar-data>BEGIN:VCALENDAR&#13;
+VERSION:2.0&#13;
+BEGIN:VEVENT&#13;
+UID:standup&#13;
+DTSTART:20240603T230000Z&#13;
+SUMMARY:Standup &amp; coffee&#13;
+END:VEVENT&#13;
+END:VCALENDAR&#13;
+</cal:calendar-data>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/calendars/me/home/dentist.ics</d:href>
+    <d:propstat>
+      <d:prop>
+        <cal:calendar-data><![CDATA[BEGIN:VCALENDAR
+VERSION:2.0
+BEGIN:VEVENT
+UID:dentist
+DTSTART:20240620T100000Z
+SUMMARY:Dentist
+END:VEVENT
+END:VCALENDAR
+]]></cal:calendar-data>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+
+    #[tokio::test]
+    async fn reports_a_time_range_with_basic_auth() {
+        let server = mock::serve(|request| match request.method.as_str() {
+            "REPORT" => Reply::new(207, MULTISTATUS).header("Content-Type", "application/xml; charset=utf-8"),
+            _ => Reply::new(405, "")
+        }).await;
+
+        let start = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2025, 7, 6, 0, 0, 0).unwrap();
+        let ics = fetch(&format!("{}/dav/calendars/me/home/", server.url), Some("me"), Some("secret"), start, end).await.unwrap();
+
+        assert_eq!(ics.matches("BEGIN:VCALENDAR").count(), 2);
+        assert!(ics.contains("SUMMARY:Standup & coffee\r\nEND:VEVENT"));
+        assert!(!ics.contains("\r\r"));
+        assert!(ics.contains("SUMMARY:Dentist"));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/dav/calendars/me/home/");
+        assert_eq!(request.header("Depth"), Some("1"));
+        // me:secret
+        assert_eq!(request.header("Authorization"), Some("Basic bWU6c2VjcmV0"));
+        assert!(request.body.contains(r#"<C:time-range start="20240601T000000Z" end="20250706T000000Z" />"#));
+    }
+
+    #[tokio::test]
+    async fn reports_failures() {
+        let server = mock::serve(|_| Reply::new(401, "Unauthorized")).await;
+        let now = Utc::now();
+
+        let result = fetch(&server.url, Some("me"), Some("wrong"), now, now).await;
+        assert!(result.unwrap_err().contains("401"));
+    }
+
+    #[test]
+    fn empty_collections_are_not_an_error() {
+        let empty = r#"<?xml version="1.0"?><multistatus xmlns="DAV:" />"#;
+        assert_eq!(calendar_data(empty), Ok(String::new()));
+        assert!(calendar_data("<html>").is_err());
+    }
+}
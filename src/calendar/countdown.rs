@@ -0,0 +1,178 @@
+use super::CalendarEvent;
+
+use chrono::{Datelike, Local, NaiveDate};
+use regex::Regex;
+use serde::Deserialize;
+
+use log::warn;
+
+/*
+    Named dates to count down to, from sensitive/calendars.json, eg
+    {"urls": [...], "countdown": {
+        "dates": [{"name": "Mum's birthday", "date": "1961-05-27", "yearly": true}, {"name": "Holiday", "date": "2024-12-20"}],
+        "events": "(?i)birthday|anniversary",
+        "count": 5
+    }}
+
+    Yearly dates count down to their next anniversary, `events` picks out the next occurrence of
+    every calendar event whose name it matches.
+*/
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CountdownConfig {
+    #[serde(default)]
+    dates: Vec<TargetDate>,
+    events: Option<String>,
+    #[serde(default = "default_count")]
+    count: usize,
+}
+
+fn default_count() -> usize {5}
+
+impl Default for CountdownConfig {
+    fn default() -> CountdownConfig {
+        CountdownConfig { dates: vec![], events: None, count: default_count() }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct TargetDate {
+    name: String,
+    /* YYYY-MM-DD */
+    date: String,
+    #[serde(default)]
+    yearly: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Countdown {
+    pub name: String,
+    pub date: NaiveDate,
+}
+
+fn next_anniversary(date: NaiveDate, today: NaiveDate) -> NaiveDate {
+    // The 29th of february falls on the 28th in other years
+    let on = |year| NaiveDate::from_ymd_opt(year, date.month(), date.day())
+        .or_else(|| NaiveDate::from_ymd_opt(year, date.month(), date.day() - 1))
+        .unwrap_or(date);
+
+    match on(today.year()) {
+        this_year if this_year >= today => this_year,
+        _ => on(today.year() + 1)
+    }
+}
+
+pub fn upcoming(config: &CountdownConfig, events: &[CalendarEvent], today: NaiveDate) -> Vec<Countdown> {
+    let mut countdowns: Vec<Countdown> = vec![];
+
+    for target in &config.dates {
+        let date = match NaiveDate::parse_from_str(&target.date, "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(e) => {
+                warn!("Could not read countdown date {} for {}: {e}", target.date, target.name);
+                continue
+            }
+        };
+
+        let date = if target.yearly {next_anniversary(date, today)} else {date};
+        if date >= today {
+            countdowns.push(Countdown { name: target.name.clone(), date });
+        }
+    }
+
+    // An invalid pattern only loses the events it would have added, like an invalid date
+    let pattern = config.events.as_ref().and_then(|pattern| match Regex::new(pattern) {
+        Ok(pattern) => Some(pattern),
+        Err(e) => {
+            warn!("Invalid countdown events {pattern}: {e}");
+            None
+        }
+    });
+
+    if let Some(pattern) = pattern {
+        // Expects events sorted by start time, so the first of each name is the next one
+        for event in events.iter().filter(|event| pattern.is_match(&event.name)) {
+            if countdowns.iter().all(|countdown| countdown.name != event.name) {
+                let date = event.start_time.with_timezone(&Local).date_naive().max(today);
+                countdowns.push(Countdown { name: event.name.clone(), date });
+            }
+        }
+    }
+
+    countdowns.sort_by_key(|countdown| countdown.date);
+    countdowns.truncate(config.count);
+    countdowns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn event(name: &str, date: NaiveDate) -> CalendarEvent {
+        let start_time = Local.from_local_datetime(&date.and_hms_opt(12, 0, 0).unwrap()).unwrap().with_timezone(&Utc);
+        CalendarEvent {
+            start_time,
+            end_time: start_time,
+            name: name.to_string(),
+            location: None,
+            all_day: false,
+            calendar: None,
+            marker: None,
+            alarm: None
+        }
+    }
+
+    fn names(countdowns: &[Countdown]) -> Vec<(&str, NaiveDate)> {
+        countdowns.iter().map(|countdown| (countdown.name.as_str(), countdown.date)).collect()
+    }
+
+    #[test]
+    fn yearly_dates_count_to_their_next_anniversary() {
+        assert_eq!(next_anniversary(date(1961, 5, 27), date(2024, 5, 27)), date(2024, 5, 27));
+        assert_eq!(next_anniversary(date(1961, 5, 27), date(2024, 5, 28)), date(2025, 5, 27));
+        assert_eq!(next_anniversary(date(2000, 2, 29), date(2025, 1, 1)), date(2025, 2, 28));
+        assert_eq!(next_anniversary(date(2000, 2, 29), date(2027, 12, 1)), date(2028, 2, 29));
+    }
+
+    #[test]
+    fn sorts_dates_and_matching_events_by_proximity() {
+        let config: CountdownConfig = serde_json::from_str(r#"{
+            "dates": [
+                {"name": "Mum's birthday", "date": "1961-05-27", "yearly": true},
+                {"name": "Holiday", "date": "2024-12-20"},
+                {"name": "Last year", "date": "2023-12-20"},
+                {"name": "Typo", "date": "2024-13-01"}
+            ],
+            "events": "(?i)birthday|anniversary",
+            "count": 3
+        }"#).unwrap();
+        let events = [
+            event("Standup", date(2024, 6, 4)),
+            event("Sam's birthday", date(2024, 7, 1)),
+            event("Wedding anniversary", date(2024, 9, 9)),
+            event("Sam's birthday", date(2025, 7, 1)),
+        ];
+
+        let countdowns = upcoming(&config, &events, date(2024, 6, 3));
+        assert_eq!(names(&countdowns), [
+            ("Sam's birthday", date(2024, 7, 1)),
+            ("Wedding anniversary", date(2024, 9, 9)),
+            ("Holiday", date(2024, 12, 20)),
+        ]);
+
+        let config = CountdownConfig { count: 10, ..config };
+        assert_eq!(upcoming(&config, &events, date(2024, 6, 3)).last().map(|c| c.name.as_str()), Some("Mum's birthday"));
+    }
+
+    #[test]
+    fn skips_invalid_patterns() {
+        let config: CountdownConfig = serde_json::from_str(r#"{"dates": [{"name": "Holiday", "date": "2024-12-20"}], "events": "("}"#).unwrap();
+        let events = [event("Sam's birthday", date(2024, 7, 1))];
+        assert_eq!(names(&upcoming(&config, &events, date(2024, 6, 3))), [("Holiday", date(2024, 12, 20))]);
+    }
+}
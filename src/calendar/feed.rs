@@ -0,0 +1,338 @@
+use super::caldav;
+use super::filter::CalendarConfig;
+use super::WINDOW_DAYS;
+
+use chrono::{Duration, Utc};
+
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, USER_AGENT};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use std::path::{Path, PathBuf};
+use std::time::Duration as stdDuration;
+
+use async_std::future;
+
+use log::{info, warn};
+
+/*
+    Fetching each calendar on its own, so one broken url only loses that calendar.
+
+    The last good copy of each feed is kept on disk along with its ETag/Last-Modified, so an
+    unchanged feed is a 304 rather than a full download, and a feed that fails to fetch is
+    shown from that copy (while still being reported as failed).
+
+    Local files and CalDAV collections are cached the same way, just without the revalidation.
+*/
+
+// Well inside the time the whole calendar gets, so a feed that hangs only loses itself
+const FETCH_TIMEOUT: stdDuration = stdDuration::from_secs(20);
+
+const USER_AGENT_STRING: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/79.0.3945.79 Safari/537.36";
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl AsRef<Path>) -> Cache {
+        Cache { dir: dir.as_ref().to_path_buf() }
+    }
+
+    fn path(&self, url: &str, extension: &str) -> PathBuf {
+        // Calendar urls tend to hold secrets, so they are not used as file names
+        self.dir.join(format!("{:016x}.{extension}", fnv1a(url)))
+    }
+
+    fn load(&self, url: &str) -> Option<(Validators, String)> {
+        let body = std::fs::read_to_string(self.path(url, "ics")).ok()?;
+        let validators = std::fs::read(self.path(url, "json")).ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default();
+        Some((validators, body))
+    }
+
+    fn store(&self, url: &str, validators: &Validators, body: &str) {
+        let result = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(self.path(url, "ics"), body))
+            .and_then(|_| std::fs::write(self.path(url, "json"), serde_json::to_vec(validators).unwrap_or_default()));
+
+        if let Err(e) = result {
+            warn!("Could not cache calendar in {}: {e}", self.dir.display());
+        }
+    }
+}
+
+fn fnv1a(s: &str) -> u64 {
+    // Unlike std's hasher this is the same in every build, so the cache outlives a toolchain upgrade
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[derive(Debug, Default)]
+pub struct Feed {
+    /* Fresh, or the last good copy if the fetch failed */
+    pub body: Option<String>,
+    pub error: Option<String>,
+}
+
+fn looks_like_ics(body: &str) -> bool {
+    body.trim_start_matches('\u{feff}').trim_start().starts_with("BEGIN:VCALENDAR")
+}
+
+async fn fetch_http(calendar: &CalendarConfig, cached: Option<&Validators>) -> Result<Option<(Validators, String)>, String> {
+    // None when the server says our copy is still current
+    let mut request = Client::new().get(&calendar.url).header(USER_AGENT, USER_AGENT_STRING);
+
+    if let Some(username) = &calendar.username {
+        request = request.basic_auth(username, calendar.password.as_ref());
+    }
+
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+
+    if status == StatusCode::NOT_MODIFIED {
+        return Ok(None)
+    }
+    if !status.is_success() {
+        return Err(format!("Request failed with status: {status}"))
+    }
+
+    let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+    let validators = Validators { etag: header(ETAG), last_modified: header(LAST_MODIFIED) };
+
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    if !looks_like_ics(&body) {
+        return Err("Response is not an ics calendar".to_string())
+    }
+
+    Ok(Some((validators, body)))
+}
+
+async fn read_file(url: &str) -> Result<String, String> {
+    let path = reqwest::Url::parse(url).ok()
+        .and_then(|url| url.to_file_path().ok())
+        .ok_or_else(|| format!("Invalid file url {url}"))?;
+
+    let body = tokio::fs::read_to_string(&path).await.map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    if !looks_like_ics(&body) {
+        return Err(format!("{} is not an ics calendar", path.display()))
+    }
+    Ok(body)
+}
+
+async fn fetch_caldav(calendar: &CalendarConfig) -> Result<String, String> {
+    // The same window parse_ics expands recurring events over
+    let now = Utc::now();
+    let start = now - Duration::days(1);
+    let end = now + Duration::days(WINDOW_DAYS);
+
+    caldav::fetch(&calendar.url, calendar.username.as_deref(), calendar.password.as_deref(), start, end).await
+}
+
+pub async fn fetch(calendar: &CalendarConfig, cache: &Cache) -> Feed {
+    fetch_within(calendar, cache, FETCH_TIMEOUT).await
+}
+
+async fn fetch_within(calendar: &CalendarConfig, cache: &Cache, timeout: stdDuration) -> Feed {
+    let cached = cache.load(&calendar.url);
+
+    let fetching = async {
+        if calendar.url.starts_with("file://") {
+            read_file(&calendar.url).await.map(|body| Some((Validators::default(), body)))
+        } else if calendar.caldav {
+            fetch_caldav(calendar).await.map(|body| Some((Validators::default(), body)))
+        } else {
+            fetch_http(calendar, cached.as_ref().map(|(validators, _)| validators)).await
+        }
+    };
+
+    let result = match future::timeout(timeout, fetching).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("Timed out after {timeout:.0?}"))
+    };
+
+    match result {
+        Ok(Some((validators, body))) => {
+            cache.store(&calendar.url, &validators, &body);
+            Feed { body: Some(body), error: None }
+        },
+        Ok(None) => {
+            info!("Calendar {} is unchanged", calendar.label());
+            Feed { body: cached.map(|(_, body)| body), error: None }
+        },
+        Err(e) => Feed { body: cached.map(|(_, body)| body), error: Some(e) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, Reply};
+
+    const ICS: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nEND:VCALENDAR\r\n";
+
+    fn cache(name: &str) -> Cache {
+        let dir = std::env::temp_dir().join(format!("kindle-feed-{name}-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        Cache::new(dir)
+    }
+
+    fn calendar(url: String) -> CalendarConfig {
+        CalendarConfig::from_url(&url)
+    }
+
+    #[tokio::test]
+    async fn revalidates_with_etag_and_last_modified() {
+        let server = mock::serve(|request| match request.header("If-None-Match") {
+            Some("\"v1\"") => Reply::new(304, ""),
+            _ => Reply::ok(ICS).header("ETag", "\"v1\"").header("Last-Modified", "Mon, 27 May 2024 09:30:00 GMT")
+        }).await;
+        let cache = cache("etag");
+        let calendar = calendar(format!("{}/cal.ics", server.url));
+
+        let first = fetch(&calendar, &cache).await;
+        assert_eq!(first.body.as_deref(), Some(ICS));
+
+        let second = fetch(&calendar, &cache).await;
+        assert_eq!(second.body.as_deref(), Some(ICS));
+        assert!(second.error.is_none());
+
+        let requests = server.requests();
+        assert!(requests.iter().all(|r| r.method == "GET" && r.body.is_empty()));
+        assert_eq!(requests[0].header("If-None-Match"), None);
+        assert_eq!(requests[1].header("If-None-Match"), Some("\"v1\""));
+        assert_eq!(requests[1].header("If-Modified-Since"), Some("Mon, 27 May 2024 09:30:00 GMT"));
+    }
+
+    #[tokio::test]
+    async fn rejects_error_statuses_and_html() {
+        let server = mock::serve(|request| match request.path.as_str() {
+            "/missing.ics" => Reply::new(404, "<html>Not found</html>"),
+            _ => Reply::ok("<!DOCTYPE html><html>Sign in</html>")
+        }).await;
+        let cache = cache("errors");
+
+        let missing = fetch(&calendar(format!("{}/missing.ics", server.url)), &cache).await;
+        assert!(missing.body.is_none());
+        assert!(missing.error.unwrap().contains("404"));
+
+        let html = fetch(&calendar(format!("{}/login.ics", server.url)), &cache).await;
+        assert!(html.body.is_none());
+        assert_eq!(html.error.as_deref(), Some("Response is not an ics calendar"));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_last_good_copy() {
+        let server = mock::serve(|request| match request.header("If-None-Match") {
+            Some(_) => Reply::new(503, "Service Unavailable"),
+            None => Reply::ok(ICS).header("ETag", "\"v1\"")
+        }).await;
+        let cache = cache("fallback");
+        let calendar = calendar(format!("{}/cal.ics", server.url));
+
+        fetch(&calendar, &cache).await;
+        let failed = fetch(&calendar, &cache).await;
+
+        assert_eq!(failed.body.as_deref(), Some(ICS));
+        assert!(failed.error.unwrap().contains("503"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_feed_that_hangs() {
+        let good = mock::serve(|_| Reply::ok(ICS).header("ETag", "\"v1\"")).await;
+        let cache = cache("timeout");
+        let calendar = calendar(format!("{}/cal.ics", good.url));
+        fetch(&calendar, &cache).await;
+
+        // Accepts the connection and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hung = CalendarConfig { url: format!("http://{}/cal.ics", listener.local_addr().unwrap()), ..Default::default() };
+        tokio::spawn(async move {
+            let _connection = listener.accept().await;
+            std::future::pending::<()>().await
+        });
+
+        let feed = fetch_within(&hung, &cache, stdDuration::from_millis(200)).await;
+        assert!(feed.body.is_none());
+        assert_eq!(feed.error.as_deref(), Some("Timed out after 200ms"));
+
+        // The cached copy of a feed that hangs is still shown
+        let cached = CalendarConfig { url: calendar.url.clone(), ..hung };
+        let feed = fetch_within(&cached, &cache, stdDuration::ZERO).await;
+        assert_eq!(feed.body.as_deref(), Some(ICS));
+        assert!(feed.error.is_some());
+    }
+
+    #[test]
+    fn names_cache_files_by_a_stable_hash() {
+        assert_eq!(fnv1a(""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(Cache::new("cache").path("a", "ics"), Path::new("cache/af63dc4c8601ec8c.ics"));
+    }
+
+    #[tokio::test]
+    async fn sends_basic_auth() {
+        let server = mock::serve(|request| match request.header("Authorization") {
+            Some(_) => Reply::ok(ICS),
+            None => Reply::new(401, "Unauthorized")
+        }).await;
+        let cache = cache("auth");
+        let calendar = CalendarConfig {
+            username: Some("me".to_string()),
+            password: Some("secret".to_string()),
+            ..calendar(format!("{}/cal.ics", server.url))
+        };
+
+        let feed = fetch(&calendar, &cache).await;
+        assert_eq!(feed.body.as_deref(), Some(ICS));
+        assert_eq!(server.requests()[0].header("Authorization"), Some("Basic bWU6c2VjcmV0"));
+    }
+
+    #[tokio::test]
+    async fn reads_local_files() {
+        let cache = cache("file");
+        let path = std::fs::canonicalize("src/fixtures/calendar/apple.ics").unwrap();
+        let url = reqwest::Url::from_file_path(&path).unwrap().to_string();
+
+        let feed = fetch(&calendar(url.clone()), &cache).await;
+        assert!(feed.error.is_none());
+        assert_eq!(feed.body, std::fs::read_to_string(&path).ok());
+        assert_eq!(calendar(url).label(), "apple.ics");
+
+        let missing = fetch(&calendar("file:///nowhere/missing.ics".to_string()), &cache).await;
+        assert!(missing.body.is_none());
+        assert!(missing.error.unwrap().contains("/nowhere/missing.ics"));
+    }
+
+    #[tokio::test]
+    async fn reads_caldav_collections() {
+        let server = mock::serve(|request| match request.method.as_str() {
+            "REPORT" => Reply::new(207, format!(r#"<multistatus xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+                <response><propstat><prop><C:calendar-data>{ICS}</C:calendar-data></prop></propstat></response>
+            </multistatus>"#)),
+            _ => Reply::new(405, "")
+        }).await;
+        let cache = cache("caldav");
+        let calendar = CalendarConfig { caldav: true, ..calendar(format!("{}/dav/home/", server.url)) };
+
+        let feed = fetch(&calendar, &cache).await;
+        assert!(feed.error.is_none());
+        assert_eq!(feed.body.as_deref(), Some(ICS.trim()));
+        assert_eq!(server.requests()[0].header("Authorization"), None);
+    }
+}
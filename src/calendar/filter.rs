@@ -0,0 +1,113 @@
+use super::VEvent;
+
+use regex::Regex;
+use serde::Deserialize;
+
+/*
+    Per calendar settings from sensitive/calendars.json, eg
+    {"url": "...", "caldav": false, "username": "...", "password": "...", "name": "Work", "marker": "hatch", "exclude": "(?i)lunch|focus time",
+     "hide_declined": true, "hide_transparent": true, "email": "me@example.com"}
+
+    The url is an ics feed (http(s)://), a local ics file (file://) or, with caldav, a CalDAV
+    calendar collection. username/password are sent as basic auth to either kind of server.
+
+    include/exclude are matched against the SUMMARY and each of the CATEGORIES. Declined means the
+    attendee with `email` (or, for google, the calendar's own address) declined it.
+*/
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct CalendarConfig {
+    pub url: String,
+    pub name: Option<String>,
+    /* "solid", "outline", "hatch", "dots", "crosshatch", "circle", "triangle", or a character to draw (eg "♥") */
+    pub marker: Option<String>,
+    pub include: Option<String>,
+    pub exclude: Option<String>,
+    #[serde(default)]
+    pub hide_declined: bool,
+    #[serde(default)]
+    pub hide_transparent: bool,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub caldav: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl CalendarConfig {
+    pub fn from_url(url: &str) -> CalendarConfig {
+        CalendarConfig { url: url.to_string(), ..Default::default() }
+    }
+
+    pub fn label(&self) -> String {
+        // For logs and the screen, the rest of a url is often a secret
+        let url = reqwest::Url::parse(&self.url).ok();
+        self.name.clone()
+            .or_else(|| url.as_ref()?.host_str().map(|host| host.to_string()))
+            .or_else(|| url.as_ref()?.path_segments()?.next_back().filter(|file| !file.is_empty()).map(|file| file.to_string()))
+            .unwrap_or_else(|| "calendar".to_string())
+    }
+}
+
+pub fn labels(calendars: &[CalendarConfig]) -> Vec<String> {
+    // Unnamed feeds from the same host would share a label (and a /status entry), so repeats are numbered
+    let labels: Vec<String> = calendars.iter().map(CalendarConfig::label).collect();
+
+    labels.iter().enumerate().map(|(i, label)| {
+        let before = labels[..i].iter().filter(|other| *other == label).count();
+        if before == 0 && !labels[i + 1..].contains(label) {label.clone()} else {format!("{label} {}", before + 1)}
+    }).collect()
+}
+
+pub struct Filter<'a> {
+    pub config: &'a CalendarConfig,
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+}
+
+fn compile(pattern: &Option<String>) -> Result<Option<Regex>, String> {
+    pattern.as_ref()
+        .map(|pattern| Regex::new(pattern).map_err(|e| format!("Invalid filter {pattern}: {e}")))
+        .transpose()
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(config: &'a CalendarConfig) -> Result<Filter<'a>, String> {
+        Ok(Filter { config, include: compile(&config.include)?, exclude: compile(&config.exclude)? })
+    }
+
+    pub fn keep(&self, event: &VEvent, name: &str) -> bool {
+        let matches = |regex: &Regex| regex.is_match(name) || event.categories.iter().any(|c| regex.is_match(c));
+
+        if self.include.as_ref().is_some_and(|regex| !matches(regex)) {
+            return false
+        }
+        if self.exclude.as_ref().is_some_and(matches) {
+            return false
+        }
+        if self.config.hide_transparent && event.transparent {
+            return false
+        }
+        if self.config.hide_declined && event.declined {
+            return false
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_repeated_labels() {
+        let calendars = [
+            CalendarConfig::from_url("https://calendar.google.com/calendar/ical/a%40gmail.com/private-1/basic.ics"),
+            CalendarConfig { name: Some("Work".to_string()), ..CalendarConfig::from_url("https://outlook.office365.com/owa/calendar/1/reachcalendar.ics") },
+            CalendarConfig::from_url("https://calendar.google.com/calendar/ical/b%40gmail.com/private-2/basic.ics"),
+            CalendarConfig::from_url("https://p01-caldav.icloud.com/published/2/abc"),
+        ];
+        assert_eq!(labels(&calendars), ["calendar.google.com 1", "Work", "calendar.google.com 2", "p01-caldav.icloud.com"]);
+    }
+}
@@ -0,0 +1,447 @@
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime, Weekday};
+
+/*
+    RFC 5545 recurrence rules, enough of them for what google/outlook/apple actually export:
+    FREQ (daily, weekly, monthly, yearly), INTERVAL, COUNT, UNTIL, BYDAY, BYMONTHDAY, BYMONTH,
+    BYSETPOS and WKST.
+
+    Expansion happens in the event's own wall clock time (whatever DTSTART is written in),
+    it is up to the caller to turn the occurrences into real instants.
+*/
+
+// Stops runaway rules (eg a yearly rule on the 30th of february) from looping forever
+const MAX_PERIODS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Until {
+    Local(NaiveDateTime),
+    Utc(NaiveDateTime),
+}
+
+#[derive(Debug, Clone)]
+pub struct RRule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<Until>,
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+    pub by_set_pos: Vec<i32>,
+    pub week_start: Weekday,
+}
+
+fn parse_weekday(s: &str) -> Result<Weekday, String> {
+    match s {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(format!("Invalid weekday {s}"))
+    }
+}
+
+fn parse_by_day(s: &str) -> Result<(Option<i32>, Weekday), String> {
+    // eg MO, 2TU, -1FR
+    let split = s.len().checked_sub(2).ok_or(format!("Invalid BYDAY {s}"))?;
+    let (ordinal, weekday) = s.split_at(split);
+    let weekday = parse_weekday(weekday)?;
+
+    let ordinal = match ordinal.trim_start_matches('+') {
+        "" => None,
+        n => Some(n.parse().map_err(|_| format!("Invalid BYDAY {s}"))?)
+    };
+
+    Ok((ordinal, weekday))
+}
+
+fn parse_list<T>(s: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Vec<T>, String> {
+    s.split(',').map(|v| parse(v.trim())).collect()
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("Invalid number {s}"))
+}
+
+pub fn parse_until(s: &str) -> Result<Until, String> {
+    let (value, utc) = match s.strip_suffix('Z') {
+        Some(value) => (value, true),
+        None => (s, false)
+    };
+
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y%m%d").map(|d| d.and_hms_opt(23, 59, 59).unwrap()))
+        .map_err(|_| format!("Invalid UNTIL {s}"))?;
+
+    Ok(if utc {Until::Utc(time)} else {Until::Local(time)})
+}
+
+impl RRule {
+    pub fn parse(s: &str) -> Result<RRule, String> {
+        let mut frequency = None;
+        let mut rule = RRule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: vec![],
+            by_month_day: vec![],
+            by_month: vec![],
+            by_set_pos: vec![],
+            week_start: Weekday::Mon,
+        };
+
+        for part in s.trim().trim_start_matches("RRULE:").split(';') {
+            let (key, value) = match part.split_once('=') {
+                Some(pair) => pair,
+                None => continue
+            };
+
+            match key.to_uppercase().as_str() {
+                "FREQ" => frequency = Some(match value {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    _ => return Err(format!("Unsupported FREQ {value}"))
+                }),
+                "INTERVAL" => rule.interval = parse_number::<u32>(value)?.max(1),
+                "COUNT" => rule.count = Some(parse_number(value)?),
+                "UNTIL" => rule.until = Some(parse_until(value)?),
+                "BYDAY" => rule.by_day = parse_list(value, parse_by_day)?,
+                "BYMONTHDAY" => rule.by_month_day = parse_list(value, parse_number)?,
+                "BYMONTH" => rule.by_month = parse_list(value, parse_number)?,
+                "BYSETPOS" => rule.by_set_pos = parse_list(value, parse_number)?,
+                "WKST" => rule.week_start = parse_weekday(value)?,
+                // BYHOUR, BYWEEKNO, ... are rare enough in calendar exports to ignore
+                _ => {}
+            }
+        }
+
+        rule.frequency = frequency.ok_or("RRULE without FREQ")?;
+        Ok(rule)
+    }
+
+    fn matches_filters(&self, date: NaiveDate) -> bool {
+        let month_ok = self.by_month.is_empty() || self.by_month.contains(&date.month());
+        let month_day_ok = self.by_month_day.is_empty() || self.by_month_day.iter().any(|&d| month_day(date, d) == Some(date));
+        let day_ok = self.by_day.is_empty() || self.by_day.iter().any(|&(_, weekday)| date.weekday() == weekday);
+        month_ok && month_day_ok && day_ok
+    }
+
+    fn month_candidates(&self, first: NaiveDate, start: NaiveDate) -> Vec<NaiveDate> {
+        // All the dates in the month starting at `first` that the rule could land on
+        let days = days_in_month(first);
+        let all = (0..days).map(|i| first + Duration::days(i as i64));
+
+        if !self.by_month_day.is_empty() {
+            let mut dates: Vec<_> = self.by_month_day.iter().filter_map(|&d| month_day(first, d)).collect();
+            if !self.by_day.is_empty() {
+                dates.retain(|d| self.by_day.iter().any(|&(_, weekday)| d.weekday() == weekday));
+            }
+            dates
+        } else if !self.by_day.is_empty() {
+            let mut dates = vec![];
+            for &(ordinal, weekday) in &self.by_day {
+                let matching: Vec<_> = all.clone().filter(|d| d.weekday() == weekday).collect();
+                match ordinal {
+                    None => dates.extend(matching),
+                    Some(n) => if let Some(d) = nth(&matching, n) {dates.push(d)}
+                }
+            }
+            dates
+        } else {
+            month_day(first, start.day() as i32).into_iter().collect()
+        }
+    }
+
+    fn period_candidates(&self, period_start: NaiveDate, start: NaiveDate) -> Vec<NaiveDate> {
+        let mut dates = match self.frequency {
+            Frequency::Daily => {
+                if self.matches_filters(period_start) {vec![period_start]} else {vec![]}
+            },
+            Frequency::Weekly => {
+                let week: Vec<_> = (0..7)
+                    .map(|i| period_start + Duration::days(i))
+                    .filter(|d| self.by_month.is_empty() || self.by_month.contains(&d.month()))
+                    .collect();
+                if self.by_day.is_empty() {
+                    week.into_iter().filter(|d| d.weekday() == start.weekday()).collect()
+                } else {
+                    week.into_iter().filter(|d| self.by_day.iter().any(|&(_, weekday)| d.weekday() == weekday)).collect()
+                }
+            },
+            Frequency::Monthly => {
+                if self.by_month.is_empty() || self.by_month.contains(&period_start.month()) {
+                    self.month_candidates(period_start, start)
+                } else {
+                    vec![]
+                }
+            },
+            Frequency::Yearly => {
+                // BYMONTHDAY and BYDAY go over the whole year unless BYMONTH narrows it
+                let months = if !self.by_month.is_empty() {
+                    self.by_month.clone()
+                } else if self.by_month_day.is_empty() && self.by_day.is_empty() {
+                    vec![start.month()]
+                } else {
+                    (1..=12).collect()
+                };
+
+                if self.by_month.is_empty() && self.by_month_day.is_empty() && self.by_day.iter().any(|(n, _)| n.is_some()) {
+                    // eg FREQ=YEARLY;BYDAY=20MO, the nth weekday of the whole year
+                    let year: Vec<_> = period_start.iter_days().take_while(|d| d.year() == period_start.year()).collect();
+                    let mut dates = vec![];
+                    for &(ordinal, weekday) in &self.by_day {
+                        let matching: Vec<_> = year.iter().copied().filter(|d| d.weekday() == weekday).collect();
+                        match ordinal {
+                            None => dates.extend(matching),
+                            Some(n) => if let Some(d) = nth(&matching, n) {dates.push(d)}
+                        }
+                    }
+                    dates
+                } else {
+                    months.iter()
+                        .filter_map(|&m| NaiveDate::from_ymd_opt(period_start.year(), m, 1))
+                        .flat_map(|first| self.month_candidates(first, start))
+                        .collect()
+                }
+            }
+        };
+
+        dates.sort();
+        dates.dedup();
+
+        if !self.by_set_pos.is_empty() {
+            dates = self.by_set_pos.iter().filter_map(|&n| nth(&dates, n)).collect();
+            dates.sort();
+            dates.dedup();
+        }
+
+        dates
+    }
+
+    fn first_period(&self, start: NaiveDate) -> NaiveDate {
+        match self.frequency {
+            Frequency::Daily => start,
+            Frequency::Weekly => {
+                let offset = (7 + start.weekday().num_days_from_monday() - self.week_start.num_days_from_monday()) % 7;
+                start - Duration::days(offset as i64)
+            },
+            Frequency::Monthly => start.with_day(1).unwrap(),
+            Frequency::Yearly => NaiveDate::from_ymd_opt(start.year(), 1, 1).unwrap(),
+        }
+    }
+
+    fn next_period(&self, first: NaiveDate, n: u32) -> Option<NaiveDate> {
+        let steps = n.checked_mul(self.interval)?;
+        match self.frequency {
+            Frequency::Daily => first.checked_add_signed(Duration::days(steps as i64)),
+            Frequency::Weekly => first.checked_add_signed(Duration::weeks(steps as i64)),
+            Frequency::Monthly => first.checked_add_months(Months::new(steps)),
+            Frequency::Yearly => first.checked_add_months(Months::new(steps.checked_mul(12)?)),
+        }
+    }
+
+    pub fn expand(&self, start: NaiveDateTime, until: Option<NaiveDateTime>, window_end: NaiveDateTime) -> Vec<NaiveDateTime> {
+        /*
+            Occurrences from DTSTART (which always counts as the first one) up to window_end.
+            `until` is the rule's UNTIL, already converted into the same wall clock as DTSTART.
+        */
+        let mut result = vec![start];
+        let first = self.first_period(start.date());
+        let time = start.time();
+
+        for n in 0..MAX_PERIODS {
+            let period = match self.next_period(first, n) {
+                Some(period) => period,
+                None => break
+            };
+
+            for date in self.period_candidates(period, start.date()) {
+                let occurrence = date.and_time(time);
+
+                if occurrence <= start {
+                    continue
+                }
+                if until.is_some_and(|until| occurrence > until) || occurrence > window_end {
+                    return result
+                }
+                if self.count.is_some_and(|count| result.len() as u32 >= count) {
+                    return result
+                }
+
+                result.push(occurrence);
+            }
+        }
+
+        result
+    }
+}
+
+fn days_in_month(first: NaiveDate) -> u32 {
+    let next = first.checked_add_months(Months::new(1)).unwrap_or(first);
+    (next - first).num_days() as u32
+}
+
+fn month_day(date: NaiveDate, day: i32) -> Option<NaiveDate> {
+    // Negative days count back from the end of the month, -1 being the last day
+    let first = date.with_day(1)?;
+    let days = days_in_month(first) as i32;
+    let day = if day < 0 {days + day + 1} else {day};
+
+    if day < 1 || day > days {
+        return None
+    }
+    first.with_day(day as u32)
+}
+
+fn nth<T: Copy>(items: &[T], n: i32) -> Option<T> {
+    // 1 is the first, -1 the last
+    if n > 0 {
+        items.get(n as usize - 1).copied()
+    } else if n < 0 {
+        items.len().checked_sub(n.unsigned_abs() as usize).and_then(|i| items.get(i).copied())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%S").unwrap()
+    }
+
+    fn expand(rule: &str, start: &str, window_end: &str) -> Vec<String> {
+        let rule = RRule::parse(rule).unwrap();
+        let until = match &rule.until {
+            Some(Until::Local(until)) | Some(Until::Utc(until)) => Some(*until),
+            None => None
+        };
+        rule.expand(at(start), until, at(window_end))
+            .iter()
+            .map(|d| d.format("%Y%m%dT%H%M%S").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn weekly_by_day_with_interval() {
+        assert_eq!(
+            expand("FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH", "20240102T093000", "20240131T000000"),
+            ["20240102T093000", "20240104T093000", "20240116T093000", "20240118T093000", "20240130T093000"]
+        );
+    }
+
+    #[test]
+    fn count_includes_dtstart() {
+        assert_eq!(
+            expand("FREQ=DAILY;COUNT=3", "20240501T080000", "20250101T000000"),
+            ["20240501T080000", "20240502T080000", "20240503T080000"]
+        );
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        assert_eq!(
+            expand("FREQ=DAILY;UNTIL=20240503T080000", "20240501T080000", "20250101T000000"),
+            ["20240501T080000", "20240502T080000", "20240503T080000"]
+        );
+    }
+
+    #[test]
+    fn monthly_last_friday_and_negative_month_day() {
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYDAY=-1FR;COUNT=3", "20240126T170000", "20250101T000000"),
+            ["20240126T170000", "20240223T170000", "20240329T170000"]
+        );
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYMONTHDAY=-1;COUNT=3", "20240131T000000", "20250101T000000"),
+            ["20240131T000000", "20240229T000000", "20240331T000000"]
+        );
+    }
+
+    #[test]
+    fn monthly_skips_months_without_the_day() {
+        assert_eq!(
+            expand("FREQ=MONTHLY;COUNT=3", "20240131T120000", "20250101T000000"),
+            ["20240131T120000", "20240331T120000", "20240531T120000"]
+        );
+    }
+
+    #[test]
+    fn yearly_birthday_and_leap_day() {
+        assert_eq!(
+            expand("FREQ=YEARLY", "19900527T000000", "19930101T000000"),
+            ["19900527T000000", "19910527T000000", "19920527T000000"]
+        );
+        assert_eq!(
+            expand("FREQ=YEARLY", "20200229T000000", "20290101T000000"),
+            ["20200229T000000", "20240229T000000", "20280229T000000"]
+        );
+    }
+
+    #[test]
+    fn yearly_by_month_and_nth_weekday() {
+        // Mother's day (in australia), second sunday of may
+        assert_eq!(
+            expand("FREQ=YEARLY;BYMONTH=5;BYDAY=2SU", "20230514T000000", "20260101T000000"),
+            ["20230514T000000", "20240512T000000", "20250511T000000"]
+        );
+    }
+
+    #[test]
+    fn yearly_by_day_or_month_day_without_month() {
+        // Without BYMONTH these are over the whole year, not just DTSTART's month
+        assert_eq!(
+            expand("FREQ=YEARLY;BYDAY=1MO", "20240101T090000", "20270101T000000"),
+            ["20240101T090000", "20250106T090000", "20260105T090000"]
+        );
+        assert_eq!(
+            expand("FREQ=YEARLY;BYMONTHDAY=1;COUNT=3", "20240101T090000", "20250101T000000"),
+            ["20240101T090000", "20240201T090000", "20240301T090000"]
+        );
+        assert_eq!(
+            expand("FREQ=YEARLY;BYDAY=FR;BYMONTHDAY=13;COUNT=3", "20240913T000000", "20260101T000000"),
+            ["20240913T000000", "20241213T000000", "20250613T000000"]
+        );
+    }
+
+    #[test]
+    fn weekly_by_month() {
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=SA;BYMONTH=1", "20240106T100000", "20250201T000000"),
+            [
+                "20240106T100000", "20240113T100000", "20240120T100000", "20240127T100000",
+                "20250104T100000", "20250111T100000", "20250118T100000", "20250125T100000"
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly_last_weekday_with_set_pos() {
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1;COUNT=3", "20240531T090000", "20250101T000000"),
+            ["20240531T090000", "20240628T090000", "20240731T090000"]
+        );
+    }
+
+    #[test]
+    fn rejects_rules_without_frequency() {
+        assert!(RRule::parse("INTERVAL=2;BYDAY=MO").is_err());
+        assert!(RRule::parse("FREQ=SECONDLY").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use super::timezone::Zones;
+use super::{param, parse_duration, read_ical_time, CalendarEvent};
+
+use chrono::{DateTime, Duration, Utc};
+use ical::parser::ical::component::IcalAlarm;
+use regex::Regex;
+use serde::Deserialize;
+
+use log::warn;
+
+/*
+    When to put an event up as a reminder banner: from the event's own VALARMs, or from rules in
+    sensitive/calendars.json for calendars that do not carry alarms, eg
+    {"urls": [...], "reminders": [{"match": "(?i)dentist|interview", "minutes": 60}]}
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /* Relative to the start (or end) of each occurrence, usually negative */
+    Start(Duration),
+    End(Duration),
+    /* A fixed time, only meaningful for events that happen once */
+    At(DateTime<Utc>),
+}
+
+pub fn read_alarm(alarm: &IcalAlarm, zones: &Zones) -> Option<Trigger> {
+    // Email alarms go somewhere else, not on the screen
+    let action = alarm.properties.iter().find(|prop| prop.name == "ACTION").and_then(|prop| prop.value.as_deref());
+    if action.is_some_and(|action| action.eq_ignore_ascii_case("EMAIL")) {
+        return None
+    }
+
+    let trigger = alarm.properties.iter().find(|prop| prop.name == "TRIGGER")?;
+    let value = trigger.value.as_deref()?;
+
+    if param(trigger, "VALUE").is_some_and(|v| v.eq_ignore_ascii_case("DATE-TIME")) {
+        return read_ical_time(trigger, zones)?.to_utc().map(Trigger::At)
+    }
+
+    let offset = parse_duration(value)?;
+    match param(trigger, "RELATED") {
+        Some(related) if related.eq_ignore_ascii_case("END") => Some(Trigger::End(offset)),
+        _ => Some(Trigger::Start(offset))
+    }
+}
+
+pub fn alarm_time(triggers: &[Trigger], start: DateTime<Utc>, end: DateTime<Utc>, recurring: bool) -> Option<DateTime<Utc>> {
+    // The earliest of an occurrence's alarms, ignoring any that would go off after it starts
+    triggers.iter()
+        .filter_map(|trigger| match trigger {
+            Trigger::Start(offset) => Some(start + *offset),
+            Trigger::End(offset) => Some(end + *offset),
+            Trigger::At(at) if !recurring => Some(*at),
+            Trigger::At(_) => None
+        })
+        .filter(|alarm| *alarm <= start)
+        .min()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReminderRule {
+    #[serde(rename = "match")]
+    pattern: String,
+    minutes: i64,
+}
+
+pub fn apply(rules: &[ReminderRule], events: &mut [CalendarEvent]) {
+    // An invalid rule is left out, the calendar is still shown without its reminders
+    for rule in rules {
+        let pattern = match Regex::new(&rule.pattern) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                warn!("Invalid reminder {}: {e}", rule.pattern);
+                continue
+            }
+        };
+
+        for event in events.iter_mut().filter(|event| pattern.is_match(&event.name)) {
+            let alarm = event.start_time - Duration::minutes(rule.minutes);
+            event.alarm = Some(event.alarm.map_or(alarm, |existing| existing.min(alarm)));
+        }
+    }
+}
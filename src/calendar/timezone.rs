@@ -0,0 +1,237 @@
+use super::recurrence::{RRule, Until};
+
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use ical::parser::ical::component::IcalTimeZone;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::warn;
+
+/*
+    Which wall clock a DTSTART (etc) is written in.
+
+    TZID=Area/City is looked up in the IANA database. Anything else (outlook writes windows
+    names like "AUS Eastern Standard Time") falls back to the VTIMEZONE block the calendar
+    embeds for it, and failing that, the device's own time zone.
+*/
+
+#[derive(Debug, Clone)]
+pub enum Zone {
+    Utc,
+    Local,
+    Iana(Tz),
+    Embedded(Arc<VTimezone>),
+}
+
+#[derive(Debug)]
+struct Observance {
+    // A STANDARD or DAYLIGHT block, onsets are written in the offset being left
+    start: NaiveDateTime,
+    rule: Option<RRule>,
+    rdates: Vec<NaiveDateTime>,
+    offset_from: i32,
+    offset_to: i32,
+}
+
+#[derive(Debug)]
+pub struct VTimezone {
+    observances: Vec<Observance>,
+}
+
+fn parse_offset(s: &str) -> Option<i32> {
+    // eg +1000, -0430 or +103000, in seconds east of utc
+    let (sign, digits) = match s.trim().split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None
+    };
+
+    if !(digits.len() == 4 || digits.len() == 6) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None
+    }
+
+    let hours: i32 = digits[0..2].parse().ok()?;
+    let minutes: i32 = digits[2..4].parse().ok()?;
+    let seconds: i32 = digits.get(4..6).map(|s| s.parse().unwrap_or(0)).unwrap_or(0);
+
+    Some(sign * (hours * 3600 + minutes * 60 + seconds))
+}
+
+fn parse_local(s: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(s.trim(), "%Y%m%dT%H%M%S").ok()
+}
+
+impl Observance {
+    fn last_onset(&self, before: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut onsets = self.rdates.clone();
+        onsets.push(self.start);
+
+        if let Some(rule) = &self.rule {
+            // Rules usually start in 1601 or 1970, skip ahead rather than expanding centuries of onsets
+            let shifted = match rule.count {
+                Some(_) => None,
+                None => self.start.with_year(before.year() - 1).filter(|start| *start > self.start)
+            };
+
+            let until = rule.until.as_ref().map(|until| match until {
+                Until::Local(until) => *until,
+                Until::Utc(until) => *until + Duration::seconds(self.offset_from as i64),
+            });
+
+            // DTSTART always counts as an occurrence, which a shifted one is not
+            let expanded = rule.expand(shifted.unwrap_or(self.start), until, before);
+            onsets.extend(expanded.into_iter().skip(shifted.is_some() as usize));
+        }
+
+        onsets.into_iter().filter(|onset| *onset <= before).max()
+    }
+}
+
+impl VTimezone {
+    fn from_ical(timezone: &IcalTimeZone) -> Option<(String, VTimezone)> {
+        let tzid = timezone.properties.iter().find(|p| p.name == "TZID")?.value.clone()?;
+
+        let observances = timezone.transitions.iter().filter_map(|transition| {
+            let mut start = None;
+            let mut rule = None;
+            let mut rdates = Vec::new();
+            let mut offset_from = None;
+            let mut offset_to = None;
+
+            for prop in &transition.properties {
+                let value = match &prop.value {
+                    Some(value) => value,
+                    None => continue
+                };
+
+                match prop.name.as_str() {
+                    "DTSTART" => start = parse_local(value),
+                    "RRULE" => rule = RRule::parse(value).ok(),
+                    "RDATE" => rdates.extend(value.split(',').filter_map(parse_local)),
+                    "TZOFFSETFROM" => offset_from = parse_offset(value),
+                    "TZOFFSETTO" => offset_to = parse_offset(value),
+                    _ => {}
+                }
+            }
+
+            let offset_to = offset_to?;
+            Some(Observance { start: start?, rule, rdates, offset_from: offset_from.unwrap_or(offset_to), offset_to })
+        }).collect::<Vec<_>>();
+
+        if observances.is_empty() {
+            return None
+        }
+
+        Some((tzid, VTimezone { observances }))
+    }
+
+    fn offset_at(&self, local: NaiveDateTime) -> i32 {
+        let latest = self.observances.iter()
+            .filter_map(|observance| Some((observance.last_onset(local)?, observance.offset_to)))
+            .max_by_key(|(onset, _)| *onset);
+
+        match latest {
+            Some((_, offset)) => offset,
+            // Before the first onset, whatever it changed from
+            None => self.observances.iter().min_by_key(|o| o.start).map(|o| o.offset_from).unwrap_or(0)
+        }
+    }
+}
+
+fn resolve<T: TimeZone>(tz: &T, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(time) => Some(time.with_timezone(&Utc)),
+        // Clocks going back, the first of the two
+        LocalResult::Ambiguous(time, _) => Some(time.with_timezone(&Utc)),
+        // Skipped by clocks going forward, which RFC 5545 reads with the offset from before the gap
+        LocalResult::None => tz.from_local_datetime(&(local + Duration::hours(1))).earliest().map(|t| t.with_timezone(&Utc)),
+    }
+}
+
+impl Zone {
+    pub fn to_utc(&self, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            Zone::Utc => Some(Utc.from_utc_datetime(&local)),
+            Zone::Local => resolve(&chrono::Local, local),
+            Zone::Iana(tz) => resolve(tz, local),
+            Zone::Embedded(tz) => {
+                let offset = Duration::seconds(tz.offset_at(local) as i64);
+                Some(Utc.from_utc_datetime(&(local - offset)))
+            },
+        }
+    }
+
+    pub fn wall_clock(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        // The same instant, on this wall clock
+        match self {
+            Zone::Utc => time.naive_utc(),
+            Zone::Local => time.with_timezone(&chrono::Local).naive_local(),
+            Zone::Iana(tz) => time.with_timezone(tz).naive_local(),
+            Zone::Embedded(tz) => {
+                // The offset depends on the local time being solved for, one guess gets close enough
+                let utc = time.naive_utc();
+                let guess = utc + Duration::seconds(tz.offset_at(utc) as i64);
+                utc + Duration::seconds(tz.offset_at(guess) as i64)
+            },
+        }
+    }
+}
+
+fn iana(tzid: &str) -> Option<Tz> {
+    // Some exporters prefix the name, eg /mozilla.org/20050126_1/Europe/Berlin
+    let parts: Vec<&str> = tzid.split('/').collect();
+    (0..parts.len()).find_map(|i| parts[i..].join("/").parse().ok())
+}
+
+#[derive(Debug, Default)]
+pub struct Zones {
+    embedded: HashMap<String, Arc<VTimezone>>,
+}
+
+impl Zones {
+    pub fn new(timezones: &[IcalTimeZone]) -> Zones {
+        let embedded = timezones.iter()
+            .filter_map(VTimezone::from_ical)
+            .map(|(tzid, timezone)| (tzid, Arc::new(timezone)))
+            .collect();
+
+        Zones { embedded }
+    }
+
+    pub fn get(&self, tzid: &str) -> Zone {
+        let tzid = tzid.trim_matches('"');
+
+        if let Some(tz) = iana(tzid) {
+            return Zone::Iana(tz)
+        }
+
+        match self.embedded.get(tzid) {
+            Some(timezone) => Zone::Embedded(timezone.clone()),
+            None => {
+                warn!("Unknown TZID {tzid}, assuming local time");
+                Zone::Local
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset("+1000"), Some(36000));
+        assert_eq!(parse_offset("-0430"), Some(-16200));
+        assert_eq!(parse_offset("+053000"), Some(19800));
+        assert_eq!(parse_offset("1000"), None);
+    }
+
+    #[test]
+    fn finds_iana_names_behind_prefixes() {
+        assert_eq!(iana("Australia/Sydney"), Some(chrono_tz::Australia::Sydney));
+        assert_eq!(iana("/mozilla.org/20050126_1/Europe/Berlin"), Some(chrono_tz::Europe::Berlin));
+        assert_eq!(iana("AUS Eastern Standard Time"), None);
+    }
+}
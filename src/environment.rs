@@ -0,0 +1,333 @@
+use serde::Deserialize;
+use serde_json::from_reader;
+use std::fs::File;
+
+use crate::weather;
+
+use futures::join;
+use std::error::Error;
+
+use log::{info, warn};
+use std::time::Instant;
+
+/*
+    Whether it is nice to be outside: the UV index, air quality and pollen, each with a category.
+
+    The UV index comes from Open-Meteo's air quality api, or in Australia from one of ARPANSA's
+    detectors when given in sensitive/environment.json as eg {"uv_location": "Sydney"} (the id of a
+    location in https://uvdata.arpansa.gov.au/xml/uvvalues.xml). Air quality is OWM's air pollution
+    api, with the weather's key. Pollen is also Open-Meteo's, which only has it for Europe.
+    All go by the weather's coordinates (sensitive/weather.json). Anything unavailable is left out.
+*/
+
+#[derive(Deserialize, Debug, Default)]
+struct EnvironmentConfig {
+    uv_location: Option<String>,
+}
+
+fn read_config() -> EnvironmentConfig {
+    let file = match File::open("sensitive/environment.json") {
+        Ok(file) => file,
+        Err(_) => return EnvironmentConfig::default()
+    };
+
+    match from_reader(file) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Could not parse sensitive/environment.json: {e}");
+            EnvironmentConfig::default()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct AirData {
+    current: Air,
+}
+
+/* Pollen is in grains/m³, and null outside of Europe */
+#[derive(Deserialize, Debug)]
+struct Air {
+    uv_index: Option<f64>,
+    alder_pollen: Option<f64>,
+    birch_pollen: Option<f64>,
+    grass_pollen: Option<f64>,
+    mugwort_pollen: Option<f64>,
+    olive_pollen: Option<f64>,
+    ragweed_pollen: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Pollution {
+    list: Vec<PollutionData>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PollutionData {
+    main: PollutionIndex,
+    components: Components,
+}
+
+#[derive(Deserialize, Debug)]
+struct PollutionIndex {
+    /* 1 (good) to 5 (very poor) */
+    aqi: u8,
+}
+
+#[derive(Deserialize, Debug)]
+struct Components {
+    pm2_5: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub value: f64,
+    pub category: &'static str,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    pub uv: Option<Reading>,
+    pub air_quality: Option<Reading>,
+    pub pollen: Option<Reading>,
+}
+
+pub fn uv_category(index: f64) -> &'static str {
+    // The WHO's, for the index rounded to a whole number
+    match index.round() as i64 {
+        ..=2 => "Low",
+        3..=5 => "Moderate",
+        6..=7 => "High",
+        8..=10 => "Very high",
+        _ => "Extreme"
+    }
+}
+
+pub fn aqi_category(aqi: u8) -> &'static str {
+    match aqi {
+        1 => "Good",
+        2 => "Fair",
+        3 => "Moderate",
+        4 => "Poor",
+        5 => "Very poor",
+        _ => "Unknown"
+    }
+}
+
+pub fn pollen_category(grains: f64) -> &'static str {
+    match grains {
+        grains if grains < 1.0 => "None",
+        grains if grains < 30.0 => "Low",
+        grains if grains < 100.0 => "Moderate",
+        grains if grains < 500.0 => "High",
+        _ => "Very high"
+    }
+}
+
+fn pollen(air: &Air) -> Option<Reading> {
+    // The worst of them, named
+    [
+        ("alder", air.alder_pollen), ("birch", air.birch_pollen), ("grass", air.grass_pollen),
+        ("mugwort", air.mugwort_pollen), ("olive", air.olive_pollen), ("ragweed", air.ragweed_pollen)
+    ].into_iter()
+        .filter_map(|(kind, grains)| Some((kind, grains?)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(kind, grains)| Reading { value: grains, category: pollen_category(grains), detail: Some(kind.to_string()) })
+}
+
+fn arpansa_index(document: &str, location: &str) -> Result<f64, String> {
+    let document = roxmltree::Document::parse(document).map_err(|e| format!("Invalid ARPANSA response: {e}"))?;
+
+    let station = document.descendants()
+        .filter(|node| node.has_tag_name("location"))
+        .find(|node| node.attribute("id").is_some_and(|id| id.eq_ignore_ascii_case(location)))
+        .ok_or(format!("ARPANSA has no UV location {location}"))?;
+    let field = |name: &str| station.children().find(|node| node.has_tag_name(name)).and_then(|node| node.text()).map(str::trim);
+
+    match field("status") {
+        Some("ok") => field("index").and_then(|index| index.parse().ok()).ok_or(format!("No UV index for {location}")),
+        status => Err(format!("ARPANSA's {location} detector is {}", status.unwrap_or("missing")))
+    }
+}
+
+struct Sources {
+    arpansa: String,
+    open_meteo: String,
+    openweathermap: String,
+    latitude: f64,
+    longitude: f64,
+    uv_location: Option<String>,
+    key: Option<String>,
+}
+
+impl Sources {
+    fn new(config: EnvironmentConfig) -> Sources {
+        let (latitude, longitude) = weather::coordinates();
+        let key = match weather::openweathermap_key() {
+            Ok(key) => Some(key),
+            Err(e) => {
+                warn!("No air quality without an openweathermap key: {e}");
+                None
+            }
+        };
+
+        Sources {
+            arpansa: "https://uvdata.arpansa.gov.au".to_string(),
+            open_meteo: "https://air-quality-api.open-meteo.com".to_string(),
+            openweathermap: "http://api.openweathermap.org".to_string(),
+            latitude,
+            longitude,
+            uv_location: config.uv_location,
+            key,
+        }
+    }
+
+    async fn arpansa(&self) -> Result<Option<f64>, Box<dyn Error>> {
+        let location = match &self.uv_location {
+            Some(location) => location,
+            None => return Ok(None)
+        };
+
+        let response = reqwest::get(format!("{}/xml/uvvalues.xml", self.arpansa)).await?;
+        let response = response.error_for_status()?;
+        Ok(Some(arpansa_index(&response.text().await?, location)?))
+    }
+
+    async fn open_meteo(&self) -> Result<Air, Box<dyn Error>> {
+        let url = format!(
+            "{}/v1/air-quality?latitude={}&longitude={}&current=uv_index,alder_pollen,birch_pollen,grass_pollen,mugwort_pollen,olive_pollen,ragweed_pollen",
+            self.open_meteo, self.latitude, self.longitude
+        );
+
+        let response = reqwest::get(&url).await?;
+        let response = response.error_for_status()?;
+        let data: AirData = response.json().await?;
+        Ok(data.current)
+    }
+
+    async fn air_quality(&self) -> Result<Option<Reading>, Box<dyn Error>> {
+        let key = match &self.key {
+            Some(key) => key,
+            None => return Ok(None)
+        };
+
+        let url = format!("{}/data/2.5/air_pollution?lat={}&lon={}&appid={key}", self.openweathermap, self.latitude, self.longitude);
+        let response = reqwest::get(&url).await?;
+        let response = response.error_for_status()?;
+        let pollution: Pollution = response.json().await?;
+
+        let now = pollution.list.first().ok_or("No air quality data")?;
+        Ok(Some(Reading {
+            value: now.main.aqi as f64,
+            category: aqi_category(now.main.aqi),
+            detail: now.components.pm2_5.map(|pm2_5| format!("PM2.5 {pm2_5:.0}")),
+        }))
+    }
+
+    async fn fetch(&self) -> Result<Environment, String> {
+        let (arpansa, air, air_quality) = join!(self.arpansa(), self.open_meteo(), self.air_quality());
+
+        // Each part failing only leaves that part out
+        let arpansa = arpansa.unwrap_or_else(|e| {warn!("Could not fetch the UV index from ARPANSA: {e}"); None});
+        let air = air.map_err(|e| warn!("Could not fetch UV and pollen from Open-Meteo: {e}")).ok();
+        let air_quality = air_quality.unwrap_or_else(|e| {warn!("Could not fetch the air quality: {e}"); None});
+
+        let environment = Environment {
+            uv: arpansa.or(air.as_ref().and_then(|air| air.uv_index)).map(|index| Reading { value: index, category: uv_category(index), detail: None }),
+            air_quality,
+            pollen: air.as_ref().and_then(pollen),
+        };
+
+        match environment {
+            Environment { uv: None, air_quality: None, pollen: None } => Err("No UV, air quality or pollen data".to_string()),
+            environment => Ok(environment)
+        }
+    }
+}
+
+pub async fn fetch_environment() -> Result<Environment, Box<dyn Error>> {
+    info!("Fetching UV, air quality and pollen...");
+    let now = Instant::now();
+
+    let environment = Sources::new(read_config()).fetch().await?;
+    info!("UV {:?}, air quality {:?}, pollen {:?}", environment.uv, environment.air_quality, environment.pollen);
+
+    let elapsed = format!("{:.2?}", now.elapsed());
+    info!("Environment took {elapsed}");
+
+    Ok(environment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, Reply};
+
+    fn sources(base: &str, uv_location: Option<&str>) -> Sources {
+        Sources {
+            arpansa: base.to_string(),
+            open_meteo: base.to_string(),
+            openweathermap: base.to_string(),
+            latitude: -33.8679,
+            longitude: 151.2073,
+            uv_location: uv_location.map(str::to_string),
+            key: Some("KEY".to_string()),
+        }
+    }
+
+    #[test]
+    fn categorises_readings() {
+        assert_eq!([0.4, 2.6, 5.4, 7.0, 10.2, 11.0].map(uv_category), ["Low", "Moderate", "Moderate", "High", "Very high", "Extreme"]);
+        assert_eq!([1, 3, 5, 0].map(aqi_category), ["Good", "Moderate", "Very poor", "Unknown"]);
+        assert_eq!([0.0, 12.4, 58.9, 120.0, 800.0].map(pollen_category), ["None", "Low", "Moderate", "High", "Very high"]);
+    }
+
+    #[test]
+    fn reads_an_arpansa_location() {
+        let document = include_str!("fixtures/environment/arpansa.xml");
+        assert_eq!(arpansa_index(document, "sydney"), Ok(3.4));
+        assert!(arpansa_index(document, "Melbourne").is_err_and(|e| e.contains("Offline")));
+        assert!(arpansa_index(document, "Hobart").is_err());
+    }
+
+    #[tokio::test]
+    async fn fetches_uv_air_quality_and_pollen() {
+        let server = mock::serve(|request| match request.path.split('?').next() {
+            Some("/v1/air-quality") => Reply::ok(include_str!("fixtures/environment/open_meteo_air.json")),
+            Some("/data/2.5/air_pollution") => Reply::ok(include_str!("fixtures/environment/owm_air_pollution.json")),
+            _ => Reply::new(404, "")
+        }).await;
+
+        let environment = sources(&server.url, None).fetch().await.unwrap();
+        assert_eq!(environment.uv, Some(Reading { value: 6.15, category: "High", detail: None }));
+        assert_eq!(environment.air_quality, Some(Reading { value: 2.0, category: "Fair", detail: Some("PM2.5 6".to_string()) }));
+        assert_eq!(environment.pollen, Some(Reading { value: 58.9, category: "Moderate", detail: Some("grass".to_string()) }));
+
+        let owm = server.requests().into_iter().find(|request| request.path.starts_with("/data")).unwrap();
+        assert_eq!(owm.path, "/data/2.5/air_pollution?lat=-33.8679&lon=151.2073&appid=KEY");
+    }
+
+    #[tokio::test]
+    async fn prefers_arpansa_and_leaves_out_what_is_missing() {
+        let server = mock::serve(|request| match request.path.split('?').next() {
+            Some("/xml/uvvalues.xml") => Reply::ok(include_str!("fixtures/environment/arpansa.xml")),
+            Some("/v1/air-quality") => Reply::ok(include_str!("fixtures/environment/open_meteo_air_no_pollen.json")),
+            _ => Reply::new(401, r#"{"cod": 401, "message": "Invalid API key"}"#)
+        }).await;
+
+        let environment = sources(&server.url, Some("Sydney")).fetch().await.unwrap();
+        assert_eq!(environment.uv.map(|uv| (uv.value, uv.category)), Some((3.4, "Moderate")));
+        assert_eq!((environment.air_quality, environment.pollen), (None, None));
+
+        // Open-Meteo's UV when the detector is down
+        let environment = sources(&server.url, Some("Melbourne")).fetch().await.unwrap();
+        assert_eq!(environment.uv.map(|uv| uv.value), Some(2.85));
+    }
+
+    #[tokio::test]
+    async fn fails_when_nothing_is_available() {
+        let server = mock::serve(|_| Reply::new(500, "")).await;
+        assert!(sources(&server.url, Some("Sydney")).fetch().await.is_err());
+    }
+}
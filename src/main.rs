@@ -2,30 +2,29 @@
 
 mod calendar;
 mod weather;
+mod environment;
 mod news;
 mod stats;
 mod radar;
+mod warnings;
 mod renderer;
+mod server;
+mod status;
+mod metrics;
+mod service;
+mod scheduler;
+
+#[cfg(test)]
+mod mock;
 
 mod utils;
 
-use chrono::Timelike;
-use env_logger;
-use log;
 use std::{env, panic::AssertUnwindSafe, time::Duration};
 use futures::FutureExt;
 
-use log::info;
-
-fn get_duration_until_next_interval() -> u64 {
-    let now = chrono::Local::now();
-    let minutes = now.minute();
-    let seconds = now.second();
-    let next_interval_minutes = 15 - (minutes % 15);
-    (next_interval_minutes * 60 - seconds) as u64
-}
+use log::{info, warn};
 
-async fn panic_wrapper() -> Result<(), String> {
+async fn panic_wrapper(dashboard: &mut renderer::Dashboard) -> Result<(), String> {
     /*
     
         The only time a panic should happen is if we cannot allocate memory, write to disk, or create a valid svg.
@@ -36,14 +35,19 @@ async fn panic_wrapper() -> Result<(), String> {
      */
 
     let may_panic = async {
-        utils::check_internet_with_retries(3, Duration::from_secs(5)).await.unwrap();
-        renderer::render_png().await
+        if !dashboard.is_quiet() {
+            utils::check_internet_with_retries(3, Duration::from_secs(5)).await.unwrap();
+        }
+        renderer::render_png(dashboard).await
     };
 
     let panic_result = AssertUnwindSafe(may_panic).catch_unwind().await;
 
+    metrics::observe_cycle(panic_result.is_err());
+    metrics::write_file();
+
     match panic_result {
-        Ok(_r) => {return Ok(())},
+        Ok(_r) => {Ok(())},
         Err(e) => {
 
             let &panic_message;
@@ -71,8 +75,36 @@ async fn panic_wrapper() -> Result<(), String> {
 
 #[tokio::main]
 async fn main() {
+    let args = match service::parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    if args.init_script {
+        match service::init_script() {
+            Ok(script) => print!("{script}"),
+            Err(e) => eprintln!("{e}")
+        }
+        return;
+    }
+
     if env::var("RUST_LOG").is_err() {env::set_var("RUST_LOG", "info")}
-    env_logger::init();
+    service::init_logging(&args).unwrap();
+
+    if args.service {
+        if let Err(e) = service::write_pid_file(&args.pid_file) {
+            warn!("{e}");
+            std::process::exit(1);
+        }
+    }
+
+    let pid_file = if args.service {Some(args.pid_file.clone())} else {None};
+    if let Err(e) = service::handle_signals(pid_file) {
+        warn!("Could not listen for signals: {e}");
+    }
 
     if env::var("NOT_KINDLE").is_err() {
         utils::check_xrandr().unwrap();
@@ -80,12 +112,21 @@ async fn main() {
         utils::check_sensitives().unwrap();
     }
 
-    panic_wrapper().await.ok();
+    server::reload().await;
+
+    let mut dashboard = renderer::Dashboard::new();
+    panic_wrapper(&mut dashboard).await.ok();
 
     loop {
-        let wait = get_duration_until_next_interval();
+        let wait = dashboard.scheduler.seconds_until_next_wake();
         info!("Sleeping for {wait} seconds...");
-        tokio::time::sleep(std::time::Duration::from_secs(wait)).await;
-        panic_wrapper().await.ok();
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(wait)) => {},
+            _ = status::refresh_requested() => {
+                info!("Refreshing everything early on request");
+                dashboard.force_refresh();
+            }
+        }
+        panic_wrapper(&mut dashboard).await.ok();
     }
 }
\ No newline at end of file
@@ -0,0 +1,255 @@
+use serde::Deserialize;
+use serde_json::from_reader;
+use std::fs::File;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+/*
+    Counters and timings kept across cycles, exposed in the prometheus text format.
+
+    They are served from /metrics by the status server, and/or written to a file every
+    cycle when sensitive/metrics.json exists, eg {"path": "/dash/metrics.prom"}, for
+    node_exporter's textfile collector to pick up.
+*/
+
+#[derive(Deserialize, Debug)]
+struct MetricsConfig {
+    path: String,
+}
+
+#[derive(Debug, Default)]
+struct SourceMetrics {
+    successes: u64,
+    failures: u64,
+    duration_sum: Duration,
+    last_duration: Duration,
+    last_success: Option<SystemTime>,
+}
+
+#[derive(Debug, Default)]
+struct StageMetrics {
+    count: u64,
+    duration_sum: Duration,
+    last_duration: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    sources: BTreeMap<String, SourceMetrics>,
+    stages: BTreeMap<String, StageMetrics>,
+    cycles: u64,
+    panics: u64,
+    eips_runs: u64,
+    eips_skipped: u64,
+    eips_failures: u64,
+    eips_exit_status: Option<i32>,
+}
+
+static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(|| Mutex::new(Registry::default()));
+
+fn get() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn observe_fetch(source: &str, duration: Duration, ok: bool) {
+    let mut registry = get();
+    let source = registry.sources.entry(source.to_string()).or_default();
+
+    source.duration_sum += duration;
+    source.last_duration = duration;
+
+    if ok {
+        source.successes += 1;
+        source.last_success = Some(SystemTime::now());
+    } else {
+        source.failures += 1;
+    }
+}
+
+pub fn observe_stage(stage: &str, duration: Duration) {
+    let mut registry = get();
+    let stage = registry.stages.entry(stage.to_string()).or_default();
+
+    stage.count += 1;
+    stage.duration_sum += duration;
+    stage.last_duration = duration;
+}
+
+pub fn observe_eips(exit_status: Option<i32>) {
+    // None means eips could not be run at all (or was killed by a signal)
+    let mut registry = get();
+    registry.eips_runs += 1;
+    registry.eips_exit_status = exit_status;
+
+    if exit_status != Some(0) {
+        registry.eips_failures += 1;
+    }
+}
+
+pub fn observe_eips_skipped() {
+    get().eips_skipped += 1;
+}
+
+pub fn observe_cycle(panicked: bool) {
+    let mut registry = get();
+    registry.cycles += 1;
+
+    if panicked {
+        registry.panics += 1;
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs_f64()
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").ok();
+    writeln!(out, "# TYPE {name} {kind}").ok();
+}
+
+fn escape(value: &str) -> String {
+    // Label values are quoted, so backslashes, quotes and newlines have to be escaped
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub fn render() -> String {
+    render_registry(&get())
+}
+
+fn render_registry(registry: &Registry) -> String {
+    let mut out = String::new();
+
+    header(&mut out, "kindle_fetch_total", "counter", "Data source fetches by result.");
+    for (name, source) in &registry.sources {
+        let name = escape(name);
+        writeln!(out, "kindle_fetch_total{{source=\"{name}\",result=\"success\"}} {}", source.successes).ok();
+        writeln!(out, "kindle_fetch_total{{source=\"{name}\",result=\"failure\"}} {}", source.failures).ok();
+    }
+
+    header(&mut out, "kindle_fetch_duration_seconds", "summary", "Time taken to fetch each data source.");
+    for (name, source) in &registry.sources {
+        let name = escape(name);
+        writeln!(out, "kindle_fetch_duration_seconds_sum{{source=\"{name}\"}} {}", seconds(source.duration_sum)).ok();
+        writeln!(out, "kindle_fetch_duration_seconds_count{{source=\"{name}\"}} {}", source.successes + source.failures).ok();
+    }
+
+    header(&mut out, "kindle_fetch_last_duration_seconds", "gauge", "Time taken by the most recent fetch of each data source.");
+    for (name, source) in &registry.sources {
+        let name = escape(name);
+        writeln!(out, "kindle_fetch_last_duration_seconds{{source=\"{name}\"}} {}", seconds(source.last_duration)).ok();
+    }
+
+    header(&mut out, "kindle_fetch_last_success_timestamp_seconds", "gauge", "Unix time of the last successful fetch of each data source.");
+    for (name, source) in &registry.sources {
+        if let Some(last_success) = source.last_success {
+            let name = escape(name);
+            let timestamp = last_success.duration_since(UNIX_EPOCH).unwrap_or_default();
+            writeln!(out, "kindle_fetch_last_success_timestamp_seconds{{source=\"{name}\"}} {}", timestamp.as_secs()).ok();
+        }
+    }
+
+    header(&mut out, "kindle_stage_duration_seconds", "summary", "Time taken by each rendering stage.");
+    for (name, stage) in &registry.stages {
+        let name = escape(name);
+        writeln!(out, "kindle_stage_duration_seconds_sum{{stage=\"{name}\"}} {}", seconds(stage.duration_sum)).ok();
+        writeln!(out, "kindle_stage_duration_seconds_count{{stage=\"{name}\"}} {}", stage.count).ok();
+    }
+
+    header(&mut out, "kindle_stage_last_duration_seconds", "gauge", "Time taken by the most recent run of each rendering stage.");
+    for (name, stage) in &registry.stages {
+        let name = escape(name);
+        writeln!(out, "kindle_stage_last_duration_seconds{{stage=\"{name}\"}} {}", seconds(stage.last_duration)).ok();
+    }
+
+    header(&mut out, "kindle_cycles_total", "counter", "Refresh cycles run.");
+    writeln!(out, "kindle_cycles_total {}", registry.cycles).ok();
+
+    header(&mut out, "kindle_panics_total", "counter", "Refresh cycles that panicked.");
+    writeln!(out, "kindle_panics_total {}", registry.panics).ok();
+
+    header(&mut out, "kindle_eips_runs_total", "counter", "Times eips was run to show a frame.");
+    writeln!(out, "kindle_eips_runs_total {}", registry.eips_runs).ok();
+
+    header(&mut out, "kindle_eips_skipped_total", "counter", "Times eips was not run because the frame was unchanged.");
+    writeln!(out, "kindle_eips_skipped_total {}", registry.eips_skipped).ok();
+
+    header(&mut out, "kindle_eips_failures_total", "counter", "Times eips could not be run or exited non-zero.");
+    writeln!(out, "kindle_eips_failures_total {}", registry.eips_failures).ok();
+
+    header(&mut out, "kindle_eips_exit_status", "gauge", "Exit status of the last eips run, -1 if it could not be run.");
+    writeln!(out, "kindle_eips_exit_status {}", registry.eips_exit_status.unwrap_or(-1)).ok();
+
+    out
+}
+
+pub fn write_file() {
+    let file = match File::open("sensitive/metrics.json") {
+        Ok(file) => file,
+        Err(_) => return
+    };
+
+    let config: MetricsConfig = match from_reader(file) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Could not parse sensitive/metrics.json: {e}");
+            return
+        }
+    };
+
+    // Write then rename, so a collector never reads half a file
+    let tmp = format!("{}.tmp", config.path);
+    let result = std::fs::write(&tmp, render()).and_then(|_| std::fs::rename(&tmp, &config.path));
+
+    if let Err(e) = result {
+        warn!("Could not write metrics to {}: {e}", config.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_text_format() {
+        let mut registry = Registry { cycles: 3, eips_runs: 2, eips_exit_status: Some(0), ..Default::default() };
+        registry.sources.insert("weather".to_string(), SourceMetrics {
+            successes: 2,
+            failures: 1,
+            duration_sum: Duration::from_millis(1500),
+            last_duration: Duration::from_millis(250),
+            last_success: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+        });
+        registry.stages.insert("render".to_string(), StageMetrics { count: 3, duration_sum: Duration::from_secs(3), last_duration: Duration::from_secs(1) });
+
+        let out = render_registry(&registry);
+        for line in [
+            "# TYPE kindle_fetch_total counter",
+            "kindle_fetch_total{source=\"weather\",result=\"success\"} 2",
+            "kindle_fetch_total{source=\"weather\",result=\"failure\"} 1",
+            "kindle_fetch_duration_seconds_sum{source=\"weather\"} 1.5",
+            "kindle_fetch_duration_seconds_count{source=\"weather\"} 3",
+            "kindle_fetch_last_success_timestamp_seconds{source=\"weather\"} 1700000000",
+            "kindle_stage_last_duration_seconds{stage=\"render\"} 1",
+            "kindle_cycles_total 3",
+            "kindle_eips_exit_status 0",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {line} in\n{out}");
+        }
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let mut registry = Registry::default();
+        registry.sources.insert("calendar: \"Work\" \\ home\nnext".to_string(), SourceMetrics::default());
+
+        let out = render_registry(&registry);
+        assert!(out.contains(r#"kindle_fetch_total{source="calendar: \"Work\" \\ home\nnext",result="success"} 0"#));
+        assert!(out.lines().all(|line| !line.starts_with("next")));
+    }
+}
@@ -1,11 +1,14 @@
-use reqwest;
-use image;
+mod map;
+pub mod palette;
+
 use serde::Deserialize;
 use serde_json::from_reader;
 use std::fs::File;
 
 use image::{DynamicImage, GenericImageView, GenericImage, imageops};
+use map::{Coordinates, LayerCache};
 
+use chrono::{DateTime, NaiveDateTime, Utc};
 use regex::Regex;
 use reqwest::header::USER_AGENT;
 
@@ -13,28 +16,60 @@ use log::{info, warn};
 use std::time::Instant;
 
 
+/*
+    From sensitive/bom.json, eg {"station": "IDR713", "frames": 4, "here": {"latitude": -33.8679, "longitude": 151.2073}}
+    frames being how many of the loop to show, and here where to mark on the map. The map's layers
+    can be listed as eg "layers": ["background", "topography", "roads", "locations", "range"] (see map.rs),
+    and for a radar map.rs does not know the position of, given as "site": {"latitude": .., "longitude": ..}
+*/
 #[derive(Deserialize, Debug)]
 struct Bom {
     station: String,
+    #[serde(default = "default_frames")]
+    frames: usize,
+    #[serde(default = "map::default_layers")]
+    layers: Vec<String>,
+    here: Option<Coordinates>,
+    site: Option<Coordinates>,
 }
 
-pub async fn get_image(url:String) -> Result<image::DynamicImage, String> {
+fn default_frames() -> usize {4}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /* When the radar was swept, from the frame's name */
+    pub time: Option<DateTime<Utc>>,
+    pub image: DynamicImage,
+    /* Where "here" is marked on the image, if it is */
+    pub marker: Option<(i64, i64)>,
+}
+
+pub async fn get_bytes(url:String) -> Result<Vec<u8>, String> {
     let client = reqwest::Client::new();
 
-    let img_bytes = match client
+    let bytes = match client
         .get(url)
         .header(USER_AGENT, "Mozilla/5.0 (Android 4.4; Mobile; rv:41.0) Gecko/41.0 Firefox/41.0")
         .send()
         .await {
             Ok(response) => {
+                if !response.status().is_success() {
+                    return Err(format!("Request failed with status: {}", response.status()))
+                }
                 match response.bytes().await {
-                    Ok(img_bytes) => img_bytes,
+                    Ok(bytes) => bytes,
                     Err(err) => return Err(format!("Failed to read response bytes: {}", err)),
                 }
             },
             Err(err) => return Err(format!("Failed to fetch image: {}", err)),
     };
 
+    Ok(bytes.to_vec())
+}
+
+pub async fn get_image(url:String) -> Result<image::DynamicImage, String> {
+    let img_bytes = get_bytes(url).await?;
+
     let image = match image::load_from_memory(&img_bytes) {
         Ok(img) => img,
         Err(err) => return Err(format!("Failed to load image: {}", err)),
@@ -43,7 +78,27 @@ pub async fn get_image(url:String) -> Result<image::DynamicImage, String> {
     Ok(image)
 }
 
-pub async fn get_radar_id(station:String) -> Result<String, Box<dyn std::error::Error>> {
+pub fn frame_paths(page: &str, station: &str, count: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    // The loop page lists its frames oldest first, eg /radar/IDR713.T.202405300400.png
+    let re = Regex::new(format!(r#"/radar/{station}\.T\.\d+\.png"#).as_str())?;
+
+    let mut paths: Vec<String> = vec![];
+    for found in re.find_iter(page) {
+        if !paths.iter().any(|path| path == found.as_str()) {
+            paths.push(found.as_str().to_string());
+        }
+    }
+
+    Ok(paths.split_off(paths.len().saturating_sub(count.max(1))))
+}
+
+pub fn frame_time(path: &str) -> Option<DateTime<Utc>> {
+    // Frames are named by their time in UTC, to the minute
+    let stamp = path.rsplit(".T.").next()?.strip_suffix(".png")?;
+    NaiveDateTime::parse_from_str(stamp, "%Y%m%d%H%M").ok().map(|time| time.and_utc())
+}
+
+pub async fn get_radar_ids(station: String, count: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
     let url = format!("https://reg.bom.gov.au/products/{station}.loop.shtml");
     
     let client = reqwest::Client::new();
@@ -62,15 +117,12 @@ pub async fn get_radar_id(station:String) -> Result<String, Box<dyn std::error::
             Err(err) => return Err(format!("Failed to fetch image: {}", err).into()),
     };
 
-    let re = Regex::new(format!(r#"/radar/{station}\.T\.\d+\.png"#).as_str())?;
-    let matches: Vec<_> = re.find_iter(&response).collect();
-    
-    if let Some(last_match) = matches.last() {
-        let url: &_ = &response[last_match.start()..last_match.end()];
-        return Ok(url.to_string());
+    let paths = frame_paths(&response, &station, count)?;
+    if paths.is_empty() {
+        return Err("No images?".to_string().into())
     }
 
-    return Err("No images?".to_string().into())
+    Ok(paths)
 }
 
 fn hide_banner(image: &DynamicImage) -> DynamicImage {
@@ -89,7 +141,7 @@ fn hide_banner(image: &DynamicImage) -> DynamicImage {
     img
 }
 
-pub async fn fetch_radar() -> Result<DynamicImage, String> {
+pub async fn fetch_radar() -> Result<Vec<Frame>, String> {
     info!("Fetching radar...");
     let now = Instant::now();
 
@@ -97,25 +149,88 @@ pub async fn fetch_radar() -> Result<DynamicImage, String> {
     let json: Bom = from_reader(file).expect("Unable to parse bom.json");
     let station = json.station.clone();
 
-    if let Ok(radar_id) = get_radar_id(station.clone()).await {
-        if let Ok(image1) = get_image(format!("https://reg.bom.gov.au{}", radar_id)).await {
-            let image1 = hide_banner(&image1);
-
-            if let Ok(mut image2) = get_image(format!("https://reg.bom.gov.au/products/radar_transparencies/{station}.background.png")).await {
-                imageops::overlay(&mut image2, &image1, 0, 0);
-                info!("Radar took {:.2?}", now.elapsed());
-                return Ok(image2);
-            } else {
-                warn!("Could not load background image for station {}", station);
-                return Err(format!("Could not load background image for station {}", station));
+    let radar_ids = match get_radar_ids(station.clone(), json.frames).await {
+        Ok(radar_ids) => radar_ids,
+        Err(_) => {
+            warn!("Could not get radar ID for station {}", station);
+            return Err(format!("Could not get radar ID for station {}", station))
+        }
+    };
+
+    let cache = LayerCache::new("cache/radar", "https://reg.bom.gov.au");
+    let (below, above) = map::ordered(&json.layers);
+    let mut below = cache.stack(&station, &below).await;
+    let mut above = cache.stack(&station, &above).await;
+
+    // Greys the kindle can tell apart, see palette.rs
+    palette::wash_out(&mut below);
+    palette::blacken(&mut above);
+
+    let mut marker = None;
+    if let Some(here) = json.here {
+        let position = json.site.or(map::site(&station))
+            .zip(map::kilometres_per_pixel(&station))
+            .and_then(|(site, scale)| map::pixel(site, here, scale));
+        match position {
+            Some(position) => {
+                map::draw_marker(&mut above, position);
+                marker = Some(position);
+            },
+            None => warn!("Could not mark {here:?} on the {station} radar, it is off the map or the radar's site is not known")
+        }
+    }
+
+    // Oldest first. An older frame going missing only shortens the loop, the newest is needed though
+    let mut frames = vec![];
+    for (i, radar_id) in radar_ids.iter().enumerate() {
+        match get_image(format!("https://reg.bom.gov.au{}", radar_id)).await {
+            Ok(rain) => {
+                let mut image = DynamicImage::ImageRgba8(below.clone());
+                imageops::overlay(&mut image, &DynamicImage::ImageRgba8(palette::rain(&hide_banner(&rain))), 0, 0);
+                imageops::overlay(&mut image, &DynamicImage::ImageRgba8(above.clone()), 0, 0);
+                frames.push(Frame { time: frame_time(radar_id), image, marker });
+            },
+            Err(_) if i + 1 < radar_ids.len() => warn!("Could not load rain data for radar ID {}, skipping it", radar_id),
+            Err(_) => {
+                warn!("Could not load rain data for radar ID {}", radar_id);
+                return Err(format!("Could not load rain data for radar ID {}", radar_id))
             }
-        } else {
-            warn!("Could not load rain data for radar ID {}", radar_id);
-            return Err(format!("Could not load rain data for radar ID {}", radar_id));
         }
-    } else {
-        warn!("Could not get radar ID for station {}", station);
-        return Err(format!("Could not get radar ID for station {}", station));
     }
+
+    info!("Radar ({} frames) took {:.2?}", frames.len(), now.elapsed());
+    Ok(frames)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOOP_PAGE: &str = r#"<script type="text/javascript">
+theImageNames = new Array();
+theImageNames[0] = "/radar/IDR713.T.202405300330.png";
+theImageNames[1] = "/radar/IDR713.T.202405300336.png";
+theImageNames[2] = "/radar/IDR713.T.202405300342.png";
+theImageNames[3] = "/radar/IDR713.T.202405300348.png";
+theImageNames[4] = "/radar/IDR713.T.202405300354.png";
+</script>
+<noscript><img src="/radar/IDR713.T.202405300354.png" alt="" /></noscript>
+<img src="/radar/IDR714.T.202405300354.png" alt="" />"#;
+
+    #[test]
+    fn keeps_the_newest_frames_in_order() {
+        let paths = frame_paths(LOOP_PAGE, "IDR713", 3).unwrap();
+        assert_eq!(paths, ["/radar/IDR713.T.202405300342.png", "/radar/IDR713.T.202405300348.png", "/radar/IDR713.T.202405300354.png"]);
+
+        assert_eq!(frame_paths(LOOP_PAGE, "IDR713", 10).unwrap().len(), 5);
+        assert_eq!(frame_paths(LOOP_PAGE, "IDR713", 0).unwrap(), ["/radar/IDR713.T.202405300354.png"]);
+        assert!(frame_paths(LOOP_PAGE, "IDR710", 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_frame_times() {
+        let time = frame_time("/radar/IDR713.T.202405300354.png");
+        assert_eq!(time.map(|time| time.to_rfc3339()).as_deref(), Some("2024-05-30T03:54:00+00:00"));
+        assert_eq!(frame_time("/radar/IDR713.background.png"), None);
+    }
+}
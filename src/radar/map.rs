@@ -0,0 +1,199 @@
+use super::get_bytes;
+
+use image::{DynamicImage, Rgba, RgbaImage, imageops};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+/*
+    The map the rain is drawn over, from the BOM's radar transparencies for the station. Each layer
+    is a 512x512 png which never changes, so they are downloaded once and kept in cache/radar/.
+    Layers go under or over the rain by what they are, whatever order they are listed in.
+*/
+
+const SIZE: u32 = 512;
+
+// Under the rain, bottom first
+const BELOW: [&str; 6] = ["background", "topography", "catchments", "waterways", "roads", "rail"];
+// Over it, so the rings and names stay readable through heavy rain
+const ABOVE: [&str; 2] = ["range", "locations"];
+
+pub fn default_layers() -> Vec<String> {
+    ["background", "topography", "locations", "range"].map(str::to_string).to_vec()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+pub fn ordered(layers: &[String]) -> (Vec<&str>, Vec<&str>) {
+    for layer in layers.iter().filter(|layer| !BELOW.contains(&layer.as_str()) && !ABOVE.contains(&layer.as_str())) {
+        warn!("Unknown radar layer {layer} in sensitive/bom.json");
+    }
+
+    let pick = |known: &[&'static str]| known.iter().copied().filter(|name| layers.iter().any(|layer| layer == name)).collect();
+    (pick(&BELOW), pick(&ABOVE))
+}
+
+pub struct LayerCache {
+    dir: PathBuf,
+    base: String,
+}
+
+impl LayerCache {
+    pub fn new(dir: impl AsRef<Path>, base: &str) -> LayerCache {
+        LayerCache { dir: dir.as_ref().to_path_buf(), base: base.to_string() }
+    }
+
+    pub async fn get(&self, station: &str, layer: &str) -> Result<DynamicImage, String> {
+        let path = self.dir.join(format!("{station}.{layer}.png"));
+
+        if let Ok(image) = image::open(&path) {
+            return Ok(image)
+        }
+
+        let bytes = get_bytes(format!("{}/products/radar_transparencies/{station}.{layer}.png", self.base)).await?;
+        let image = image::load_from_memory(&bytes).map_err(|e| format!("Failed to load the {layer} layer: {e}"))?;
+
+        // Only kept once it is known to be an image, so a bad download is tried again next time
+        if let Err(e) = std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::write(&path, &bytes)) {
+            warn!("Could not cache {}: {e}", path.display());
+        }
+        Ok(image)
+    }
+
+    pub async fn stack(&self, station: &str, layers: &[&str]) -> RgbaImage {
+        // A layer that cannot be had is left out rather than losing the whole radar
+        let mut stack = RgbaImage::new(SIZE, SIZE);
+        for layer in layers {
+            match self.get(station, layer).await {
+                Ok(image) => imageops::overlay(&mut stack, &image.to_rgba8(), 0, 0),
+                Err(e) => warn!("Could not load the {layer} layer for {station}: {e}")
+            }
+        }
+        stack
+    }
+}
+
+pub fn site(station: &str) -> Option<Coordinates> {
+    // Where some of the BOM's radars are, by the number in their id (IDR71x is Terrey Hills)
+    let (latitude, longitude) = match station.get(3..5)? {
+        "02" => (-37.855, 144.755),
+        "40" => (-35.661, 149.512),
+        "63" => (-12.457, 130.925),
+        "64" => (-34.617, 138.469),
+        "66" => (-27.718, 153.240),
+        "70" => (-32.392, 115.867),
+        "71" => (-33.701, 151.210),
+        "76" => (-43.112, 147.806),
+        _ => return None
+    };
+    Some(Coordinates { latitude, longitude })
+}
+
+pub fn kilometres_per_pixel(station: &str) -> Option<f64> {
+    // The last digit of the id is the range: 1 is 512km, 2 is 256km, 3 is 128km and 4 is 64km either side
+    let range = match station.chars().last()? {
+        '1' => 512.0,
+        '2' => 256.0,
+        '3' => 128.0,
+        '4' => 64.0,
+        _ => return None
+    };
+    Some(range * 2.0 / SIZE as f64)
+}
+
+pub fn pixel(site: Coordinates, here: Coordinates, kilometres_per_pixel: f64) -> Option<(i64, i64)> {
+    // Flat is close enough over a few hundred kilometres
+    let east = (here.longitude - site.longitude) * 111.32 * site.latitude.to_radians().cos();
+    let north = (here.latitude - site.latitude) * 110.57;
+
+    let x = (SIZE as f64 / 2.0 + east / kilometres_per_pixel).round() as i64;
+    let y = (SIZE as f64 / 2.0 - north / kilometres_per_pixel).round() as i64;
+    if (0..SIZE as i64).contains(&x) && (0..SIZE as i64).contains(&y) {Some((x, y))} else {None}
+}
+
+pub fn draw_marker(image: &mut RgbaImage, (x, y): (i64, i64)) {
+    // A bullseye, with a white edge to stand out against both the map and the rain
+    for dy in -11..=11_i64 {
+        for dx in -11..=11_i64 {
+            let (px, py) = (x + dx, y + dy);
+            if px < 0 || py < 0 || px >= image.width() as i64 || py >= image.height() as i64 {
+                continue
+            }
+
+            let distance = ((dx * dx + dy * dy) as f64).sqrt();
+            let colour = match distance {
+                d if d <= 3.5 => [0, 0, 0],
+                d if d <= 6.0 => [255, 255, 255],
+                d if d <= 9.0 => [0, 0, 0],
+                d if d <= 11.0 => [255, 255, 255],
+                _ => continue
+            };
+            image.put_pixel(px as u32, py as u32, Rgba([colour[0], colour[1], colour[2], 255]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, Reply};
+    use std::io::Cursor;
+
+    fn png(colour: [u8; 4]) -> Vec<u8> {
+        let mut bytes = Cursor::new(vec![]);
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(SIZE, SIZE, Rgba(colour))).write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn orders_layers_around_the_rain() {
+        let layers = ["range", "locations", "topography", "background", "clouds"].map(str::to_string);
+        assert_eq!(ordered(&layers), (vec!["background", "topography"], vec!["range", "locations"]));
+    }
+
+    #[test]
+    fn places_coordinates_on_the_map() {
+        let sydney = site("IDR713").unwrap();
+        assert_eq!(kilometres_per_pixel("IDR713"), Some(0.5));
+
+        // The radar itself is the middle, and the CBD about 19km south of Terrey Hills
+        assert_eq!(pixel(sydney, sydney, 0.5), Some((256, 256)));
+        assert_eq!(pixel(sydney, Coordinates { latitude: -33.8679, longitude: 151.2073 }, 0.5), Some((255, 293)));
+
+        // Canberra is off the 128km map, but not the 512km one
+        let canberra = Coordinates { latitude: -35.2809, longitude: 149.1300 };
+        assert_eq!(pixel(sydney, canberra, 0.5), None);
+        assert_eq!(pixel(sydney, canberra, 2.0), Some((160, 343)));
+
+        assert_eq!(site("IDR993"), None);
+    }
+
+    #[tokio::test]
+    async fn downloads_each_layer_once() {
+        let server = mock::serve(|request| match request.path.as_str() {
+            "/products/radar_transparencies/IDR713.background.png" => Reply::ok(png([200, 220, 255, 255])),
+            "/products/radar_transparencies/IDR713.range.png" => Reply::ok(png([0, 0, 0, 0])),
+            _ => Reply::new(404, "")
+        }).await;
+
+        let dir = std::env::temp_dir().join(format!("kindle-radar-layers-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let cache = LayerCache::new(&dir, &server.url);
+
+        let stack = cache.stack("IDR713", &["background", "topography", "range"]).await;
+        assert_eq!(stack.get_pixel(100, 100), &Rgba([200, 220, 255, 255]));
+        assert!(dir.join("IDR713.background.png").exists());
+        assert!(!dir.join("IDR713.topography.png").exists());
+
+        cache.stack("IDR713", &["background", "range"]).await;
+        let fetched: Vec<String> = server.requests().into_iter().map(|request| request.path).collect();
+        assert_eq!(fetched.len(), 3);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
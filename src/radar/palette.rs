@@ -0,0 +1,147 @@
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+/*
+    The BOM colours rain by how heavy it is, in 15 colours which mostly turn into the same mid
+    greys on the kindle. They are put into 5 bands instead, each its own grey (or a hatch, between
+    the two darkest), lightest for the lightest rain. The map under the rain is washed out so even
+    the lightest band stands out against it, and everything over it is drawn in black.
+*/
+
+// The BOM's palette with the rain rate (mm/h) each colour starts at
+const PALETTE: [([u8; 3], f64); 15] = [
+    ([245, 245, 255], 0.2),
+    ([180, 180, 255], 0.5),
+    ([120, 120, 255], 1.5),
+    ([20, 20, 255], 2.5),
+    ([0, 216, 195], 4.0),
+    ([0, 150, 144], 6.0),
+    ([0, 102, 102], 10.0),
+    ([255, 255, 0], 15.0),
+    ([255, 200, 0], 20.0),
+    ([255, 150, 0], 35.0),
+    ([255, 100, 0], 50.0),
+    ([255, 0, 0], 80.0),
+    ([200, 0, 0], 120.0),
+    ([120, 0, 0], 200.0),
+    ([40, 0, 0], 300.0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shade {
+    Grey(u8),
+    Hatch,
+}
+
+pub struct Band {
+    /* The lowest rain rate in the band, mm/h */
+    pub from: f64,
+    pub label: &'static str,
+    pub shade: Shade,
+}
+
+pub const BANDS: [Band; 5] = [
+    Band { from: 0.2, label: "<1.5", shade: Shade::Grey(200) },
+    Band { from: 1.5, label: "1.5-4", shade: Shade::Grey(150) },
+    Band { from: 4.0, label: "4-15", shade: Shade::Grey(100) },
+    Band { from: 15.0, label: "15-50", shade: Shade::Hatch },
+    Band { from: 50.0, label: "50+", shade: Shade::Grey(0) },
+];
+
+// Close enough to a palette colour, for edges that have been blended
+const TOLERANCE: i32 = 24 * 24;
+
+pub fn rain_rate(colour: [u8; 3]) -> Option<f64> {
+    let distance = |palette: [u8; 3]| (0..3).map(|i| (colour[i] as i32 - palette[i] as i32).pow(2)).sum::<i32>();
+
+    PALETTE.iter()
+        .map(|(palette, rate)| (distance(*palette), *rate))
+        .min_by_key(|(distance, _)| *distance)
+        .filter(|(distance, _)| *distance <= TOLERANCE)
+        .map(|(_, rate)| rate)
+}
+
+pub fn band(rate: f64) -> &'static Band {
+    BANDS.iter().rev().find(|band| rate >= band.from).unwrap_or(&BANDS[0])
+}
+
+fn shade(shade: Shade, x: u32, y: u32) -> u8 {
+    match shade {
+        Shade::Grey(grey) => grey,
+        // Diagonal black lines on a mid grey
+        Shade::Hatch => if (x + y) % 4 < 2 {0} else {140}
+    }
+}
+
+pub fn rain(image: &DynamicImage) -> RgbaImage {
+    // Anything that is not one of the palette's colours is left out
+    let mut rain = RgbaImage::new(image.width(), image.height());
+    for (x, y, Rgba([r, g, b, a])) in image.pixels() {
+        if a == 0 {
+            continue
+        }
+        if let Some(rate) = rain_rate([r, g, b]) {
+            let grey = shade(band(rate).shade, x, y);
+            rain.put_pixel(x, y, Rgba([grey, grey, grey, 255]));
+        }
+    }
+    rain
+}
+
+fn luma(Rgba([r, g, b, _]): Rgba<u8>) -> f64 {
+    0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+}
+
+pub fn wash_out(map: &mut RgbaImage) {
+    // Dark lines (coasts, roads) go black, everything else into the lightest few greys
+    for pixel in map.pixels_mut() {
+        let luma = luma(*pixel);
+        let grey = if luma < 110.0 {0} else {(230.0 + (luma - 110.0) / 145.0 * 25.0).min(255.0) as u8};
+        *pixel = Rgba([grey, grey, grey, pixel.0[3]]);
+    }
+}
+
+pub fn blacken(overlay: &mut RgbaImage) {
+    // Range rings and names, thin lines that only need to show up
+    for pixel in overlay.pixels_mut().filter(|pixel| pixel.0[3] > 0) {
+        *pixel = Rgba([0, 0, 0, pixel.0[3]]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_the_palette_to_bands() {
+        let labels: Vec<&str> = PALETTE.iter().map(|(colour, _)| band(rain_rate(*colour).unwrap()).label).collect();
+        assert_eq!(labels, [
+            "<1.5", "<1.5", "1.5-4", "1.5-4", "4-15", "4-15", "4-15",
+            "15-50", "15-50", "15-50", "50+", "50+", "50+", "50+", "50+"
+        ]);
+
+        // A slightly blended yellow is still yellow, map colours are not rain
+        assert_eq!(rain_rate([250, 250, 10]), Some(15.0));
+        assert_eq!(rain_rate([120, 180, 90]), None);
+    }
+
+    #[test]
+    fn redraws_rain_in_greys() {
+        let mut image = RgbaImage::new(4, 1);
+        image.put_pixel(0, 0, Rgba([180, 180, 255, 255]));
+        image.put_pixel(1, 0, Rgba([0, 150, 144, 255]));
+        image.put_pixel(2, 0, Rgba([255, 200, 0, 255]));
+        image.put_pixel(3, 0, Rgba([120, 180, 90, 255]));
+
+        let rain = rain(&DynamicImage::ImageRgba8(image));
+        let greys: Vec<[u8; 2]> = rain.pixels().map(|pixel| [pixel.0[0], pixel.0[3]]).collect();
+        assert_eq!(greys, [[200, 255], [100, 255], [140, 255], [0, 0]]);
+    }
+
+    #[test]
+    fn keeps_the_map_lighter_than_the_rain() {
+        let mut map = RgbaImage::from_fn(3, 1, |x, _| [Rgba([20, 20, 20, 255]), Rgba([180, 200, 230, 255]), Rgba([255, 255, 255, 255])][x as usize]);
+        wash_out(&mut map);
+        assert_eq!(map.pixels().map(|pixel| pixel.0[0]).collect::<Vec<_>>(), [0, 245, 255]);
+        assert!(map.get_pixel(1, 0).0[0] > 200);
+    }
+}
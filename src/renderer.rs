@@ -1,26 +1,40 @@
+mod icons;
+mod widgets;
+
 
 use crate::calendar;
+use crate::environment;
 use crate::stats;
 use crate::weather;
 use crate::news;
 use crate::radar;
+use crate::warnings;
+use crate::status;
+use crate::metrics;
+use crate::scheduler::{self, Scheduler, when_due};
+use widgets::{ChartConfig, MonthConfig, PanelEntry};
 
-use crate::calendar::CalendarEvent;
+use crate::calendar::{Agenda, AgendaDay, CalendarEvent, CalendarState, Countdown};
 use crate::stats::Stats;
 use crate::weather::DayData;
 
-use resvg;
 use tiny_skia::{Transform, PixmapMut, BYTES_PER_PIXEL};
 use usvg::Tree;
 use image::{ImageBuffer, Rgba, DynamicImage};
 
 use std::process::Command;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use serde::Deserialize;
+use serde_json::from_reader;
 
 use base64::prelude::*;
 use std::io::Cursor;
 use regex::Regex;
 
-use chrono::{DateTime, Duration, Timelike, Utc};
+use chrono::{DateTime, Datelike, Duration, Local, Months, NaiveDateTime, Timelike, Utc};
 use std::time::Instant;
 
 use log::{info, warn};
@@ -29,60 +43,142 @@ use futures::join;
 use async_std::future;
 use std::time::Duration as stdDuration;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct KindleDisplayData {
     short_stats: Option<stats::Stats>,
-    weather: Option<Vec<weather::DayData>>,
+    weather: Option<weather::Forecast>,
+    conditions: Option<weather::CurrentWeather>,
+    warnings: Option<Vec<warnings::Warning>>,
+    environment: Option<environment::Environment>,
     news: Option<Vec<String>>,
-    calendar_event: Option<Option<calendar::CalendarEvent>>,
-    image: Option<DynamicImage>
+    calendar: Option<calendar::Agenda>,
+    radar: Option<Vec<radar::Frame>>
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct DisplayConfig {
+    /* Redraw (clearing the screen) at least this often even if nothing changed, to clear ghosting */
+    full_refresh_minutes: Option<u64>,
+
+    /* What goes in the large panel on the right, just the news if empty */
+    #[serde(default)]
+    panel: Vec<PanelEntry>,
+
+    /* Settings for the month widget */
+    #[serde(default)]
+    month: MonthConfig,
+
+    /* Settings for the hourly chart widget */
+    #[serde(default)]
+    chart: ChartConfig,
+
+    /* Icons for OWM condition codes in place of the usual ones, see icons.rs */
+    #[serde(default)]
+    weather_icons: HashMap<String, String>,
+}
+
+fn read_display_config() -> DisplayConfig {
+    let file = match File::open("sensitive/display.json") {
+        Ok(file) => file,
+        Err(_) => return DisplayConfig::default()
+    };
+
+    match from_reader(file) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Could not parse sensitive/display.json: {e}");
+            DisplayConfig::default()
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Dashboard {
+    /* Kept between cycles, so that sources that are not due can be shown from the last fetch */
+    data: KindleDisplayData,
+    pub scheduler: Scheduler,
+    last_shown: Option<String>,
+    night: bool,
+
+    /* What eips last showed, and when */
+    last_frame: Option<u64>,
+    last_update: Option<Instant>,
+}
+
+impl Dashboard {
+    pub fn new() -> Dashboard {
+        Dashboard { scheduler: Scheduler::new(), ..Default::default() }
+    }
+
+    pub fn force_refresh(&mut self) {
+        self.scheduler.force();
+        self.last_shown = None;
+        self.last_frame = None;
+    }
+
+    fn full_refresh_due(&self, full_refresh_minutes: Option<u64>) -> bool {
+        match (full_refresh_minutes, self.last_update) {
+            (Some(minutes), Some(last_update)) => last_update.elapsed() >= stdDuration::from_secs(minutes * 60),
+            _ => false
+        }
+    }
+
+    fn template_unchanged(&self, template: &str, full_refresh_minutes: Option<u64>) -> bool {
+        self.last_shown.as_deref() == Some(template) && !self.full_refresh_due(full_refresh_minutes)
+    }
+
+    fn frame_unchanged(&self, frame: u64, full_refresh_minutes: Option<u64>) -> bool {
+        self.last_frame == Some(frame) && !self.full_refresh_due(full_refresh_minutes)
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.scheduler.is_quiet(scheduler::now())
+    }
 }
 
-async fn build_all_data() -> KindleDisplayData {
-    info!("Fetching all data...");
+async fn build_all_data(data: &mut KindleDisplayData, scheduler: &mut Scheduler) {
+    info!("Fetching due data...");
     let now = Instant::now();
+    let at = scheduler::now();
 
     let timeout = stdDuration::from_secs(30);
 
-    let short_stats = future::timeout(timeout, stats::fetch_stats());
-    let weather = future::timeout(timeout, weather::fetch_weather());
-    let news = future::timeout(timeout, news::fetch_news());
-    let calendar_event = future::timeout(timeout, calendar::fetch_event());
-    let image = future::timeout(timeout, radar::fetch_radar());
+    let weather = when_due(scheduler.is_due("weather", at), status::timed(future::timeout(timeout, weather::fetch_weather())));
+    let conditions = when_due(scheduler.is_due("conditions", at), status::timed(future::timeout(timeout, weather::fetch_current())));
+    let environment = when_due(scheduler.is_due("environment", at), status::timed(future::timeout(timeout, environment::fetch_environment())));
+    let news = when_due(scheduler.is_due("news", at), status::timed(future::timeout(timeout, news::fetch_news())));
+    let calendar = when_due(scheduler.is_due("calendar", at), status::timed(future::timeout(timeout, calendar::fetch_agenda())));
+    let radar = when_due(scheduler.is_due("radar", at), status::timed(future::timeout(timeout, radar::fetch_radar())));
+    let warnings = when_due(scheduler.is_due("warnings", at), status::timed(future::timeout(timeout, warnings::fetch_warnings())));
+
+    // Statistics have their own timeouts and intervals
+    let short_stats = stats::fetch_stats(data.short_stats.take(), scheduler);
 
     let (
         short_stats, 
         weather, 
+        conditions,
+        environment,
         news, 
-        calendar_event, 
-        image
-    )  = join!(short_stats, weather, news, calendar_event, image);
+        calendar, 
+        radar,
+        warnings
+    )  = join!(short_stats, weather, conditions, environment, news, calendar, radar, warnings);
 
     
     let elapsed = format!("{:.2?}", now.elapsed());
-    info!("Fetched all kindle data in {elapsed}");
-
-    // Checking timeout messages
-    let short_stats = match short_stats {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-    let weather = match weather {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-    let news = match news {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-    let calendar_event = match calendar_event {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-    let image = match image {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-
-    // Warning on error
-    match &short_stats {Ok(_) => {}, Err(e) => warn!("Short stats failed: {e}")}
-    match &weather {Ok(_) => {}, Err(e) => warn!("Weather failed: {e}")}
-    match &news {Ok(_) => {}, Err(e) => warn!("News failed: {e}")}
-    match &calendar_event {Ok(_) => {}, Err(e) => warn!("Calendar failed: {e}")}
-    match &image {Ok(_) => {}, Err(e) => warn!("Radar failed: {e}")}
-
-    KindleDisplayData {
-        short_stats: short_stats.ok(),
-        weather: weather.ok(),
-        news: news.ok(),
-        calendar_event: calendar_event.ok(),
-        image: image.ok()
-    }
+    info!("Fetched all due kindle data in {elapsed}");
+
+    data.short_stats = Some(short_stats);
+    if let Some(weather) = scheduler.settle("weather", at, weather) {data.weather = weather}
+    if let Some(conditions) = scheduler.settle("conditions", at, conditions) {data.conditions = conditions}
+    if let Some(environment) = scheduler.settle("environment", at, environment) {data.environment = environment}
+    if let Some(news) = scheduler.settle("news", at, news) {data.news = news}
+    if let Some(calendar) = scheduler.settle("calendar", at, calendar) {data.calendar = calendar}
+    if let Some(radar) = scheduler.settle("radar", at, radar) {data.radar = radar}
+    if let Some(warnings) = scheduler.settle("warnings", at, warnings) {data.warnings = warnings}
+
+    scheduler.set_warning(data.warnings.as_ref().is_some_and(|warnings| !warnings.is_empty()));
 }
 
 async fn _build_some_data() -> KindleDisplayData {
@@ -101,7 +197,22 @@ async fn _build_some_data() -> KindleDisplayData {
 
     let calendar_event = CalendarEvent {
         start_time: Utc::now() + Duration::days(1),
-        name: "ASSESSMENT 3 (Part G) - Oral Defense (Points - 25), DUE DATE: Starting from Monday, May 27, 2024".to_string()
+        end_time: Utc::now() + Duration::days(1) + Duration::hours(1),
+        name: "ASSESSMENT 3 (Part G) - Oral Defense (Points - 25), DUE DATE: Starting from Monday, May 27, 2024".to_string(),
+        location: Some("Building 11, Room 4".to_string()),
+        all_day: false,
+        calendar: Some("Uni".to_string()),
+        marker: Some("hatch".to_string()),
+        alarm: None
+    };
+
+    let date = calendar_event.start_time.with_timezone(&Local).date_naive();
+    let calendar = Agenda {
+        busy_days: [(date, vec![calendar_event.marker.clone()])].into(),
+        countdowns: vec![Countdown { name: "Mum's birthday".to_string(), date: date + Duration::days(12) }],
+        days: vec![AgendaDay { date, events: vec![calendar_event] }],
+        failed: vec![],
+        reminders: vec![]
     };
     
     let short_stats = Stats {
@@ -119,39 +230,78 @@ async fn _build_some_data() -> KindleDisplayData {
             data_points: 10,
             date: 10,
             day: "FRI".to_string(),
-            rain_sum: 1.0,
-            cloud_sum: 1.0,
+            precipitation: 1.0,
+            cloud_cover: 0.1,
             max_c: 10.0,
             min_c: 20.0,
+            ..Default::default()
         },
         DayData {
             data_points: 10,
             date: 11,
             day: "SAT".to_string(),
-            rain_sum: 10.0,
-            cloud_sum: 10.0,
+            precipitation: 10.0,
+            cloud_cover: 1.0,
             max_c: 10.0,
             min_c: 20.0,
+            ..Default::default()
         },
         DayData {
             data_points: 10,
             date: 12,
             day: "SUN".to_string(),
-            rain_sum: 100.0,
-            cloud_sum: 10.0,
+            precipitation: 100.0,
+            cloud_cover: 1.0,
             max_c: 10.0,
             min_c: 20.0,
+            ..Default::default()
         }
     ];
     
+    let hours = (0..16).map(|i| weather::HourData {
+        time: (Local::now() + Duration::hours(3 * i - 2)).fixed_offset(),
+        temp_c: 14.0 + 6.0 * (i as f64 * 0.8).sin(),
+        precipitation: [0.0, 0.0, 0.4, 2.5, 1.2, 0.0, 0.0, 0.0][i as usize % 8]
+    }).collect();
+
+    let conditions = weather::CurrentWeather {
+        temp_c: 13.4,
+        feels_like_c: 12.8,
+        humidity: 77.0,
+        pressure_hpa: Some(1021.0),
+        wind_speed: 4.6,
+        wind_deg: Some(225.0),
+        condition: Some(803),
+        night: false,
+        sunrise: (Local::now() - Duration::hours(4)).fixed_offset(),
+        sunset: (Local::now() + Duration::hours(6)).fixed_offset()
+    };
+
+    let environment = environment::Environment {
+        uv: Some(environment::Reading { value: 3.4, category: "Moderate", detail: None }),
+        air_quality: Some(environment::Reading { value: 2.0, category: "Fair", detail: Some("PM2.5 6".to_string()) }),
+        pollen: None,
+    };
+
+    let warnings = vec![warnings::Warning {
+        title: "Severe Thunderstorm Warning for Sydney Metropolitan and Illawarra".to_string(),
+        area: Some("Sydney Metropolitan, Illawarra".to_string()),
+        severity: warnings::Severity::Severe,
+        issued: Some(Utc::now() - Duration::minutes(20)),
+        expires: Some(Utc::now() + Duration::hours(3)),
+    }];
+
     // let image = radar::fetch_radar().await.unwrap(); // too slow for testing
 
     KindleDisplayData {
         short_stats: Some(short_stats),
-        weather: Some(weather),
+        weather: Some(weather::Forecast { days: weather, hours }),
+        conditions: Some(conditions),
+        warnings: Some(warnings),
+        environment: Some(environment),
         news: Some(news),
-        calendar_event: Some(Some(calendar_event)),
-        image: None
+        calendar: Some(calendar),
+        radar: None
     }
 }
 
@@ -162,7 +312,7 @@ fn generate_svg_text(text: Vec<String>, max_lines:usize, max_width: f64, x:i32,
     let mut current_lines = 0;
 
     let line_height = font_size as f64 * line_height;
-    let mut y_new = y.clone() as f64;
+    let mut y_new = y as f64;
 
     for news in text {
 
@@ -179,9 +329,9 @@ fn generate_svg_text(text: Vec<String>, max_lines:usize, max_width: f64, x:i32,
                 r#"<tspan x="{}" y="{}" font-family="FreeSans" font-weight="bold" font-size="{}px">"#,
                 x, y_new, font_size
             ));
-            svg_text.push_str(&line);
+            svg_text.push_str(line);
             svg_text.push_str("</tspan>");
-            current_lines = current_lines + 1;
+            current_lines += 1;
             y_new += line_height;
 
             /* If we are "sitting" on the end but there are more lines to go, then just show ... and return */
@@ -197,52 +347,86 @@ fn generate_svg_text(text: Vec<String>, max_lines:usize, max_width: f64, x:i32,
         }
         
         y_new += line_height;
-        current_lines  = current_lines + 1;
+        current_lines += 1;
 
     }
 
     svg_text
 }
 
-fn time_remaining(target: DateTime<Utc>) -> String {
-    let now = Utc::now();
+fn plural(n: i64, unit: &str) -> String {
+    if n == 1 {format!("1 {unit}")} else {format!("{n} {unit}s")}
+}
+
+fn whole_months(from: NaiveDateTime, to: NaiveDateTime) -> u32 {
+    // Calendar months rather than 30 days (a month from the 31st is the end of the next month)
+    if to <= from {
+        return 0
+    }
+
+    let months = (to.year() - from.year()) * 12 + to.month() as i32 - from.month() as i32;
+    let mut months = months.max(0) as u32;
+    while months > 0 && from.checked_add_months(Months::new(months)).is_none_or(|date| date > to) {
+        months -= 1;
+    }
+    months
+}
+
+fn time_between(now: DateTime<Local>, target: DateTime<Local>) -> String {
     let duration = target - now;
+    let months = whole_months(now.naive_local(), target.naive_local());
 
-    if duration.num_days() >= 365 {
-        let years = duration.num_days() / 365;
-        format!("{} years", years)
-    } else if duration.num_days() >= 30 {
-        let months = duration.num_days() / 30;
-        format!("{} months", months)
+    if months >= 12 {
+        plural(months as i64 / 12, "year")
+    } else if months >= 1 {
+        plural(months as i64, "month")
     } else if duration.num_days() >= 7 {
-        let weeks = duration.num_days() / 7;
-        format!("{} weeks", weeks)
+        plural(duration.num_days() / 7, "week")
     } else if duration.num_hours() >= 24 {
-        let days = duration.num_days();
-        format!("{} days", days)
+        plural(duration.num_days(), "day")
     } else if duration.num_minutes() >= 60 {
-        let hours = duration.num_hours();
-        format!("{} hours", hours)
+        plural(duration.num_hours(), "hour")
     } else if duration.num_seconds() >= 60 {
-        let minutes = duration.num_minutes();
-        format!("{} mins", minutes)
+        plural(duration.num_minutes(), "min")
     } else {
-        let seconds = duration.num_seconds();
-        format!("{} secs", seconds)
+        plural(duration.num_seconds(), "sec")
+    }
+}
+
+fn time_remaining(target: DateTime<Utc>) -> String {
+    time_between(Local::now(), target.with_timezone(&Local))
+}
+
+fn overlay(template: String, svg: &str) -> String {
+    // Drawn last, so over everything in the template
+    match template.rfind("</svg>") {
+        Some(end) => format!("{}{svg}{}", &template[..end], &template[end..]),
+        None => template
     }
 }
 
 fn format_news(template: String, data: &KindleDisplayData) -> String {
-    let new_template = match &data.news {
+    match &data.news {
         Some(news) => {            
             template.replace("#N1", &generate_svg_text(news.clone(), 18, 35.0, 2267, 878, 120, 1.2))
         },
         None => {
             template.replace("#N1", "ERR")
         }
-    };
+    }
+}
 
-    new_template
+fn format_panel(template: String, data: &KindleDisplayData, config: &DisplayConfig) -> String {
+    if config.panel.is_empty() {
+        return format_news(template, data)
+    }
+
+    let widgets: String = std::iter::once(widgets::PATTERNS.to_string())
+        .chain(widgets::layout(&config.panel, widgets::PANEL).into_iter()
+            .filter_map(|(widget, area)| widgets::draw(&widget, data, area, config)))
+        .collect();
+
+    overlay(template.replace("#N1", ""), &widgets)
 }
 
 fn escape_xml(s: &str) -> String {
@@ -255,32 +439,100 @@ fn escape_xml(s: &str) -> String {
 
 fn format_calendar(template: String, data: &KindleDisplayData) -> String {
     let mut template = template.clone();
-    match &data.calendar_event {
-        Some(possible_calendar_event) => {
-            match possible_calendar_event {
-                Some(calendar_event) => {
-                    let name = escape_xml(&calendar_event.name);
-                    let time = calendar_event.start_time.clone();
-                    let remaining = escape_xml(&time_remaining(time));
-
-                    template = template.replace("#G2", &format!("in {remaining}"));
-                    template = template.replace("#G1", &generate_svg_text(vec![name], 5, 33.0, 3080, 180, 100, 1.2))
-                }
-                None => {
-                    template = template.replace("#G2", "");
-                    template = template.replace("#G1", "No upcoming events");
-                }
-            }
+
+    let (name, when) = match &data.calendar {
+        Some(agenda) => match agenda.state(Utc::now()) {
+            CalendarState::InProgress(event) => (Some(&event.name), format!("ends in {}", time_remaining(event.end_time))),
+            CalendarState::NextUp(event) => (Some(&event.name), format!("in {}", time_remaining(event.start_time))),
+            CalendarState::FreeToday(Some(event)) => {
+                // An all day event tomorrow starts at midnight, not in however many hours that is
+                let tomorrow = Local::now().date_naive().succ_opt();
+                let when = if event.all_day && Some(event.start_time.with_timezone(&Local).date_naive()) == tomorrow {
+                    "tomorrow".to_string()
+                } else {
+                    format!("in {}", time_remaining(event.start_time))
+                };
+                (Some(&event.name), format!("free today · {when}"))
+            },
+            CalendarState::FreeToday(None) => (None, "".to_string())
         },
-        None => {
-            template = template.replace("#G2", "ERR!");
-            template = template.replace("#G1", "Could not fetch any events");
-        }
+        None => (None, "ERR!".to_string())
     };
 
+    template = template.replace("#G2", &escape_xml(&when));
+    template = template.replace("#G1", &match (name, &data.calendar) {
+        (Some(name), _) => generate_svg_text(vec![escape_xml(name)], 5, 33.0, 3080, 180, 100, 1.2),
+        (None, Some(_)) => "No upcoming events".to_string(),
+        (None, None) => "Could not fetch any events".to_string()
+    });
+
     template
 }
 
+fn format_reminder(template: String, data: &KindleDisplayData) -> String {
+    // A banner across the middle of the screen while an event's reminder is due
+    let now = Utc::now();
+    let event = match data.calendar.as_ref().and_then(|agenda| agenda.reminder(now)) {
+        Some(event) => event,
+        None => return template
+    };
+
+    let start = event.start_time.with_timezone(&Local);
+    let when = if event.all_day {
+        format!("All day {}", start.format("%a %-d %b"))
+    } else {
+        format!("{} · in {}", start.format("%H:%M"), time_remaining(event.start_time))
+    };
+
+    let banner = format!(
+        r#"<rect x="40" y="300" width="1120" height="270" rx="12" fill="black" />{}{}{}"#,
+        widgets::aligned(600.0, 365.0, 36.0, false, "white", "middle", "Reminder"),
+        widgets::aligned(600.0, 450.0, 64.0, true, "white", "middle", &widgets::fit(&event.name, 1060.0, 64.0)),
+        widgets::aligned(600.0, 530.0, 44.0, false, "white", "middle", &when)
+    );
+
+    overlay(template, &banner)
+}
+
+fn warning_height(title: &str) -> f64 {
+    // Taller when the title takes two lines
+    if textwrap::wrap(title, 34).len() > 1 {120.0} else {90.0}
+}
+
+fn format_warnings(template: String, data: &KindleDisplayData) -> String {
+    // A banner over the top of the radar while the BOM has warnings out, the most severe first
+    let warnings = match data.warnings.as_deref() {
+        Some([first, rest @ ..]) => (first, rest.len()),
+        _ => return template
+    };
+    let (warning, more) = warnings;
+
+    let mut details = vec![];
+    if let Some(expires) = warning.expires {
+        details.push(format!("Until {}", expires.with_timezone(&Local).format("%a %H:%M")));
+    }
+    if more > 0 {
+        details.push(format!("+{more} more"));
+    }
+
+    // The title on up to two lines, as warnings' titles run long
+    let lines = textwrap::wrap(&warning.title, 34);
+    let title: String = lines.iter().take(2).enumerate().map(|(i, line)| {
+        let line = if i == 1 && lines.len() > 2 {widgets::fit(&lines[1..].join(" "), 480.0, 26.0)} else {line.to_string()};
+        widgets::text(100.0, 196.0 + 30.0 * i as f64, 26.0, true, "white", &line)
+    }).collect();
+
+    let banner = format!(
+        r#"<rect x="10" y="130" width="580" height="{}" rx="8" fill="black" />{}{}{title}{}"#,
+        warning_height(&warning.title),
+        r#"<path d="M52 146L84 202H20Z" fill="white" /><path d="M52 158V184M52 190V196" stroke="black" stroke-width="6" />"#,
+        widgets::text(100.0, 164.0, 22.0, false, "white", &widgets::fit(warning.area.as_deref().unwrap_or("Weather warning"), 300.0, 22.0)),
+        widgets::aligned(578.0, 164.0, 20.0, true, "white", "end", &details.join(" · "))
+    );
+
+    overlay(template, &banner)
+}
+
 fn format_stats(template: String, data: &KindleDisplayData) -> String {
     let mut template = template.clone();
     match &data.short_stats {
@@ -327,7 +579,7 @@ fn format_stats(template: String, data: &KindleDisplayData) -> String {
         }
     };
 
-    return template;
+    template
 }
 
 fn format_time(template: String, _data: &KindleDisplayData) -> String {
@@ -341,51 +593,47 @@ fn format_time(template: String, _data: &KindleDisplayData) -> String {
 
     template = template.replace("#1", &format!("{:0>2}", hour));
     template = template.replace("#2", &format!("{:0>2}", minute));
-    return template;
+    template
 }
 
-fn weather_to_icon(day: &DayData) -> String {
-    let icon1 = include_str!("icons/1.svg").to_string();
-    let icon2 = include_str!("icons/2.svg").to_string();
-    let icon3 = include_str!("icons/3.svg").to_string();
-    let icon4 = include_str!("icons/4.svg").to_string();
-    let icon5 = include_str!("icons/5.svg").to_string();
-    let icon6 = include_str!("icons/6.svg").to_string();
-    let icon7 = include_str!("icons/7.svg").to_string();
-    let icon8 = include_str!("icons/8.svg").to_string();
-
-    let avg_rain = day.rain_sum / day.data_points as f64;
-    let avg_cloud = day.cloud_sum / day.data_points as f64;    
+fn weather_to_icon(day: &DayData, overrides: &HashMap<String, String>) -> String {
+    if let Some(code) = day.condition {
+        // A day's forecast is for the daytime
+        return icons::for_condition(code, false, overrides).to_string()
+    }
 
-    let mut result = icon1;
+    // Forecasts without condition codes go by how much rain and cloud there is
+    let avg_rain = day.precipitation / day.data_points.max(1) as f64;
+    let avg_cloud = day.cloud_cover;
 
-    if avg_cloud > 20.0 {result = icon2} 
-    if avg_cloud > 50.0 {result = icon3}
-    if avg_cloud > 80.0 {result = icon4}
+    let mut result = "sun";
 
-    if avg_rain > 0.1 {result = icon5}
-    if avg_rain > 0.5 {result = icon6} 
-    if avg_rain > 1.0 {result = icon7} 
-    if avg_rain > 5.0 {result = icon8}
+    if avg_cloud > 20.0 {result = "cloud-sun"}
+    if avg_cloud > 50.0 {result = "cloud"}
+    if avg_cloud > 80.0 {result = "clouds"}
 
-    result
+    if avg_rain > 0.1 {result = "cloud-fog"}
+    if avg_rain > 0.5 {result = "cloud-rain"}
+    if avg_rain > 1.0 {result = "cloud-rain-heavy"}
+    if avg_rain > 5.0 {result = "cloud-lightning-rain"}
 
+    icons::svg(result).unwrap_or_default().to_string()
 }
 
-fn format_weather(template: String, data: &KindleDisplayData) -> String {
+fn format_weather(template: String, data: &KindleDisplayData, config: &DisplayConfig) -> String {
     let mut template = template.clone();
 
-    match &data.weather {
+    match data.weather.as_ref().map(|forecast| &forecast.days) {
         Some(weather) => {
             
             // Trust me, I'm not happy with this code either
 
-            template = match weather.get(0) {
+            template = match weather.first() {
                 Some(day) => {
                     template = template.replace("#D1", &format!("{:0>2} {}", day.date, day.day));
                     template = template.replace("#T1", &format!("{:.1}", day.max_c));
                     template = template.replace("#T2", &format!("{:.1}", day.min_c));
-                    template = template.replace("<path\n         id=\"WEATHERICON1\" />", &weather_to_icon(day));
+                    template = template.replace("<path\n         id=\"WEATHERICON1\" />", &weather_to_icon(day, &config.weather_icons));
                     template
                 },
                 None => {
@@ -401,7 +649,7 @@ fn format_weather(template: String, data: &KindleDisplayData) -> String {
                     template = template.replace("#D2", &format!("{:0>2} {}", day.date, day.day));
                     template = template.replace("#T3", &format!("{:.1}", day.max_c));
                     template = template.replace("#T4", &format!("{:.1}", day.min_c));
-                    template = template.replace("<path\n         id=\"WEATHERICON2\" />", &weather_to_icon(day));
+                    template = template.replace("<path\n         id=\"WEATHERICON2\" />", &weather_to_icon(day, &config.weather_icons));
                     template
                 },
                 None => {
@@ -417,7 +665,7 @@ fn format_weather(template: String, data: &KindleDisplayData) -> String {
                     template = template.replace("#D3", &format!("{:0>2} {}", day.date, day.day));
                     template = template.replace("#T5", &format!("{:.1}", day.max_c));
                     template = template.replace("#T6", &format!("{:.1}", day.min_c));
-                    template = template.replace("<path\n         id=\"WEATHERICON3\" />", &weather_to_icon(day));
+                    template = template.replace("<path\n         id=\"WEATHERICON3\" />", &weather_to_icon(day, &config.weather_icons));
                     template
                 },
                 None => {
@@ -442,31 +690,141 @@ fn format_weather(template: String, data: &KindleDisplayData) -> String {
         }
     };
 
-    return template;
+    template
+}
+
+fn format_conditions(template: String, data: &KindleDisplayData, config: &DisplayConfig) -> String {
+    // The strip along the bottom of the radar, from OWM's current weather
+    let now = match &data.conditions {
+        Some(now) => now,
+        None => return template.replace("<path\n       id=\"CONDITIONS\" />", &widgets::aligned(300.0, 676.0, 26.0, false, "#555555", "middle", "No current conditions"))
+    };
+
+    let icon = match now.condition {
+        Some(code) => icons::for_condition(code, now.night, &config.weather_icons),
+        None => icons::svg(if now.night {"moon"} else {"sun"}).unwrap_or_default()
+    };
+
+    // Points the way the wind is blowing, ie away from where it comes from
+    let arrow = match now.wind_deg {
+        Some(deg) => format!(
+            r#"<polygon points="0,-17 10,12 0,6 -10,12" transform="translate(380,667) rotate({:.0})" fill="black" />"#,
+            deg + 180.0
+        ),
+        None => String::new()
+    };
+
+    let conditions = format!(
+        r#"<g transform="translate(20,631) scale(4.5)" style="fill:currentColor">{icon}</g>{}{}{}{}{arrow}{}{}{}{}"#,
+        widgets::text(106.0, 668.0, 44.0, true, "black", &format!("{:.1}°", now.temp_c)),
+        widgets::text(108.0, 700.0, 20.0, false, "#555555", &format!("Feels {:.1}°", now.feels_like_c)),
+        widgets::text(234.0, 660.0, 20.0, false, "black", &format!("Humidity {:.0}%", now.humidity)),
+        widgets::text(234.0, 692.0, 20.0, false, "black", &now.pressure_hpa.map_or(String::new(), |pressure| format!("{pressure:.0} hPa"))),
+        widgets::text(400.0, 660.0, 20.0, true, "black", &format!("{:.0} km/h", now.wind_speed * 3.6)),
+        widgets::text(400.0, 692.0, 20.0, false, "black", now.wind_deg.map_or("", weather::compass)),
+        widgets::aligned(578.0, 660.0, 20.0, false, "black", "end", &format!("Rise {}", now.sunrise.format("%H:%M"))),
+        widgets::aligned(578.0, 692.0, 20.0, false, "black", "end", &format!("Set {}", now.sunset.format("%H:%M")))
+    );
+
+    template.replace("<path\n       id=\"CONDITIONS\" />", &conditions)
+}
+
+fn encode_png(image: &DynamicImage) -> Option<String> {
+    let mut buffer = Cursor::new(Vec::new());
+
+    match image.write_to(&mut buffer, image::ImageFormat::Png) {
+        Ok(_r) => Some(BASE64_STANDARD.encode(buffer.get_ref())),
+        Err(e) => {
+            warn!("Could not write to buffer: {e}");
+            None
+        }
+    }
+}
+
+const RADAR_THUMB: f64 = 120.0;
+// As many as fit beside the newest frame's time
+const RADAR_STRIP: usize = 3;
+// Where the newest frame is in template.svg, below the conditions hide the rest of it
+const RADAR_TOP: f64 = 130.0;
+const RADAR_BOTTOM: f64 = 612.0;
+const RADAR_SIZE: f64 = 590.0;
+
+fn radar_legend(x: f64, bottom: f64) -> String {
+    // The rain's greys (see radar/palette.rs) in mm/h, heaviest at the top
+    let row = 17.0;
+    let top = bottom - row * (radar::palette::BANDS.len() + 1) as f64 - 8.0;
+
+    let mut legend = format!(
+        r#"<defs><pattern id="radar-hatch" width="4" height="4" patternUnits="userSpaceOnUse" patternTransform="rotate(45)"><rect width="4" height="4" fill="rgb(140,140,140)" /><rect width="2" height="4" fill="black" /></pattern></defs><rect x="{x:.1}" y="{top:.1}" width="70" height="{:.1}" fill="white" stroke="black" stroke-width="1.5" />{}"#,
+        bottom - top, widgets::aligned(x + 35.0, top + row, 14.0, true, "black", "middle", "mm/h")
+    );
+
+    for (i, band) in radar::palette::BANDS.iter().rev().enumerate() {
+        let y = top + row * (i + 1) as f64 + 4.0;
+        let fill = match band.shade {
+            radar::palette::Shade::Grey(grey) => format!("rgb({grey},{grey},{grey})"),
+            radar::palette::Shade::Hatch => "url(#radar-hatch)".to_string()
+        };
+        legend.push_str(&format!(r#"<rect x="{:.1}" y="{y:.1}" width="14" height="14" fill="{fill}" stroke="black" stroke-width="1" />"#, x + 5.0));
+        legend.push_str(&widgets::text(x + 24.0, y + 12.0, 13.0, false, "black", band.label));
+    }
+
+    legend
 }
 
 fn format_radar(template: String, data: &KindleDisplayData) -> String {
-    let mut template = template.clone();
-    match &data.image {
-        Some(image) => {
-            
-            let mut buffer = Cursor::new(Vec::new());
-            
-            let r = image.write_to(&mut buffer, image::ImageFormat::Png);
-            match r {
-                Ok(_r) => {
-                    let encoded_image = BASE64_STANDARD.encode(buffer.get_ref());
-                    template = template.replace("BASE64RADAR", &encoded_image);
-                },
-                Err(e) => {
-                    warn!("Could not write to buffer: {e}")
-                }
-            }
+    // The newest frame fills the radar, with the ones before it in a strip along the bottom (oldest first)
+    let (earlier, latest) = match data.radar.as_deref() {
+        Some([earlier @ .., latest]) => (earlier, latest),
+        _ => return template
+    };
+
+    let template = match encode_png(&latest.image) {
+        Some(encoded_image) => template.replace("BASE64RADAR", &encoded_image),
+        None => return template
+    };
+
+    // Along the bottom unless that would hide the marker, then along the top (below any warning's banner)
+    let height = RADAR_THUMB + 24.0;
+    let scale = RADAR_SIZE / latest.image.height().max(1) as f64;
+    let bottom = match latest.marker {
+        Some((_, y)) if RADAR_TOP + (y + 11) as f64 * scale >= RADAR_BOTTOM - height => {
+            let warning = data.warnings.as_deref().and_then(|warnings| warnings.first()).map_or(0.0, |warning| warning_height(&warning.title));
+            RADAR_TOP + warning + 6.0 + height
         },
-        None => {}
+        _ => RADAR_BOTTOM
     };
 
-    return template;
+    let time = |frame: &radar::Frame| frame.time.map(|time| time.with_timezone(&Local).format("%H:%M").to_string());
+    let label_size = 18.0;
+
+    let mut strip = String::new();
+    if let Some(time) = time(latest) {
+        strip.push_str(&format!(r#"<rect x="512" y="{:.1}" width="70" height="24" fill="white" stroke="black" stroke-width="1.5" />"#, bottom - 24.0));
+        strip.push_str(&widgets::aligned(547.0, bottom - 6.0, label_size, true, "black", "middle", &time));
+    }
+
+    let top = bottom - height;
+    for (i, frame) in earlier[earlier.len().saturating_sub(RADAR_STRIP)..].iter().enumerate() {
+        let x = 18.0 + i as f64 * (RADAR_THUMB + 8.0);
+        let thumb = frame.image.resize_exact(RADAR_THUMB as u32 * 2, RADAR_THUMB as u32 * 2, image::imageops::FilterType::Triangle);
+        let encoded = match encode_png(&thumb) {
+            Some(encoded) => encoded,
+            None => continue
+        };
+
+        strip.push_str(&format!(
+            r#"<rect x="{x:.1}" y="{top:.1}" width="{RADAR_THUMB}" height="{:.1}" fill="white" stroke="black" stroke-width="1.5" /><image x="{x:.1}" y="{top:.1}" width="{RADAR_THUMB}" height="{RADAR_THUMB}" preserveAspectRatio="none" xlink:href="data:image/png;base64,{encoded}" />"#,
+            height
+        ));
+        if let Some(time) = time(frame) {
+            strip.push_str(&widgets::aligned(x + RADAR_THUMB / 2.0, bottom - 6.0, label_size, false, "black", "middle", &time));
+        }
+    }
+
+    strip.push_str(&radar_legend(512.0, bottom - 30.0));
+
+    overlay(template, &strip)
 }
 
 struct Screen {
@@ -495,8 +853,8 @@ fn get_screen_dim() -> Option<Screen> {
                         match height {
                             Ok(height) =>{
                                 Some(Screen {
-                                    width: width,
-                                    height: height
+                                    width,
+                                    height
                                 })
                             },
                             Err(e) => {
@@ -523,28 +881,38 @@ fn get_screen_dim() -> Option<Screen> {
     }
 }
 
-async fn create_output_svg() -> String {
+fn create_output_svg(data: &KindleDisplayData, config: &DisplayConfig) -> String {
     let mut template = include_str!("template.svg").to_string();
-    
-    let data = build_all_data().await;
 
-    template = format_news(template, &data);
-    template = format_calendar(template, &data);
-    template = format_stats(template, &data);
-    template = format_time(template, &data);
-    template = format_weather(template, &data);
-    template = format_radar(template, &data);
+    template = format_panel(template, data, config);
+    template = format_calendar(template, data);
+    template = format_stats(template, data);
+    template = format_weather(template, data, config);
+    template = format_conditions(template, data, config);
+    template = format_radar(template, data);
+    template = format_warnings(template, data);
+    template = format_reminder(template, data);
 
     template
 }
 
+fn night_svg(until: NaiveDateTime) -> String {
+    format!(r#"<svg width="1200px" height="900px" viewBox="0 0 1200 900" xmlns="http://www.w3.org/2000/svg">
+  <rect width="1200" height="900" fill="white" />
+  <text x="600" y="430" font-family="FreeSans" font-weight="bold" font-size="120px" text-anchor="middle">Good night</text>
+  <text x="600" y="530" font-family="FreeSans" font-size="60px" text-anchor="middle">Back at {}</text>
+</svg>"#, until.format("%H:%M"))
+}
+
 async fn render_svg(template: String) -> DynamicImage {
     let mut fontdb = usvg::fontdb::Database::new();
     fontdb.load_font_data(include_bytes!("fonts/FreeSans.ttf").to_vec());
     fontdb.load_font_data(include_bytes!("fonts/FreeSansBold.ttf").to_vec());
 
-    let mut options = usvg::Options::default();
-    options.fontdb = std::sync::Arc::new(fontdb);
+    let options = usvg::Options {
+        fontdb: std::sync::Arc::new(fontdb),
+        ..Default::default()
+    };
 
     let svg_tree = Tree::from_str(&template, &options).unwrap();
 
@@ -558,11 +926,12 @@ async fn render_svg(template: String) -> DynamicImage {
     resvg::render(&svg_tree, Transform::identity(), &mut PixmapMut::from_bytes(&mut image, size.width() as u32, size.height() as u32).unwrap());
     let elapsed = format!("{:.2?}", now.elapsed());
     info!("Rendering took {elapsed}");
+    status::record_timing("render", now.elapsed());
 
     let image_vec = image.to_vec();
     let img_buffer: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_raw(width as u32, height as u32, image_vec).unwrap();
-    let result = DynamicImage::ImageRgba8(img_buffer);
-    return result;
+    
+    DynamicImage::ImageRgba8(img_buffer)
     
 }
 
@@ -599,8 +968,30 @@ pub async fn show_panic(panic: &String) -> Result<(), Box<dyn std::error::Error>
     
 }
 
+pub async fn show_stopped() -> Result<(), Box<dyn std::error::Error>> {
+    // Same idea as show_panic, so it is obvious the dashboard is no longer updating
+    if std::env::var("NOT_KINDLE").is_err() {
+        clear_screen().await;
+        let output = Command::new("eips").arg("2").arg("1").arg("\"Dashboard stopped\"").output();
+        match output {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Could not show stopped screen: {e}").into())
+        }
+    } else {
+        info!("Skipping showing the stopped screen due to env NOT_KINDLE");
+        Ok(())
+    }
+}
+
+fn frame_hash(image: &image::GrayImage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image.dimensions().hash(&mut hasher);
+    image.as_raw().hash(&mut hasher);
+    hasher.finish()
+}
 
-pub fn save(mut image: DynamicImage) -> String {
+pub fn save(mut image: DynamicImage) -> (String, u64) {
+    // Also gives back a hash of exactly what eips will show, to skip redrawing identical frames
     let output_path = "output.png".to_string();
 
     info!("Saving the rendering...");
@@ -620,23 +1011,182 @@ pub fn save(mut image: DynamicImage) -> String {
     result.save(output_path.clone()).unwrap();
     let elapsed = format!("{:.2?}", now.elapsed());
     info!("Saving took {elapsed}");
-    output_path
+    status::record_timing("save", now.elapsed());
+    (output_path, frame_hash(&result))
 }
 
-pub async fn render_png() {
-    let start = Instant::now();
+async fn show(dashboard: &mut Dashboard, template: String) {
+    let image = render_svg(template.clone()).await;
+    let (output_pth, frame) = save(image.clone());
+
+    match std::fs::read(&output_pth) {
+        Ok(png) => status::record_frame(template, png),
+        Err(e) => warn!("Could not read back {output_pth} for the status server: {e}")
+    }
+
+    if dashboard.frame_unchanged(frame, read_display_config().full_refresh_minutes) {
+        info!("Frame is unchanged, skipping the e-ink update");
+        metrics::observe_eips_skipped();
+        return;
+    }
 
-    let template = create_output_svg().await;
-    let image = render_svg(template).await;
-    let output_pth = save(image.clone());
+    let eips_start = Instant::now();
     let eips_result = update_screen(output_pth).await;
+    status::record_timing("eips", eips_start.elapsed());
 
     match eips_result {
-        Ok(_r) => {info!("Success! Now showing the result!")},
-        Err(e) => warn!("Could not show result! Is eips available? {e}") // Mainly for testing
+        Ok(r) => {
+            metrics::observe_eips(r.status.code());
+            dashboard.last_frame = Some(frame);
+            dashboard.last_update = Some(Instant::now());
+            info!("Success! Now showing the result!")
+        },
+        Err(e) => {
+            metrics::observe_eips(None);
+            warn!("Could not show result! Is eips available? {e}") // Mainly for testing
+        }
+    }
+}
+
+pub async fn render_png(dashboard: &mut Dashboard) {
+    let start = Instant::now();
+
+    dashboard.scheduler.reload();
+
+    if let Some(until) = dashboard.scheduler.quiet_until(scheduler::now()) {
+        if !dashboard.night {
+            info!("Quiet hours until {}, showing the night screen", until.format("%H:%M"));
+            show(dashboard, night_svg(until)).await;
+            dashboard.night = true;
+            dashboard.last_shown = None;
+        } else {
+            info!("Quiet hours, nothing to refresh");
+        }
+        return;
+    }
+    dashboard.night = false;
+
+    build_all_data(&mut dashboard.data, &mut dashboard.scheduler).await;
+
+    let reminders = dashboard.data.calendar.iter()
+        .flat_map(|agenda| &agenda.reminders)
+        .filter_map(|event| Some((event.alarm?.with_timezone(&Local).naive_local(), event.start_time.with_timezone(&Local).naive_local())))
+        .collect();
+    dashboard.scheduler.set_reminders(reminders);
+
+    let config = read_display_config();
+    let template = create_output_svg(&dashboard.data, &config);
+
+    // Compared with the clock filled in, so a wake on the quarter hour always moves it on
+    let template = format_time(template, &dashboard.data);
+    if dashboard.template_unchanged(&template, config.full_refresh_minutes) {
+        info!("Nothing displayed has changed, skipping the redraw");
+        return;
     }
+    dashboard.last_shown = Some(template.clone());
+
+    show(dashboard, template).await;
 
     let elapsed = format!("{:.2?}", start.elapsed());
     info!("Finished in {elapsed}");
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn local(y: i32, m: u32, d: u32, h: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn skips_unchanged_frames_until_a_full_refresh() {
+        let dashboard = Dashboard {
+            last_shown: Some("<svg>12:15</svg>".to_string()),
+            last_frame: Some(42),
+            last_update: Instant::now().checked_sub(stdDuration::from_secs(20 * 60)),
+            ..Default::default()
+        };
+
+        assert!(dashboard.template_unchanged("<svg>12:15</svg>", None));
+        assert!(!dashboard.template_unchanged("<svg>12:30</svg>", None));
+        assert!(dashboard.frame_unchanged(42, None));
+        assert!(!dashboard.frame_unchanged(43, None));
+
+        // Shown 20 minutes ago, so a 30 minute full refresh is not due yet but a 15 minute one is
+        assert!(dashboard.template_unchanged("<svg>12:15</svg>", Some(30)));
+        assert!(dashboard.frame_unchanged(42, Some(30)));
+        assert!(!dashboard.template_unchanged("<svg>12:15</svg>", Some(15)));
+        assert!(!dashboard.frame_unchanged(42, Some(15)));
+
+        // Nothing shown yet
+        assert!(!Dashboard::default().frame_unchanged(42, Some(15)));
+    }
+
+    fn frames(times: &[Option<u32>], marker: Option<(i64, i64)>) -> KindleDisplayData {
+        let frames = times.iter().map(|minute| radar::Frame {
+            time: minute.map(|minute| Utc.with_ymd_and_hms(2024, 5, 30, 3, minute, 0).unwrap()),
+            image: DynamicImage::new_rgba8(64, 64),
+            marker,
+        }).collect();
+        KindleDisplayData { radar: Some(frames), ..Default::default() }
+    }
+
+    #[test]
+    fn shows_a_single_radar_frame_without_a_strip() {
+        let svg = format_radar("<svg>BASE64RADAR</svg>".to_string(), &frames(&[Some(54)], None));
+
+        assert!(!svg.contains("BASE64RADAR"));
+        assert_eq!(svg.matches("<image").count(), 0);
+        assert!(svg.contains(r#"<rect x="512" y="588.0" width="70" height="24""#));
+        assert!(svg.contains(">mm/h<"));
+    }
+
+    #[test]
+    fn leaves_out_missing_radar_times() {
+        let svg = format_radar("<svg>BASE64RADAR</svg>".to_string(), &frames(&[None, Some(42), None], None));
+
+        assert_eq!(svg.matches("<image").count(), 2);
+        assert!(!svg.contains(r#"width="70" height="24""#));
+        let time = Utc.with_ymd_and_hms(2024, 5, 30, 3, 42, 0).unwrap().with_timezone(&Local).format("%H:%M").to_string();
+        assert_eq!(svg.matches("</text>").count(), 1 + radar::palette::BANDS.len() + 1);
+        assert!(svg.contains(&format!(">{time}<")));
+    }
+
+    #[test]
+    fn moves_the_radar_strip_off_the_marker() {
+        // 64 pixels to 590, so 60 down is at the bottom of the radar and 10 down at the top
+        let svg = format_radar("<svg>BASE64RADAR</svg>".to_string(), &frames(&[Some(48), Some(54)], Some((32, 60))));
+        assert!(svg.contains(r#"<rect x="18.0" y="136.0""#));
+        assert!(svg.contains(r#"y="256.0" width="70" height="24""#));
+
+        let svg = format_radar("<svg>BASE64RADAR</svg>".to_string(), &frames(&[Some(48), Some(54)], Some((32, 10))));
+        assert!(svg.contains(r#"<rect x="18.0" y="468.0""#));
+    }
+
+    #[test]
+    fn counts_calendar_months_and_years() {
+        let now = local(2024, 1, 31, 12);
+
+        assert_eq!(time_between(now, local(2024, 2, 29, 12)), "1 month");
+        assert_eq!(time_between(local(2024, 3, 1, 12), local(2024, 3, 31, 12)), "4 weeks");
+        assert_eq!(time_between(now, local(2024, 3, 31, 12)), "2 months");
+        assert_eq!(time_between(now, local(2024, 3, 30, 12)), "1 month");
+        assert_eq!(time_between(now, local(2025, 1, 30, 12)), "11 months");
+        assert_eq!(time_between(now, local(2025, 1, 31, 12)), "1 year");
+        assert_eq!(time_between(local(2024, 2, 29, 12), local(2028, 2, 29, 12)), "4 years");
+    }
+
+    #[test]
+    fn counts_short_times() {
+        let now = local(2024, 6, 3, 9);
+
+        assert_eq!(time_between(now, local(2024, 6, 13, 9)), "1 week");
+        assert_eq!(time_between(now, local(2024, 6, 5, 10)), "2 days");
+        assert_eq!(time_between(now, local(2024, 6, 3, 9) + Duration::minutes(59)), "59 mins");
+        assert_eq!(time_between(now, now + Duration::minutes(90)), "1 hour");
+        assert_eq!(time_between(now, now + Duration::seconds(1)), "1 sec");
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use log::warn;
+
+/*
+    Weather icons for OWM condition codes (https://openweathermap.org/weather-conditions).
+
+    Any code can be given another icon in sensitive/display.json, for day and night alike or for
+    just one of them with a d/n suffix, eg {"weather_icons": {"701": "cloud", "800n": "sun"}}
+*/
+
+const ICONS: [(&str, &str); 15] = [
+    ("sun", include_str!("../icons/sun.svg")),
+    ("moon", include_str!("../icons/moon.svg")),
+    ("cloud-sun", include_str!("../icons/cloud-sun.svg")),
+    ("cloud-moon", include_str!("../icons/cloud-moon.svg")),
+    ("cloud", include_str!("../icons/cloud.svg")),
+    ("clouds", include_str!("../icons/clouds.svg")),
+    ("cloud-fog", include_str!("../icons/cloud-fog.svg")),
+    ("cloud-drizzle", include_str!("../icons/cloud-drizzle.svg")),
+    ("cloud-rain", include_str!("../icons/cloud-rain.svg")),
+    ("cloud-rain-heavy", include_str!("../icons/cloud-rain-heavy.svg")),
+    ("cloud-sleet", include_str!("../icons/cloud-sleet.svg")),
+    ("cloud-snow", include_str!("../icons/cloud-snow.svg")),
+    ("cloud-lightning", include_str!("../icons/cloud-lightning.svg")),
+    ("cloud-lightning-rain", include_str!("../icons/cloud-lightning-rain.svg")),
+    ("wind", include_str!("../icons/wind.svg")),
+];
+
+pub fn svg(name: &str) -> Option<&'static str> {
+    ICONS.iter().find(|(icon, _)| *icon == name).map(|(_, svg)| *svg)
+}
+
+fn default_icon(code: u32, night: bool) -> &'static str {
+    match code {
+        210..=221 => "cloud-lightning",
+        200..=299 => "cloud-lightning-rain",
+        300..=310 => "cloud-drizzle",
+        311..=399 => "cloud-rain",
+        500 | 501 | 520 => "cloud-rain",
+        511 => "cloud-sleet",
+        502..=599 => "cloud-rain-heavy",
+        611..=616 => "cloud-sleet",
+        600..=699 => "cloud-snow",
+        771 | 781 => "wind",
+        700..=799 => "cloud-fog",
+        800 => if night {"moon"} else {"sun"},
+        801 | 802 => if night {"cloud-moon"} else {"cloud-sun"},
+        803 => "cloud",
+        _ => "clouds"
+    }
+}
+
+pub fn for_condition(code: u32, night: bool, overrides: &HashMap<String, String>) -> &'static str {
+    let suffix = if night {"n"} else {"d"};
+    let chosen = overrides.get(&format!("{code}{suffix}")).or_else(|| overrides.get(&code.to_string()));
+
+    if let Some(name) = chosen {
+        match svg(name) {
+            Some(icon) => return icon,
+            None => warn!("Unknown weather icon {name} for {code} in sensitive/display.json")
+        }
+    }
+
+    svg(default_icon(code, night)).unwrap_or(ICONS[0].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(icon: &str) -> &str {
+        ICONS.iter().find(|(_, svg)| *svg == icon).map(|(name, _)| *name).unwrap()
+    }
+
+    #[test]
+    fn maps_every_condition_group() {
+        let none = HashMap::new();
+        let icon = |code, night| name(for_condition(code, night, &none)).to_string();
+
+        assert_eq!(icon(201, false), "cloud-lightning-rain");
+        assert_eq!(icon(211, false), "cloud-lightning");
+        assert_eq!(icon(300, false), "cloud-drizzle");
+        assert_eq!(icon(500, false), "cloud-rain");
+        assert_eq!(icon(503, false), "cloud-rain-heavy");
+        assert_eq!(icon(511, false), "cloud-sleet");
+        assert_eq!(icon(601, false), "cloud-snow");
+        assert_eq!(icon(741, false), "cloud-fog");
+        assert_eq!(icon(781, false), "wind");
+        assert_eq!(icon(804, false), "clouds");
+    }
+
+    #[test]
+    fn clear_skies_have_night_variants() {
+        let none = HashMap::new();
+        let icon = |code, night| name(for_condition(code, night, &none)).to_string();
+
+        assert_eq!((icon(800, false), icon(800, true)), ("sun".to_string(), "moon".to_string()));
+        assert_eq!((icon(801, false), icon(801, true)), ("cloud-sun".to_string(), "cloud-moon".to_string()));
+        assert_eq!(icon(804, true), "clouds");
+    }
+
+    #[test]
+    fn overrides_by_code_and_time_of_day() {
+        let overrides: HashMap<String, String> = serde_json::from_str(r#"{"701": "cloud", "800n": "sun", "500": "umbrella"}"#).unwrap();
+        let icon = |code, night| name(for_condition(code, night, &overrides)).to_string();
+
+        assert_eq!((icon(701, false), icon(701, true)), ("cloud".to_string(), "cloud".to_string()));
+        assert_eq!((icon(800, false), icon(800, true)), ("sun".to_string(), "sun".to_string()));
+        // Not an icon we have
+        assert_eq!(icon(500, false), "cloud-rain");
+    }
+}
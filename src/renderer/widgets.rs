@@ -0,0 +1,177 @@
+mod agenda;
+mod chart;
+mod countdown;
+mod environment;
+mod month;
+
+pub use chart::ChartConfig;
+pub use month::MonthConfig;
+
+use super::{escape_xml, generate_svg_text, time_between, DisplayConfig, KindleDisplayData};
+
+use serde::Deserialize;
+use chrono::Local;
+
+use log::warn;
+
+/*
+    Widgets drawn into the large panel on the right, where the news normally is.
+
+    Configured in sensitive/display.json, eg {"panel": ["agenda", {"widget": "news", "height": 250}]}
+    Widgets are stacked top to bottom, those without a height share whatever is left.
+    Each one draws itself as an svg fragment in screen coordinates (1200x900).
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn inset(&self, by: f64) -> Rect {
+        Rect { x: self.x + by, y: self.y + by, width: self.width - 2.0 * by, height: self.height - 2.0 * by }
+    }
+
+    pub fn bottom(&self) -> f64 {
+        self.y + self.height
+    }
+}
+
+pub const PANEL: Rect = Rect { x: 605.0, y: 210.0, width: 585.0, height: 680.0 };
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PanelEntry {
+    Name(String),
+    Sized { widget: String, height: f64 },
+}
+
+impl PanelEntry {
+    fn widget(&self) -> &str {
+        match self {
+            PanelEntry::Name(widget) => widget,
+            PanelEntry::Sized { widget, .. } => widget
+        }
+    }
+}
+
+pub fn layout(entries: &[PanelEntry], area: Rect) -> Vec<(String, Rect)> {
+    let fixed: f64 = entries.iter()
+        .map(|entry| match entry {PanelEntry::Sized { height, .. } => *height, _ => 0.0})
+        .sum();
+    let flexible = entries.iter().filter(|entry| matches!(entry, PanelEntry::Name(_))).count();
+    let share = if flexible > 0 {((area.height - fixed) / flexible as f64).max(0.0)} else {0.0};
+
+    let mut y = area.y;
+    entries.iter().map(|entry| {
+        let height = match entry {PanelEntry::Sized { height, .. } => *height, _ => share};
+        let rect = Rect { x: area.x, y, width: area.width, height };
+        y += height;
+        (entry.widget().to_string(), rect)
+    }).collect()
+}
+
+// Fills for greyscale, referenced as url(#widget-hatch) etc
+pub const PATTERNS: &str = r#"<defs>
+<pattern id="widget-hatch" width="6" height="6" patternUnits="userSpaceOnUse" patternTransform="rotate(45)"><rect width="6" height="6" fill="white" /><line x1="0" y1="0" x2="0" y2="6" stroke="black" stroke-width="3" /></pattern>
+<pattern id="widget-crosshatch" width="6" height="6" patternUnits="userSpaceOnUse" patternTransform="rotate(45)"><rect width="6" height="6" fill="white" /><path d="M0 0V6M0 0H6" stroke="black" stroke-width="2" /></pattern>
+<pattern id="widget-dots" width="5" height="5" patternUnits="userSpaceOnUse"><rect width="5" height="5" fill="white" /><circle cx="2.5" cy="2.5" r="1.5" fill="black" /></pattern>
+</defs>"#;
+
+pub fn marker(style: &str, x: f64, baseline: f64, size: f64) -> String {
+    // A calendar's marker, as a square sitting on the baseline (or the text itself, for a glyph)
+    let y = baseline - size * 0.8;
+    let square = |fill: &str| format!(
+        r#"<rect x="{x:.1}" y="{y:.1}" width="{size:.1}" height="{size:.1}" fill="{fill}" stroke="black" stroke-width="2" />"#
+    );
+
+    match style {
+        "circle" => format!(r#"<circle cx="{:.1}" cy="{:.1}" r="{:.1}" fill="black" />"#, x + size / 2.0, y + size / 2.0, size / 2.0),
+        "triangle" => format!(r#"<path d="M{:.1} {:.1}H{:.1}L{:.1} {y:.1}Z" fill="black" />"#, x, y + size, x + size, x + size / 2.0),
+        "solid" => square("black"),
+        "outline" => square("white"),
+        "hatch" | "dots" | "crosshatch" => square(&format!("url(#widget-{style})")),
+        glyph => text(x, baseline, size, true, "black", glyph)
+    }
+}
+
+pub fn fit(text: &str, width: f64, font_size: f64) -> String {
+    // No text metrics here, FreeSans averages a little over half its size per character
+    let max_chars = (width / (font_size * 0.55)).max(1.0) as usize;
+
+    if text.chars().count() <= max_chars {
+        return text.to_string()
+    }
+
+    let cut: String = text.chars().take(max_chars.saturating_sub(3)).collect();
+    format!("{}...", cut.trim_end())
+}
+
+pub fn text(x: f64, y: f64, font_size: f64, bold: bool, fill: &str, content: &str) -> String {
+    format!(
+        r#"<text x="{x:.1}" y="{y:.1}" font-family="FreeSans" font-weight="{}" font-size="{font_size}px" fill="{fill}">{}</text>"#,
+        if bold {"bold"} else {"normal"}, escape_xml(content)
+    )
+}
+
+pub fn aligned(x: f64, y: f64, font_size: f64, bold: bool, fill: &str, anchor: &str, content: &str) -> String {
+    // Text centred on x ("middle") or ending at it ("end")
+    format!(
+        r#"<text x="{x:.1}" y="{y:.1}" text-anchor="{anchor}" font-family="FreeSans" font-weight="{}" font-size="{font_size:.1}px" fill="{fill}">{}</text>"#,
+        if bold {"bold"} else {"normal"}, escape_xml(content)
+    )
+}
+
+fn news(data: &KindleDisplayData, area: Rect) -> String {
+    // The same text as the template's news, which is drawn at a quarter scale
+    let scale = 0.26458333;
+    let line = 120.0 * 1.2 * scale;
+    let max_lines = ((area.height - 20.0) / line).max(1.0) as usize;
+
+    let lines = match &data.news {
+        Some(news) => generate_svg_text(news.clone(), max_lines, 35.0, ((area.x + 5.0) / scale) as i32, ((area.y + 32.0) / scale) as i32, 120, 1.2),
+        None => "<tspan>ERR</tspan>".to_string()
+    };
+
+    format!(r#"<text transform="scale({scale})">{lines}</text>"#)
+}
+
+pub fn draw(widget: &str, data: &KindleDisplayData, area: Rect, config: &DisplayConfig) -> Option<String> {
+    let now = Local::now();
+
+    match widget {
+        "news" => Some(news(data, area)),
+        "agenda" => Some(agenda::draw(data.calendar.as_ref(), area, now)),
+        "month" => Some(month::draw(data.calendar.as_ref(), area, now, &config.month)),
+        "countdown" => Some(countdown::draw(data.calendar.as_ref(), area, now)),
+        "chart" => Some(chart::draw(data.weather.as_ref(), area, now, &config.chart)),
+        "environment" => Some(environment::draw(data.environment.as_ref(), area)),
+        _ => {
+            warn!("Unknown widget {widget} in sensitive/display.json");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stacks_fixed_and_shared_heights() {
+        let entries: Vec<PanelEntry> = serde_json::from_str(r#"["agenda", {"widget": "news", "height": 280}, "countdown"]"#).unwrap();
+        let rects = layout(&entries, PANEL);
+
+        assert_eq!(rects.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["agenda", "news", "countdown"]);
+        assert_eq!(rects.iter().map(|(_, rect)| (rect.y, rect.height)).collect::<Vec<_>>(), [(210.0, 200.0), (410.0, 280.0), (690.0, 200.0)]);
+    }
+
+    #[test]
+    fn fits_text_to_width() {
+        assert_eq!(fit("Standup", 200.0, 20.0), "Standup");
+        assert_eq!(fit("A very long event name that will not fit", 110.0, 20.0), "A very...");
+    }
+}
@@ -0,0 +1,220 @@
+use super::{fit, marker, text, time_between, Rect};
+use crate::calendar::{Agenda, CalendarEvent, CalendarState};
+
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+
+const HEADING_SIZE: f64 = 30.0;
+const EVENT_SIZE: f64 = 26.0;
+const LOCATION_SIZE: f64 = 20.0;
+const TIME_WIDTH: f64 = 165.0;
+const MARKER_WIDTH: f64 = 28.0;
+
+pub fn day_heading(date: NaiveDate, today: NaiveDate) -> String {
+    if date == today {
+        "Today".to_string()
+    } else if date == today + Duration::days(1) {
+        "Tomorrow".to_string()
+    } else {
+        date.format("%a %-d %b").to_string()
+    }
+}
+
+fn event_time(event: &CalendarEvent, now: DateTime<Utc>) -> String {
+    if event.all_day {
+        return "All day".to_string()
+    }
+
+    let start = event.start_time.with_timezone(&Local);
+    let end = event.end_time.with_timezone(&Local);
+
+    if event.start_time <= now && end > now {
+        return format!("Until {}", end.format("%H:%M"))
+    }
+
+    if end > start && end.date_naive() == start.date_naive() {
+        format!("{}-{}", start.format("%H:%M"), end.format("%H:%M"))
+    } else {
+        start.format("%H:%M").to_string()
+    }
+}
+
+fn status(agenda: &Agenda, now: DateTime<Local>) -> String {
+    match agenda.state(now.to_utc()) {
+        CalendarState::InProgress(event) => {
+            let end = event.end_time.with_timezone(&Local);
+            format!("Busy until {} (ends in {})", end.format("%H:%M"), time_between(now, end))
+        },
+        CalendarState::NextUp(event) => {
+            let start = event.start_time.with_timezone(&Local);
+            format!("Free until {} (in {})", start.format("%H:%M"), time_between(now, start))
+        },
+        CalendarState::FreeToday(_) => "Free for the rest of today".to_string()
+    }
+}
+
+fn details(event: &CalendarEvent) -> Vec<&str> {
+    // Which calendar it is from, and where
+    event.calendar.iter().chain(&event.location).map(|s| s.as_str()).collect()
+}
+
+fn event_height(event: &CalendarEvent) -> f64 {
+    EVENT_SIZE * 1.4 + if details(event).is_empty() {0.0} else {LOCATION_SIZE * 1.2}
+}
+
+pub fn draw(agenda: Option<&Agenda>, area: Rect, now: DateTime<Local>) -> String {
+    let area = area.inset(15.0);
+    let today = now.date_naive();
+
+    let agenda = match agenda {
+        Some(agenda) => agenda,
+        None => return text(area.x, area.y + HEADING_SIZE, HEADING_SIZE, true, "black", "Could not fetch any events")
+    };
+
+    let mut svg = String::new();
+    let mut y = area.y;
+
+    // Events from the calendars that worked are still listed, above a note of those that did not
+    let mut area = area;
+    if !agenda.failed.is_empty() {
+        let note = format!("Could not update {}", agenda.failed.join(", "));
+        svg.push_str(&text(area.x, area.bottom(), LOCATION_SIZE, false, "#555555", &fit(&note, area.width, LOCATION_SIZE)));
+        area.height -= LOCATION_SIZE * 1.5;
+    }
+
+    if agenda.days.is_empty() {
+        svg.push_str(&text(area.x, area.y + HEADING_SIZE, HEADING_SIZE, true, "black", "No upcoming events"));
+        return svg
+    }
+
+    y += EVENT_SIZE;
+    svg.push_str(&text(area.x, y, EVENT_SIZE, false, "black", &fit(&status(agenda, now), area.width, EVENT_SIZE)));
+    y += 6.0;
+
+    // Names line up whether or not their calendar has a marker
+    let has_markers = agenda.days.iter().flat_map(|day| &day.events).any(|event| event.marker.is_some());
+    let name_x = area.x + TIME_WIDTH + if has_markers {MARKER_WIDTH} else {0.0};
+    let name_width = area.x + area.width - name_x;
+
+    'days: for day in &agenda.days {
+        // A heading needs at least its first event under it
+        let first = day.events.first().map(event_height).unwrap_or(0.0);
+        if y + HEADING_SIZE + 8.0 + first > area.bottom() {
+            break
+        }
+
+        y += HEADING_SIZE;
+        svg.push_str(&text(area.x, y, HEADING_SIZE, true, "black", &day_heading(day.date, today)));
+        y += 8.0;
+        svg.push_str(&format!(r#"<line x1="{:.1}" y1="{y:.1}" x2="{:.1}" y2="{y:.1}" stroke="black" stroke-width="2" />"#, area.x, area.x + area.width));
+
+        for event in &day.events {
+            let details = details(event);
+
+            if y + event_height(event) > area.bottom() {
+                break 'days
+            }
+
+            y += EVENT_SIZE * 1.4;
+            svg.push_str(&text(area.x, y, EVENT_SIZE, false, "black", &event_time(event, now.to_utc())));
+            if let Some(style) = &event.marker {
+                svg.push_str(&marker(style, area.x + TIME_WIDTH, y, EVENT_SIZE * 0.75));
+            }
+            svg.push_str(&text(name_x, y, EVENT_SIZE, true, "black", &fit(&event.name, name_width, EVENT_SIZE)));
+
+            if !details.is_empty() {
+                y += LOCATION_SIZE * 1.2;
+                svg.push_str(&text(name_x, y, LOCATION_SIZE, false, "#555555", &fit(&details.join(" · "), name_width, LOCATION_SIZE)));
+            }
+        }
+
+        y += 14.0;
+    }
+
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::AgendaDay;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 6, 3, 9, 0, 0).unwrap()
+    }
+
+    fn event(name: &str, day: u32, hour: u32) -> CalendarEvent {
+        let start = Local.with_ymd_and_hms(2024, 6, day, hour, 0, 0).unwrap().to_utc();
+        CalendarEvent {
+            start_time: start,
+            end_time: start + Duration::minutes(30),
+            name: name.to_string(),
+            location: None,
+            all_day: false,
+            calendar: None,
+            marker: None,
+            alarm: None,
+        }
+    }
+
+    fn agenda(days: &[(u32, Vec<CalendarEvent>)], failed: &[&str]) -> Agenda {
+        Agenda {
+            days: days.iter().map(|(day, events)| AgendaDay { date: NaiveDate::from_ymd_opt(2024, 6, *day).unwrap(), events: events.clone() }).collect(),
+            failed: failed.iter().map(|name| name.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn area(height: f64) -> Rect {
+        Rect { x: 0.0, y: 0.0, width: 585.0, height }
+    }
+
+    #[test]
+    fn counts_down_from_the_time_drawn_for() {
+        let agenda = agenda(&[(3, vec![event("Standup", 3, 9), event("Review", 3, 11)])], &[]);
+
+        assert_eq!(status(&agenda, now() + Duration::minutes(10)), "Busy until 09:30 (ends in 20 mins)");
+        assert_eq!(status(&agenda, now() + Duration::minutes(45)), "Free until 11:00 (in 1 hour)");
+        assert!(draw(Some(&agenda), area(300.0), now() + Duration::minutes(10)).contains("Busy until 09:30 (ends in 20 mins)"));
+    }
+
+    #[test]
+    fn stops_at_the_bottom_of_its_area() {
+        let today = (0..6).map(|i| event(&format!("Event {i}"), 3, 10 + i)).collect();
+        let agenda = agenda(&[(3, today), (4, vec![event("Dentist", 4, 10)])], &[]);
+
+        // Room for five events under the status and today's heading, and nothing of tomorrow
+        let svg = draw(Some(&agenda), area(300.0), now());
+        assert!(svg.contains("Today") && svg.contains("Event 4"));
+        assert!(!svg.contains("Event 5") && !svg.contains("Tomorrow") && !svg.contains("Dentist"));
+
+        // A heading is left off when its first event would not fit under it
+        let agenda = self::agenda(&[(3, vec![event("Standup", 3, 10)]), (4, vec![event("Dentist", 4, 10)])], &[]);
+        let svg = draw(Some(&agenda), area(215.0), now());
+        assert!(svg.contains("Standup"));
+        assert!(!svg.contains("Tomorrow"));
+    }
+
+    #[test]
+    fn shortens_long_names() {
+        let agenda = agenda(&[(3, vec![event("Quarterly planning and review with the extended leadership team", 3, 10)])], &[]);
+        let svg = draw(Some(&agenda), area(300.0), now());
+        assert!(svg.contains("Quarterly planning") && svg.contains("..."));
+        assert!(!svg.contains("leadership team"));
+    }
+
+    #[test]
+    fn notes_failed_calendars() {
+        // The note takes space from the events
+        let today = (0..6).map(|i| event(&format!("Event {i}"), 3, 10 + i)).collect();
+        let svg = draw(Some(&agenda(&[(3, today)], &["Work"])), area(300.0), now());
+        assert!(svg.contains("Could not update Work"));
+        assert!(svg.contains("Event 3") && !svg.contains("Event 4"));
+
+        let svg = draw(Some(&agenda(&[], &["Work", "Family"])), area(300.0), now());
+        assert!(svg.contains("Could not update Work, Family") && svg.contains("No upcoming events"));
+
+        assert!(draw(Some(&agenda(&[], &[])), area(300.0), now()).contains("No upcoming events"));
+        assert!(draw(None, area(300.0), now()).contains("Could not fetch any events"));
+    }
+}
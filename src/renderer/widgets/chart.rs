@@ -0,0 +1,222 @@
+use super::{aligned, text, Rect};
+use crate::weather::{Forecast, HourData};
+
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveTime};
+use serde::Deserialize;
+
+const HEADING_SIZE: f64 = 26.0;
+const LABEL_SIZE: f64 = 18.0;
+const AXIS_WIDTH: f64 = 46.0;
+const LABEL_SPACING: f64 = 64.0;
+
+/* From sensitive/display.json, eg {"chart": {"hours": 48}} */
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChartConfig {
+    #[serde(default = "default_hours")]
+    hours: i64,
+}
+
+fn default_hours() -> i64 {24}
+
+impl Default for ChartConfig {
+    fn default() -> ChartConfig {
+        ChartConfig { hours: default_hours() }
+    }
+}
+
+pub fn window(hours: &[HourData], now: DateTime<Local>, span: Duration) -> &[HourData] {
+    // From the last point before now, so the line starts behind the now marker, to the end of the span
+    let now = now.fixed_offset();
+    let start = hours.iter().rposition(|hour| hour.time <= now).unwrap_or(0);
+    let end = hours.iter().position(|hour| hour.time > now + span).unwrap_or(hours.len());
+    &hours[start..end.max(start)]
+}
+
+pub fn ticks(min: f64, max: f64) -> Vec<f64> {
+    // Every 5 degrees, or 10 for wide ranges, enclosing the whole line
+    let step = if max - min > 20.0 {10.0} else {5.0};
+    let low = (min / step).floor() * step;
+    let high = ((max / step).ceil() * step).max(low + step);
+
+    (0..=((high - low) / step).round() as usize).map(|i| low + step * i as f64).collect()
+}
+
+pub fn draw(forecast: Option<&Forecast>, area: Rect, now: DateTime<Local>, config: &ChartConfig) -> String {
+    let area = area.inset(15.0);
+    let mut svg = text(area.x, area.y + HEADING_SIZE, HEADING_SIZE, true, "black", &format!("Next {} hours", config.hours));
+
+    let points = match forecast {
+        Some(forecast) => window(&forecast.hours, now, Duration::hours(config.hours)),
+        None => return text(area.x, area.y + HEADING_SIZE, HEADING_SIZE, true, "black", "Could not fetch the forecast")
+    };
+    if points.len() < 2 {
+        svg.push_str(&text(area.x, area.y + HEADING_SIZE * 2.5, LABEL_SIZE, false, "black", "No hourly forecast"));
+        return svg
+    }
+
+    // Room above for the now label, and below for the hours
+    let top = area.y + HEADING_SIZE + LABEL_SIZE * 2.0;
+    let plot = Rect {
+        x: area.x + AXIS_WIDTH,
+        y: top,
+        width: area.width - AXIS_WIDTH * 2.0,
+        height: area.bottom() - LABEL_SIZE * 1.6 - top,
+    };
+
+    let first = points[0].time;
+    // At least a second, as points can share a time
+    let seconds = ((points[points.len() - 1].time - first).num_seconds() as f64).max(1.0);
+    let x = |time: DateTime<FixedOffset>| plot.x + ((time - first).num_seconds() as f64 / seconds).clamp(0.0, 1.0) * plot.width;
+
+    let temps = ticks(
+        points.iter().map(|point| point.temp_c).fold(f64::INFINITY, f64::min),
+        points.iter().map(|point| point.temp_c).fold(f64::NEG_INFINITY, f64::max)
+    );
+    let (low, high) = (temps[0], temps[temps.len() - 1]);
+    let y = |temp: f64| plot.bottom() - (temp - low) / (high - low) * plot.height;
+
+    for temp in &temps {
+        svg.push_str(&format!(
+            r##"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="#aaaaaa" stroke-width="1" />"##,
+            plot.x, y(*temp), plot.x + plot.width, y(*temp)
+        ));
+        svg.push_str(&aligned(plot.x - 6.0, y(*temp) + LABEL_SIZE * 0.35, LABEL_SIZE, false, "black", "end", &format!("{temp:.0}°")));
+    }
+
+    // Rain and snow as hatched bars back to the point before, against the right hand axis
+    let most = points.iter().map(|point| point.precipitation).fold(0.0, f64::max).max(2.0).ceil();
+    for (i, point) in points.iter().enumerate().filter(|(_, point)| point.precipitation > 0.0) {
+        let since = if i > 0 {points[i - 1].time} else {first - (points[1].time - first)};
+        let left = x(since);
+        let height = point.precipitation / most * plot.height;
+        svg.push_str(&format!(
+            r#"<rect x="{left:.1}" y="{:.1}" width="{:.1}" height="{height:.1}" fill="url(#widget-hatch)" stroke="black" stroke-width="1.5" />"#,
+            plot.bottom() - height, x(point.time) - left
+        ));
+    }
+    svg.push_str(&text(plot.x + plot.width + 6.0, plot.y + LABEL_SIZE * 0.35, LABEL_SIZE, false, "black", &format!("{most:.0}mm")));
+    svg.push_str(&text(plot.x + plot.width + 6.0, plot.bottom() + LABEL_SIZE * 0.35, LABEL_SIZE, false, "black", "0"));
+
+    let line: Vec<String> = points.iter().map(|point| format!("{:.1} {:.1}", x(point.time), y(point.temp_c))).collect();
+    svg.push_str(&format!(r#"<path d="M{}" fill="none" stroke="black" stroke-width="3" stroke-linejoin="round" />"#, line.join("L")));
+
+    // Hours along the bottom, with midnights marked by the day they start
+    let mut last_label = f64::NEG_INFINITY;
+    for (i, point) in points.iter().enumerate() {
+        if x(point.time) - last_label >= LABEL_SPACING {
+            last_label = x(point.time);
+            svg.push_str(&aligned(last_label, plot.bottom() + LABEL_SIZE * 1.3, LABEL_SIZE, false, "black", "middle", &point.time.format("%H:%M").to_string()));
+        }
+
+        let midnight = point.time.date_naive().and_time(NaiveTime::MIN).and_local_timezone(point.time.timezone()).single();
+        if let Some(midnight) = midnight.filter(|midnight| i > 0 && *midnight > points[i - 1].time) {
+            svg.push_str(&format!(
+                r##"<line x1="{0:.1}" y1="{1:.1}" x2="{0:.1}" y2="{2:.1}" stroke="#555555" stroke-width="2" stroke-dasharray="4 4" />"##,
+                x(midnight), plot.y, plot.bottom()
+            ));
+            svg.push_str(&text(x(midnight) + 5.0, plot.y + LABEL_SIZE, LABEL_SIZE, true, "#555555", &midnight.format("%a").to_string()));
+        }
+    }
+
+    let now = now.with_timezone(&first.timezone());
+    if now >= first {
+        svg.push_str(&format!(
+            r#"<line x1="{0:.1}" y1="{1:.1}" x2="{0:.1}" y2="{2:.1}" stroke="black" stroke-width="2" stroke-dasharray="8 4" />"#,
+            x(now), plot.y - 4.0, plot.bottom()
+        ));
+        svg.push_str(&aligned(x(now), plot.y - 8.0, LABEL_SIZE, true, "black", "middle", "now"));
+    }
+
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn hours() -> Vec<HourData> {
+        (0..16).map(|i| HourData {
+            time: Local.with_ymd_and_hms(2024, 6, 3, 1, 0, 0).unwrap().fixed_offset() + Duration::hours(3 * i),
+            temp_c: 10.0 + i as f64,
+            precipitation: 0.0,
+        }).collect()
+    }
+
+    #[test]
+    fn windows_from_the_point_before_now() {
+        let hours = hours();
+        let now = Local.with_ymd_and_hms(2024, 6, 3, 9, 30, 0).unwrap();
+
+        let day = window(&hours, now, Duration::hours(24));
+        assert_eq!(day.first().map(|hour| hour.time.format("%H:%M").to_string()).as_deref(), Some("07:00"));
+        assert_eq!(day.last().map(|hour| hour.time.format("%d %H:%M").to_string()).as_deref(), Some("04 07:00"));
+        assert_eq!(day.len(), 9);
+
+        // Past the end of the forecast
+        assert_eq!(window(&hours, now + Duration::days(7), Duration::hours(24)).len(), 1);
+    }
+
+    fn coordinates(svg: &str) -> Vec<(&str, f64)> {
+        // Every x or y attribute, and each point of the path
+        let attributes = regex::Regex::new(r#" (x|y)[12]?="([^"]+)""#).unwrap();
+        let path = regex::Regex::new(r#"d="M([^"]+)""#).unwrap();
+
+        let mut found: Vec<(&str, f64)> = attributes.captures_iter(svg)
+            .map(|c| (c.get(1).unwrap().as_str(), c[2].parse().unwrap()))
+            .collect();
+        for points in path.captures_iter(svg) {
+            for point in points.get(1).unwrap().as_str().split('L') {
+                let (x, y) = point.split_once(' ').unwrap();
+                found.push(("x", x.parse().unwrap()));
+                found.push(("y", y.parse().unwrap()));
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn draws_temperature_rain_and_now_within_its_area() {
+        let mut hours = hours();
+        hours[4].precipitation = 2.4;
+        hours[5].precipitation = 1.0;
+        let forecast = Forecast { hours, ..Default::default() };
+        let area = Rect { x: 605.0, y: 210.0, width: 585.0, height: 300.0 };
+        let now = Local.with_ymd_and_hms(2024, 6, 3, 9, 30, 0).unwrap();
+
+        let svg = draw(Some(&forecast), area, now, &ChartConfig::default());
+
+        assert!(svg.contains(">Next 24 hours<"));
+        assert!(svg.contains(">now<") && svg.contains(r#"stroke-dasharray="8 4""#));
+        assert_eq!(svg.matches(r#"fill="url(#widget-hatch)""#).count(), 2);
+        assert_eq!(svg.matches("<path").count(), 1);
+        for label in [">10°<", ">15°<", ">20°<", ">3mm<", ">0<"] {
+            assert!(svg.contains(label), "missing {label} in {svg}");
+        }
+
+        // Inset by 15, and the temperature line starts after the left hand axis
+        assert!(svg.contains(r#"<path d="M666.0 "#));
+        for (axis, value) in coordinates(&svg) {
+            let (from, to) = if axis == "x" {(area.x, area.x + area.width)} else {(area.y, area.bottom())};
+            assert!((from..=to).contains(&value), "{axis} {value} outside {area:?}");
+        }
+    }
+
+    #[test]
+    fn copes_with_points_at_the_same_time() {
+        let mut hours = hours();
+        hours[1].time = hours[0].time;
+        let forecast = Forecast { hours: hours[..2].to_vec(), ..Default::default() };
+        let now = Local.with_ymd_and_hms(2024, 6, 2, 23, 0, 0).unwrap();
+
+        let svg = draw(Some(&forecast), Rect { x: 0.0, y: 0.0, width: 585.0, height: 300.0 }, now, &ChartConfig::default());
+        assert!(svg.contains("<path") && !svg.contains("NaN"));
+    }
+
+    #[test]
+    fn rounds_temperatures_out_to_ticks() {
+        assert_eq!(ticks(11.2, 18.9), [10.0, 15.0, 20.0]);
+        assert_eq!(ticks(-3.0, 24.0), [-10.0, 0.0, 10.0, 20.0, 30.0]);
+        assert_eq!(ticks(15.0, 15.0), [15.0, 20.0]);
+    }
+}
@@ -0,0 +1,72 @@
+use super::{aligned, fit, text, time_between, Rect};
+use crate::calendar::Agenda;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveTime};
+
+const NAME_SIZE: f64 = 26.0;
+const DATE_SIZE: f64 = 20.0;
+const WHEN_WIDTH: f64 = 170.0;
+
+pub fn days_until(date: NaiveDate, now: DateTime<Local>) -> String {
+    // Whole days up to a month away, whole calendar months and years after that
+    let days = (date - now.date_naive()).num_days();
+    let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN).and_local_timezone(Local).earliest();
+
+    match days {
+        0 => "today".to_string(),
+        1 => "tomorrow".to_string(),
+        2..=31 => format!("in {days} days"),
+        _ => match (midnight(now.date_naive()), midnight(date)) {
+            (Some(today), Some(date)) => format!("in {}", time_between(today, date)),
+            _ => format!("in {days} days")
+        }
+    }
+}
+
+pub fn draw(agenda: Option<&Agenda>, area: Rect, now: DateTime<Local>) -> String {
+    let area = area.inset(15.0);
+
+    let countdowns = match agenda {
+        Some(agenda) if !agenda.countdowns.is_empty() => &agenda.countdowns,
+        Some(_) => return text(area.x, area.y + NAME_SIZE, NAME_SIZE, true, "black", "Nothing to count down to"),
+        None => return text(area.x, area.y + NAME_SIZE, NAME_SIZE, true, "black", "Could not fetch any events")
+    };
+
+    let mut svg = String::new();
+    let mut y = area.y;
+
+    // The list is from the last calendar fetch, which may have been before midnight
+    for countdown in countdowns.iter().filter(|countdown| countdown.date >= now.date_naive()) {
+        if y + NAME_SIZE * 1.4 + DATE_SIZE * 1.2 > area.bottom() {
+            break
+        }
+
+        y += NAME_SIZE * 1.4;
+        svg.push_str(&text(area.x, y, NAME_SIZE, true, "black", &fit(&countdown.name, area.width - WHEN_WIDTH, NAME_SIZE)));
+        svg.push_str(&aligned(area.x + area.width, y, NAME_SIZE, false, "black", "end", &days_until(countdown.date, now)));
+
+        y += DATE_SIZE * 1.2;
+        svg.push_str(&text(area.x, y, DATE_SIZE, false, "#555555", &countdown.date.format("%a %-d %b %Y").to_string()));
+    }
+
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn counts_days_then_calendar_months() {
+        let now = Local.with_ymd_and_hms(2024, 6, 3, 21, 0, 0).unwrap();
+        let date = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+
+        assert_eq!(days_until(date(6, 3), now), "today");
+        assert_eq!(days_until(date(6, 4), now), "tomorrow");
+        assert_eq!(days_until(date(6, 15), now), "in 12 days");
+        assert_eq!(days_until(date(7, 10), now), "in 1 month");
+        assert_eq!(days_until(date(12, 2), now), "in 5 months");
+        assert_eq!(days_until(NaiveDate::from_ymd_opt(2025, 6, 3).unwrap(), now), "in 1 year");
+    }
+}
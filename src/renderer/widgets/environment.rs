@@ -0,0 +1,90 @@
+use super::{aligned, text, Rect};
+use crate::environment::{Environment, Reading};
+
+const LABEL_SIZE: f64 = 20.0;
+const VALUE_SIZE: f64 = 40.0;
+const CATEGORY_SIZE: f64 = 22.0;
+
+fn column(x: f64, y: f64, label: &str, value: String, reading: Option<&Reading>) -> String {
+    let mut svg = aligned(x, y + LABEL_SIZE, LABEL_SIZE, false, "#555555", "middle", label);
+
+    match reading {
+        Some(reading) => {
+            svg.push_str(&aligned(x, y + LABEL_SIZE + VALUE_SIZE, VALUE_SIZE, true, "black", "middle", &value));
+            let category = match &reading.detail {
+                Some(detail) => format!("{} · {detail}", reading.category),
+                None => reading.category.to_string()
+            };
+            svg.push_str(&aligned(x, y + LABEL_SIZE + VALUE_SIZE + CATEGORY_SIZE * 1.3, CATEGORY_SIZE, true, "black", "middle", &category));
+        },
+        None => svg.push_str(&aligned(x, y + LABEL_SIZE + VALUE_SIZE, VALUE_SIZE, true, "#aaaaaa", "middle", "-"))
+    }
+
+    svg
+}
+
+pub fn draw(environment: Option<&Environment>, area: Rect) -> String {
+    let area = area.inset(15.0);
+
+    let environment = match environment {
+        Some(environment) => environment,
+        None => return text(area.x, area.y + CATEGORY_SIZE, CATEGORY_SIZE, true, "black", "Could not fetch UV, air quality or pollen")
+    };
+
+    // Side by side, as the answers to "is it nice out"
+    let width = area.width / 3.0;
+    let x = |i: f64| area.x + width * (i + 0.5);
+
+    let uv = environment.uv.as_ref();
+    let air = environment.air_quality.as_ref();
+    let pollen = environment.pollen.as_ref();
+
+    [
+        column(x(0.0), area.y, "UV index", uv.map_or(String::new(), |uv| format!("{:.0}", uv.value)), uv),
+        column(x(1.0), area.y, "Air quality", air.map_or(String::new(), |air| format!("{:.0}/5", air.value)), air),
+        column(x(2.0), area.y, "Pollen", pollen.map_or(String::new(), |pollen| format!("{:.0}", pollen.value)), pollen),
+    ].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect { x: 605.0, y: 210.0, width: 585.0, height: 150.0 };
+
+    fn reading(value: f64, category: &'static str, detail: Option<&str>) -> Option<Reading> {
+        Some(Reading { value, category, detail: detail.map(str::to_string) })
+    }
+
+    #[test]
+    fn draws_each_reading_in_its_column() {
+        let environment = Environment {
+            uv: reading(7.4, "High", None),
+            air_quality: reading(2.0, "Fair", Some("PM2.5 12")),
+            pollen: reading(31.0, "Moderate", Some("grass")),
+        };
+        let svg = draw(Some(&environment), AREA);
+
+        for value in [">7<", ">2/5<", ">31<", ">High<", ">Fair · PM2.5 12<", ">Moderate · grass<"] {
+            assert!(svg.contains(value), "missing {value} in {svg}");
+        }
+        assert!(!svg.contains(">-<"));
+    }
+
+    #[test]
+    fn dashes_a_missing_reading() {
+        let environment = Environment { uv: reading(3.0, "Moderate", None), air_quality: None, pollen: None };
+        let svg = draw(Some(&environment), AREA);
+
+        assert_eq!(svg.matches(">-<").count(), 2);
+        assert!(svg.contains(">Air quality<") && svg.contains(">Pollen<"));
+        assert!(svg.contains(">3<"));
+    }
+
+    #[test]
+    fn says_when_nothing_could_be_fetched() {
+        let svg = draw(None, AREA);
+        assert!(svg.contains(">Could not fetch UV, air quality or pollen<"));
+        assert!(!svg.contains("UV index"));
+    }
+}
@@ -0,0 +1,195 @@
+use super::{aligned, marker, text, Rect};
+use crate::calendar::Agenda;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Weekday};
+use serde::Deserialize;
+
+use log::warn;
+
+const HEADING_SIZE: f64 = 30.0;
+const WEEKDAY_SIZE: f64 = 20.0;
+const WEEK_NUMBER_WIDTH: f64 = 44.0;
+
+/* From sensitive/display.json, eg {"month": {"week_start": "sunday", "week_numbers": true}} */
+#[derive(Deserialize, Debug, Clone)]
+pub struct MonthConfig {
+    #[serde(default = "default_week_start")]
+    week_start: String,
+    /* ISO week numbers down the left */
+    #[serde(default)]
+    week_numbers: bool,
+}
+
+fn default_week_start() -> String {"monday".to_string()}
+
+impl Default for MonthConfig {
+    fn default() -> MonthConfig {
+        MonthConfig { week_start: default_week_start(), week_numbers: false }
+    }
+}
+
+impl MonthConfig {
+    fn week_start(&self) -> Weekday {
+        self.week_start.parse().unwrap_or_else(|_| {
+            warn!("Unknown week_start {} in sensitive/display.json, using monday", self.week_start);
+            Weekday::Mon
+        })
+    }
+}
+
+pub fn weeks(today: NaiveDate, week_start: Weekday) -> Vec<[Option<NaiveDate>; 7]> {
+    // The rows of this month, with the days of the months either side left empty
+    let first = today.with_day(1).unwrap_or(today);
+    let offset = (first.weekday().num_days_from_monday() + 7 - week_start.num_days_from_monday()) % 7;
+    let mut start = first - Duration::days(offset as i64);
+
+    let mut weeks = vec![];
+    while start <= first || start.month() == first.month() {
+        let mut week = [None; 7];
+        for (i, day) in week.iter_mut().enumerate() {
+            let date = start + Duration::days(i as i64);
+            if date.month() == first.month() {
+                *day = Some(date)
+            }
+        }
+        weeks.push(week);
+        start += Duration::days(7);
+    }
+    weeks
+}
+
+fn week_number(week: &[Option<NaiveDate>; 7]) -> Option<u32> {
+    // Whichever week the row's monday is in, for rows that start on other days
+    week.iter().flatten().find(|date| date.weekday() == Weekday::Mon)
+        .or(week.iter().flatten().next())
+        .map(|date| date.iso_week().week())
+}
+
+pub fn draw(agenda: Option<&Agenda>, area: Rect, now: DateTime<Local>, config: &MonthConfig) -> String {
+    let area = area.inset(15.0);
+    let today = now.date_naive();
+    let weeks = weeks(today, config.week_start());
+
+    let mut svg = text(area.x, area.y + HEADING_SIZE, HEADING_SIZE, true, "black", &today.format("%B %Y").to_string());
+
+    let left = area.x + if config.week_numbers {WEEK_NUMBER_WIDTH} else {0.0};
+    let column = (area.x + area.width - left) / 7.0;
+    let centre = |i: usize| left + column * (i as f64 + 0.5);
+
+    let header = area.y + HEADING_SIZE + 12.0 + WEEKDAY_SIZE;
+    let start = config.week_start();
+    for i in 0..7 {
+        let weekday = NaiveDate::from_isoywd_opt(2024, 1, start).map(|date| date + Duration::days(i as i64));
+        let name = weekday.map(|date| date.format("%a").to_string()).unwrap_or_default();
+        svg.push_str(&aligned(centre(i), header, WEEKDAY_SIZE, true, "black", "middle", &name));
+    }
+
+    let top = header + 10.0;
+    svg.push_str(&format!(r#"<line x1="{:.1}" y1="{top:.1}" x2="{:.1}" y2="{top:.1}" stroke="black" stroke-width="2" />"#, area.x, area.x + area.width));
+
+    // Square cells if there is room, squashed rows if not
+    let row = ((area.bottom() - top) / weeks.len() as f64).min(column);
+    let number_size = (row * 0.45).min(30.0);
+    let marker_size = (row * 0.2).min(14.0);
+
+    for (w, week) in weeks.iter().enumerate() {
+        let y = top + row * w as f64;
+        let baseline = y + row * 0.5;
+
+        if config.week_numbers {
+            if let Some(number) = week_number(week) {
+                svg.push_str(&aligned(area.x + WEEK_NUMBER_WIDTH / 2.0, baseline, number_size * 0.7, false, "#555555", "middle", &number.to_string()));
+            }
+        }
+
+        for (i, date) in week.iter().enumerate() {
+            let date = match date {
+                Some(date) => *date,
+                None => continue
+            };
+
+            if date == today {
+                svg.push_str(&format!(
+                    r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="none" stroke="black" stroke-width="3" />"#,
+                    left + column * i as f64 + 2.0, y + 2.0, column - 4.0, row - 4.0
+                ));
+            }
+            let fill = if date < today {"#777777"} else {"black"};
+            svg.push_str(&aligned(centre(i), baseline, number_size, date == today, fill, "middle", &date.day().to_string()));
+
+            let markers = day_markers(agenda.and_then(|agenda| agenda.busy_days.get(&date)).map_or(&[], |busy| busy.as_slice()));
+
+            let width = markers.len() as f64 * marker_size * 1.4 - marker_size * 0.4;
+            for (m, style) in markers.iter().enumerate() {
+                let x = centre(i) - width / 2.0 + m as f64 * marker_size * 1.4;
+                svg.push_str(&marker(style, x, baseline + row * 0.35, marker_size));
+            }
+        }
+    }
+
+    svg
+}
+
+fn day_markers(busy: &[Option<String>]) -> Vec<&str> {
+    // One marker per calendar with something on, in the order first seen, plain dots for calendars without one
+    let mut markers = vec![];
+    for marker in busy.iter().map(|marker| marker.as_deref().unwrap_or("circle")) {
+        if !markers.contains(&marker) {
+            markers.push(marker);
+        }
+    }
+    markers.truncate(3);
+    markers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn first_week(weeks: &[[Option<NaiveDate>; 7]]) -> Vec<Option<u32>> {
+        weeks[0].iter().map(|date| date.map(|date| date.day())).collect()
+    }
+
+    #[test]
+    fn lays_out_weeks_from_either_start_day() {
+        // June 2024 starts on a saturday and ends on a sunday
+        let monday = weeks(date(2024, 6, 18), Weekday::Mon);
+        assert_eq!(monday.len(), 5);
+        assert_eq!(first_week(&monday), [None, None, None, None, None, Some(1), Some(2)]);
+        assert_eq!(monday[4][6], Some(date(2024, 6, 30)));
+
+        let sunday = weeks(date(2024, 6, 18), Weekday::Sun);
+        assert_eq!(sunday.len(), 6);
+        assert_eq!(first_week(&sunday), [None, None, None, None, None, None, Some(1)]);
+        assert_eq!(sunday[5][0], Some(date(2024, 6, 30)));
+    }
+
+    #[test]
+    fn numbers_weeks_by_iso_week() {
+        // The 30th of december 2024 is in week 1 of 2025
+        let december = weeks(date(2024, 12, 1), Weekday::Mon);
+        assert_eq!(december.iter().map(week_number).collect::<Vec<_>>(), [Some(48), Some(49), Some(50), Some(51), Some(52), Some(1)]);
+
+        let sunday = weeks(date(2024, 12, 1), Weekday::Sun);
+        assert_eq!(week_number(&sunday[0]), Some(49));
+    }
+
+    #[test]
+    fn marks_each_calendar_once() {
+        let busy = ["hatch", "dots", "hatch", "solid", "dots", "triangle"].map(|marker| Some(marker.to_string()));
+        assert_eq!(day_markers(&busy), ["hatch", "dots", "solid"]);
+        assert_eq!(day_markers(&[None, Some("hatch".to_string()), None]), ["circle", "hatch"]);
+        assert!(day_markers(&[]).is_empty());
+    }
+
+    #[test]
+    fn reads_week_start() {
+        let config: MonthConfig = serde_json::from_str(r#"{"week_start": "Sunday"}"#).unwrap();
+        assert_eq!(config.week_start(), Weekday::Sun);
+        assert_eq!(MonthConfig::default().week_start(), Weekday::Mon);
+    }
+}
@@ -0,0 +1,366 @@
+use serde::Deserialize;
+use serde_json::from_reader;
+use std::fs::File;
+
+use crate::status;
+
+use chrono::{Duration, Local, NaiveDateTime, NaiveTime};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration as stdDuration;
+
+use log::warn;
+
+/*
+    Each data source gets its own refresh cadence, aligned to local midnight so that a 15 minute
+    source still refreshes on the quarter hour (which the clock relies on). Optionally, nothing is
+    refreshed during quiet hours.
+
+    Configured in sensitive/schedule.json (re-read every cycle), eg:
+    {"intervals": {"radar": 10, "news": 60}, "quiet_hours": {"start": "23:00", "end": "06:00"}}
+    where intervals are in minutes, and any source not listed keeps its default.
+
+    While a weather warning is active, the warnings, radar and current conditions refresh at least
+    every "warning_minutes" (5 by default).
+*/
+
+pub const SOURCES: [&str; 13] = [
+    "weather", "conditions", "environment", "radar", "warnings", "news", "calendar",
+    "spx", "btc", "yield_spread", "linux_share", "halving", "kernel_version"
+];
+
+fn default_interval(source: &str) -> i64 {
+    match source {
+        "radar" => 10,
+        "news" | "environment" => 60,
+        "halving" | "kernel_version" => 24 * 60,
+        _ => 15
+    }
+}
+
+// Failed fetches are retried sooner than a long interval would allow
+const RETRY_MINUTES: i64 = 15;
+
+const WARNING_SOURCES: [&str; 3] = ["warnings", "radar", "conditions"];
+const WARNING_MINUTES: i64 = 5;
+
+#[derive(Deserialize, Debug, Default)]
+struct QuietHours {
+    start: String,
+    end: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ScheduleConfig {
+    #[serde(default)]
+    intervals: HashMap<String, i64>,
+    quiet_hours: Option<QuietHours>,
+    warning_minutes: Option<i64>,
+}
+
+fn read_config() -> ScheduleConfig {
+    let file = match File::open("sensitive/schedule.json") {
+        Ok(file) => file,
+        Err(_) => return ScheduleConfig::default()
+    };
+
+    match from_reader(file) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Could not parse sensitive/schedule.json, using the default schedule: {e}");
+            ScheduleConfig::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Attempt {
+    at: NaiveDateTime,
+    ok: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    config: ScheduleConfig,
+    attempts: HashMap<String, Attempt>,
+
+    /* Calendar reminders as (when it is due, when its event starts), refreshed every minute in between */
+    reminders: Vec<(NaiveDateTime, NaiveDateTime)>,
+
+    /* Whether a weather warning is active, which shortens some intervals */
+    warning: bool,
+}
+
+fn next_boundary(after: NaiveDateTime, interval: Duration) -> NaiveDateTime {
+    let midnight = after.date().and_time(NaiveTime::MIN);
+    let elapsed = (after - midnight).num_seconds();
+    let interval = interval.num_seconds().max(60);
+    midnight + Duration::seconds((elapsed / interval + 1) * interval)
+}
+
+fn parse_time(s: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M").ok()
+}
+
+pub fn now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+// What when_due gives back for a timed, timeout wrapped fetch
+pub type Fetched<T, E, TE> = Option<(Result<Result<T, E>, TE>, stdDuration)>;
+
+pub async fn when_due<F: Future>(due: bool, future: F) -> Option<F::Output> {
+    if due {Some(future.await)} else {None}
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler { config: read_config(), ..Default::default() }
+    }
+
+    pub fn reload(&mut self) {
+        self.config = read_config();
+    }
+
+    pub fn interval(&self, source: &str) -> Duration {
+        let minutes = self.config.intervals.get(source).copied().unwrap_or_else(|| default_interval(source));
+        let interval = Duration::minutes(minutes.max(1));
+
+        if self.warning && WARNING_SOURCES.contains(&source) {
+            interval.min(Duration::minutes(self.config.warning_minutes.unwrap_or(WARNING_MINUTES).max(1)))
+        } else {
+            interval
+        }
+    }
+
+    fn due_at(&self, source: &str) -> Option<NaiveDateTime> {
+        let attempt = self.attempts.get(source)?;
+        let interval = self.interval(source);
+
+        let interval = if attempt.ok {interval} else {interval.min(Duration::minutes(RETRY_MINUTES))};
+        Some(next_boundary(attempt.at, interval))
+    }
+
+    pub fn is_due(&self, source: &str, now: NaiveDateTime) -> bool {
+        match self.due_at(source) {
+            Some(due) => now >= due,
+            None => true
+        }
+    }
+
+    pub fn mark(&mut self, source: &str, at: NaiveDateTime, ok: bool) {
+        self.attempts.insert(source.to_string(), Attempt { at, ok });
+    }
+
+    pub fn settle<T, E, TE>(&mut self, source: &str, at: NaiveDateTime, fetched: Fetched<T, E, TE>) -> Option<Option<T>>
+    where E: Display + From<String>, TE: Display {
+        /*
+            Takes the (timed, timeout wrapped) result of a fetch made with when_due.
+            None if the source was not due, otherwise what should now be displayed.
+        */
+        let (result, took) = fetched?;
+
+        let result = match result {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
+        match &result {Ok(_) => {}, Err(e) => warn!("{source} failed: {e}")}
+
+        status::record(source, took, &result);
+        self.mark(source, at, result.is_ok());
+        Some(result.ok())
+    }
+
+    pub fn set_reminders(&mut self, reminders: Vec<(NaiveDateTime, NaiveDateTime)>) {
+        self.reminders = reminders;
+    }
+
+    pub fn set_warning(&mut self, active: bool) {
+        self.warning = active;
+    }
+
+    pub fn force(&mut self) {
+        self.attempts.clear();
+    }
+
+    fn quiet_hours(&self) -> Option<(NaiveTime, NaiveTime)> {
+        let quiet_hours = self.config.quiet_hours.as_ref()?;
+
+        match (parse_time(&quiet_hours.start), parse_time(&quiet_hours.end)) {
+            (Some(start), Some(end)) => Some((start, end)),
+            _ => {
+                warn!("Quiet hours should be given as HH:MM, ignoring them");
+                None
+            }
+        }
+    }
+
+    pub fn is_quiet(&self, now: NaiveDateTime) -> bool {
+        match self.quiet_hours() {
+            Some((start, end)) => {
+                let time = now.time();
+                if start <= end {
+                    time >= start && time < end
+                } else {
+                    time >= start || time < end
+                }
+            },
+            None => false
+        }
+    }
+
+    fn next_time(now: NaiveDateTime, time: NaiveTime) -> NaiveDateTime {
+        let today = now.date().and_time(time);
+        if today > now {today} else {today + Duration::days(1)}
+    }
+
+    pub fn quiet_until(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        if !self.is_quiet(now) {
+            return None
+        }
+        self.quiet_hours().map(|(_, end)| Self::next_time(now, end))
+    }
+
+    pub fn next_wake(&self, now: NaiveDateTime) -> NaiveDateTime {
+        if let Some(until) = self.quiet_until(now) {
+            return until
+        }
+
+        let mut wake = SOURCES.iter()
+            .map(|source| self.due_at(source).unwrap_or(now))
+            .min()
+            .unwrap_or(now);
+
+        // Put reminders up when they are due, then count down to their event every minute
+        for (due, start) in &self.reminders {
+            if now < *due {
+                wake = wake.min(*due);
+            } else if now < *start {
+                wake = wake.min(next_boundary(now, Duration::minutes(1)));
+            }
+        }
+
+        // Wake up for the start of quiet hours too, to show the night screen
+        if let Some((start, _)) = self.quiet_hours() {
+            wake = wake.min(Self::next_time(now, start));
+        }
+
+        wake
+    }
+
+    pub fn seconds_until_next_wake(&self) -> u64 {
+        let now = now();
+        let wake = self.next_wake(now);
+        (wake - now).num_seconds().max(1) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn scheduler(config: &str) -> Scheduler {
+        Scheduler { config: serde_json::from_str(config).unwrap(), ..Default::default() }
+    }
+
+    #[test]
+    fn aligns_to_midnight() {
+        assert_eq!(next_boundary(at("2024-05-30 14:02"), Duration::minutes(15)), at("2024-05-30 14:15"));
+        assert_eq!(next_boundary(at("2024-05-30 14:15"), Duration::minutes(15)), at("2024-05-30 14:30"));
+        assert_eq!(next_boundary(at("2024-05-30 01:00"), Duration::minutes(90)), at("2024-05-30 01:30"));
+        assert_eq!(next_boundary(at("2024-05-30 23:50"), Duration::minutes(60)), at("2024-05-31 00:00"));
+        assert_eq!(next_boundary(at("2024-05-30 09:00"), Duration::hours(24)), at("2024-05-31 00:00"));
+
+        // Never more often than every minute
+        assert_eq!(next_boundary(at("2024-05-30 09:00"), Duration::seconds(5)), at("2024-05-30 09:01"));
+    }
+
+    #[test]
+    fn retries_failures_sooner() {
+        let mut scheduler = scheduler("{}");
+        assert!(scheduler.is_due("halving", at("2024-05-30 14:02")));
+
+        scheduler.mark("halving", at("2024-05-30 14:02"), true);
+        assert_eq!(scheduler.due_at("halving"), Some(at("2024-05-31 00:00")));
+        assert!(!scheduler.is_due("halving", at("2024-05-30 14:15")));
+
+        scheduler.mark("halving", at("2024-05-30 14:02"), false);
+        assert_eq!(scheduler.due_at("halving"), Some(at("2024-05-30 14:15")));
+        assert!(scheduler.is_due("halving", at("2024-05-30 14:15")));
+
+        // Radar is already more often than a retry would be
+        scheduler.mark("radar", at("2024-05-30 14:02"), false);
+        assert_eq!(scheduler.due_at("radar"), Some(at("2024-05-30 14:10")));
+    }
+
+    #[test]
+    fn quiet_hours_span_midnight() {
+        let scheduler = scheduler(r#"{"quiet_hours": {"start": "23:00", "end": "06:00"}}"#);
+
+        assert!(scheduler.is_quiet(at("2024-05-30 23:30")));
+        assert!(scheduler.is_quiet(at("2024-05-31 05:59")));
+        assert!(!scheduler.is_quiet(at("2024-05-31 06:00")));
+        assert!(!scheduler.is_quiet(at("2024-05-30 22:59")));
+
+        assert_eq!(scheduler.quiet_until(at("2024-05-30 23:30")), Some(at("2024-05-31 06:00")));
+        assert_eq!(scheduler.quiet_until(at("2024-05-31 01:00")), Some(at("2024-05-31 06:00")));
+        assert_eq!(scheduler.quiet_until(at("2024-05-31 12:00")), None);
+
+        let daytime = self::scheduler(r#"{"quiet_hours": {"start": "13:00", "end": "14:00"}}"#);
+        assert!(daytime.is_quiet(at("2024-05-30 13:30")));
+        assert!(!daytime.is_quiet(at("2024-05-30 23:30")));
+
+        assert!(!self::scheduler(r#"{"quiet_hours": {"start": "11pm", "end": "6am"}}"#).is_quiet(at("2024-05-30 23:30")));
+    }
+
+    #[test]
+    fn wakes_for_the_soonest_of_sources_reminders_and_quiet_hours() {
+        let mut scheduler = scheduler(r#"{"quiet_hours": {"start": "23:00", "end": "06:00"}}"#);
+        let now = at("2024-05-30 14:02");
+
+        // Anything never fetched is due straight away
+        assert_eq!(scheduler.next_wake(now), now);
+
+        for source in SOURCES {
+            scheduler.mark(source, now, true);
+        }
+        assert_eq!(scheduler.next_wake(now), at("2024-05-30 14:10"));
+
+        scheduler.set_reminders(vec![(at("2024-05-30 14:07"), at("2024-05-30 14:30"))]);
+        assert_eq!(scheduler.next_wake(now), at("2024-05-30 14:07"));
+        assert_eq!(scheduler.next_wake(at("2024-05-30 14:08")), at("2024-05-30 14:09"));
+        assert_eq!(scheduler.next_wake(at("2024-05-30 14:30")), at("2024-05-30 14:10"));
+
+        let now = at("2024-05-30 22:55");
+        scheduler.set_reminders(vec![]);
+        for source in SOURCES {
+            scheduler.mark(source, now, true);
+        }
+        assert_eq!(scheduler.next_wake(now), at("2024-05-30 23:00"));
+        assert_eq!(scheduler.next_wake(at("2024-05-30 23:00")), at("2024-05-31 06:00"));
+    }
+
+    #[test]
+    fn warnings_shorten_their_sources_intervals() {
+        let mut scheduler = scheduler(r#"{"intervals": {"radar": 30}}"#);
+        scheduler.mark("radar", at("2024-05-30 14:02"), true);
+        scheduler.mark("news", at("2024-05-30 14:02"), true);
+
+        assert_eq!(scheduler.due_at("radar"), Some(at("2024-05-30 14:30")));
+
+        scheduler.set_warning(true);
+        assert_eq!(scheduler.due_at("radar"), Some(at("2024-05-30 14:05")));
+        assert_eq!(scheduler.interval("news"), Duration::minutes(60));
+
+        // A failed fetch leaves no warnings shown, so (as in refresh) the warning is lifted rather than kept
+        let failed: Fetched<Vec<String>, String, String> = Some((Ok(Err("Could not fetch warnings".to_string())), stdDuration::from_secs(1)));
+        let shown = scheduler.settle("warnings", at("2024-05-30 14:05"), failed);
+        assert_eq!(shown, Some(None));
+
+        scheduler.set_warning(shown.flatten().is_some_and(|warnings| !warnings.is_empty()));
+        assert_eq!(scheduler.due_at("radar"), Some(at("2024-05-30 14:30")));
+        assert_eq!(scheduler.due_at("warnings"), Some(at("2024-05-30 14:15")));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::status;
+use crate::metrics;
+
+use serde::Deserialize;
+use serde_json::from_reader;
+use std::fs::File;
+use std::sync::Mutex;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use log::{info, warn};
+
+/*
+    A (very) small http server to check on the dashboard without having to ssh in.
+
+    GET  /status      per source fetch status, timings and last error as json
+    GET  /output.png  the last image sent to eips
+    GET  /output.svg  the last composed svg
+    GET  /metrics     fetch/render counters and timings in the prometheus text format
+    POST /refresh     skip the wait and run the next cycle now
+
+    Only enabled when sensitive/server.json exists, eg {"address": "0.0.0.0:8080"}
+*/
+
+#[derive(Deserialize, Debug)]
+struct ServerConfig {
+    address: String,
+}
+
+fn read_config() -> Option<ServerConfig> {
+    let file = File::open("sensitive/server.json").ok()?;
+
+    match from_reader(file) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            warn!("Could not parse sensitive/server.json: {e}");
+            None
+        }
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: &'static str, content_type: &'static str, body: Vec<u8>) -> Response {
+        Response { status, content_type, body }
+    }
+
+    fn text(status: &'static str, body: &str) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", body.as_bytes().to_vec())
+    }
+}
+
+fn route(method: &str, path: &str) -> Response {
+    match (method, path) {
+        ("GET", "/") => Response::text("200 OK", "GET /status, /metrics, /output.png, /output.svg or POST /refresh\n"),
+        ("GET", "/status") => {
+            Response::new("200 OK", "application/json", status::to_json().to_string().into_bytes())
+        },
+        ("GET", "/metrics") => {
+            Response::new("200 OK", "text/plain; version=0.0.4", metrics::render().into_bytes())
+        },
+        ("GET", "/output.png") => match status::get().last_png.clone() {
+            Some(png) => Response::new("200 OK", "image/png", png),
+            None => Response::text("404 Not Found", "Nothing rendered yet\n"),
+        },
+        ("GET", "/output.svg") => match status::get().last_svg.clone() {
+            Some(svg) => Response::new("200 OK", "image/svg+xml", svg.into_bytes()),
+            None => Response::text("404 Not Found", "Nothing rendered yet\n"),
+        },
+        ("POST", "/refresh") => {
+            info!("Refresh requested over http");
+            status::request_refresh();
+            Response::text("202 Accepted", "Refreshing\n")
+        },
+        (_, "/" | "/status" | "/metrics" | "/output.png" | "/output.svg" | "/refresh") => {
+            Response::text("405 Method Not Allowed", "Method not allowed\n")
+        },
+        _ => Response::text("404 Not Found", "Not found\n"),
+    }
+}
+
+async fn handle(stream: TcpStream) -> Result<(), std::io::Error> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    // Headers (and any body) are not needed for any of the routes, but must be read past
+    let mut header = String::new();
+    while reader.read_line(&mut header).await? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+    let path = path.split('?').next().unwrap_or("");
+
+    let response = route(method, path);
+
+    let mut stream = reader.into_inner();
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, response.content_type, response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
+
+pub async fn serve(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _addr)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream).await {
+                        warn!("Status server request failed: {e}");
+                    }
+                });
+            },
+            Err(e) => warn!("Status server could not accept connection: {e}"),
+        }
+    }
+}
+
+static SERVER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+pub async fn reload() {
+    // (Re)starts the server from sensitive/server.json, so it can be changed with a SIGHUP
+    let previous = SERVER.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(previous) = previous {
+        previous.abort();
+        previous.await.ok();
+    }
+
+    let config = match read_config() {
+        Some(config) => config,
+        None => return
+    };
+
+    match TcpListener::bind(&config.address).await {
+        Ok(listener) => {
+            info!("Status server listening on {}", config.address);
+            *SERVER.lock().unwrap_or_else(|e| e.into_inner()) = Some(tokio::spawn(serve(listener)));
+        },
+        Err(e) => warn!("Could not bind status server to {}: {e}", config.address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn spawn_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener));
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn serves_status_and_frames() {
+        let url = spawn_server().await;
+
+        status::record("news", Duration::from_millis(12), &Err::<(), _>("Request failed with status: 429"));
+        status::record_frame("<svg/>".to_string(), vec![137, 80, 78, 71]);
+
+        let response = reqwest::get(format!("{url}/status")).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let json: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(json["sources"]["news"]["ok"], false);
+        assert_eq!(json["sources"]["news"]["last_duration_ms"], 12);
+        assert_eq!(json["sources"]["news"]["last_error"], "Request failed with status: 429");
+
+        let png = reqwest::get(format!("{url}/output.png")).await.unwrap();
+        assert_eq!(png.headers()["content-type"], "image/png");
+        assert_eq!(png.bytes().await.unwrap().as_ref(), &[137, 80, 78, 71]);
+
+        let svg = reqwest::get(format!("{url}/output.svg")).await.unwrap();
+        assert_eq!(svg.text().await.unwrap(), "<svg/>");
+
+        let metrics = reqwest::get(format!("{url}/metrics")).await.unwrap().text().await.unwrap();
+        assert!(metrics.contains("kindle_fetch_total{source=\"news\",result=\"failure\"}"));
+        assert!(metrics.contains("kindle_fetch_last_duration_seconds{source=\"news\"} 0.012"));
+
+        let missing = reqwest::get(format!("{url}/nope")).await.unwrap();
+        assert_eq!(missing.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn post_refresh_wakes_the_main_loop() {
+        let url = spawn_server().await;
+        let client = reqwest::Client::new();
+
+        let wrong_method = client.get(format!("{url}/refresh")).send().await.unwrap();
+        assert_eq!(wrong_method.status(), 405);
+
+        let response = client.post(format!("{url}/refresh")).send().await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        tokio::time::timeout(Duration::from_secs(1), status::refresh_requested()).await
+            .expect("refresh was not requested");
+    }
+}
@@ -0,0 +1,328 @@
+use crate::renderer;
+use crate::server;
+use crate::status;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use tokio::signal::unix::{signal, SignalKind};
+
+use log::{info, warn};
+
+/*
+    Running under the kindle's init (upstart) rather than in a tmux session.
+
+    Upstart supervises the process in the foreground, so there is no forking here. Service mode
+    instead writes a pid file, logs to a rotating file rather than the terminal, and handles:
+
+    SIGTERM / SIGINT  show a "stopped" screen, remove the pid file and exit
+    SIGHUP            reload the config (the status server, then refresh everything else)
+    SIGUSR1           refresh now
+*/
+
+const USAGE: &str = "Usage: kindle_screen [--service] [--pid-file PATH] [--log-file PATH] [--log-size BYTES] [--init-script]";
+
+#[derive(Debug)]
+pub struct Args {
+    pub service: bool,
+    pub init_script: bool,
+    pub pid_file: String,
+    pub log_file: String,
+    pub log_size: u64,
+}
+
+impl Default for Args {
+    fn default() -> Args {
+        Args {
+            service: false,
+            init_script: false,
+            pid_file: "kindle_screen.pid".to_string(),
+            log_file: "kindle_screen.log".to_string(),
+            log_size: 1024 * 1024,
+        }
+    }
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut result = Args::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--service" => result.service = true,
+            "--init-script" => result.init_script = true,
+            "--pid-file" => result.pid_file = args.next().ok_or("--pid-file needs a path")?,
+            "--log-file" => result.log_file = args.next().ok_or("--log-file needs a path")?,
+            "--log-size" => {
+                result.log_size = args.next().ok_or("--log-size needs a size")?
+                    .parse().map_err(|e| format!("Invalid --log-size: {e}"))?
+            },
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ => return Err(format!("Unknown argument {arg}\n{USAGE}"))
+        }
+    }
+
+    Ok(result)
+}
+
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    size: u64,
+    file: File,
+}
+
+impl RotatingFile {
+    /*
+        Keeps the current log under max_size bytes, moving older logs to log.1, log.2, ...
+        The kindle only has a few GB, so we can't let a tmux-less log grow forever.
+    */
+    pub fn new(path: impl AsRef<Path>, max_size: u64, keep: usize) -> io::Result<RotatingFile> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, max_size, keep, size, file })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        for n in (1..self.keep).rev() {
+            if self.rotated(n).exists() {
+                fs::rename(self.rotated(n), self.rotated(n + 1))?;
+            }
+        }
+
+        if self.keep > 0 {
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+pub fn init_logging(args: &Args) -> io::Result<()> {
+    let mut builder = env_logger::Builder::from_default_env();
+
+    if args.service {
+        let file = RotatingFile::new(&args.log_file, args.log_size, 3)?;
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+    }
+
+    builder.init();
+    Ok(())
+}
+
+fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+pub fn write_pid_file(path: &str) -> Result<(), String> {
+    if let Ok(existing) = fs::read_to_string(path) {
+        // Anything but a pid (eg cut short by losing power) is as stale as one that is no longer running
+        let running = existing.trim().parse::<u32>().ok().filter(|&pid| pid != std::process::id() && process_alive(pid));
+        if let Some(pid) = running {
+            return Err(format!("Already running with pid {pid} (from {path})"));
+        }
+        warn!("Removing stale pid file {path}");
+    }
+
+    fs::write(path, format!("{}\n", std::process::id()))
+        .map_err(|e| format!("Could not write pid file {path}: {e}"))
+}
+
+pub fn init_script() -> Result<String, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Could not find executable: {e}"))?;
+    let dir = std::env::current_dir().map_err(|e| format!("Could not find working directory: {e}"))?;
+
+    let exe = exe.display();
+    let dir = dir.display();
+
+    Ok(format!(r#"# Kindle dashboard, save as /etc/upstart/kindle_screen.conf
+# Generated with: {exe} --init-script
+
+description "Kindle dashboard"
+
+start on started lab126_gui
+stop on stopping lab126_gui
+
+respawn
+respawn limit 5 300
+
+pre-start script
+    lipc-set-prop com.lab126.pillow disableEnablePillow disable || true
+end script
+
+chdir {dir}
+exec {exe} --service --pid-file {dir}/kindle_screen.pid --log-file {dir}/kindle_screen.log
+"#))
+}
+
+async fn stop(pid_file: Option<String>) -> ! {
+    info!("Stopping...");
+
+    if let Err(e) = renderer::show_stopped().await {
+        warn!("Could not show the stopped screen: {e}");
+    }
+
+    if let Some(pid_file) = pid_file {
+        fs::remove_file(&pid_file).ok();
+    }
+
+    log::logger().flush();
+    std::process::exit(0)
+}
+
+pub fn handle_signals(pid_file: Option<String>) -> Result<(), String> {
+    let mut sigterm = signal(SignalKind::terminate()).map_err(|e| e.to_string())?;
+    let mut sigint = signal(SignalKind::interrupt()).map_err(|e| e.to_string())?;
+    let mut sighup = signal(SignalKind::hangup()).map_err(|e| e.to_string())?;
+    let mut sigusr1 = signal(SignalKind::user_defined1()).map_err(|e| e.to_string())?;
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = sigterm.recv() => stop(pid_file.clone()).await,
+                _ = sigint.recv() => stop(pid_file.clone()).await,
+                _ = sighup.recv() => {
+                    // Everything else in sensitive/ is read fresh each refresh
+                    info!("SIGHUP, reloading config");
+                    server::reload().await;
+                    status::request_refresh();
+                },
+                _ = sigusr1.recv() => {
+                    info!("SIGUSR1, refreshing now");
+                    status::request_refresh();
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kindle-service-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let parsed = args(&["--service", "--pid-file", "/run/k.pid", "--log-size", "2048"]).unwrap();
+        assert!(parsed.service && !parsed.init_script);
+        assert_eq!((parsed.pid_file.as_str(), parsed.log_file.as_str(), parsed.log_size), ("/run/k.pid", "kindle_screen.log", 2048));
+
+        assert!(args(&["--daemon"]).unwrap_err().starts_with("Unknown argument --daemon"));
+        assert_eq!(args(&["--pid-file"]).unwrap_err(), "--pid-file needs a path");
+        assert_eq!(args(&["--log-size"]).unwrap_err(), "--log-size needs a size");
+        assert!(args(&["--log-size", "big"]).unwrap_err().starts_with("Invalid --log-size"));
+    }
+
+    #[test]
+    fn rotates_at_the_size_limit() {
+        let dir = dir("rotate");
+        let path = dir.join("dash.log");
+        let mut file = RotatingFile::new(&path, 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        // Each line pushes the last over 10 bytes, and only two old logs are kept
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(dir.join("dash.log.1")).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(dir.join("dash.log.2")).unwrap(), "second\n");
+        assert!(!dir.join("dash.log.3").exists());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn writes_the_pid_file() {
+        let dir = dir("pid");
+        let path = dir.join("dash.pid");
+        let path = path.to_str().unwrap();
+
+        write_pid_file(path).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), format!("{}\n", std::process::id()));
+
+        // Our own pid, or one that is no longer running, is replaced
+        write_pid_file(path).unwrap();
+        fs::write(path, "999999999\n").unwrap();
+        write_pid_file(path).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), format!("{}\n", std::process::id()));
+
+        // Another live process (init) is not
+        fs::write(path, "1\n").unwrap();
+        assert!(write_pid_file(path).unwrap_err().starts_with("Already running with pid 1"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn replaces_an_empty_pid_file() {
+        let dir = dir("empty-pid");
+        let path = dir.join("dash.pid");
+        let path = path.to_str().unwrap();
+
+        for contents in ["", " \n"] {
+            fs::write(path, contents).unwrap();
+            write_pid_file(path).unwrap();
+            assert_eq!(fs::read_to_string(path).unwrap(), format!("{}\n", std::process::id()));
+        }
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn replaces_a_pid_file_without_a_pid() {
+        let dir = dir("garbage-pid");
+        let path = dir.join("dash.pid");
+        let path = path.to_str().unwrap();
+
+        // /proc/self exists, so this has to be parsed rather than looked up
+        for contents in ["self\n", "12ab\n", "-1\n"] {
+            fs::write(path, contents).unwrap();
+            write_pid_file(path).unwrap();
+            assert_eq!(fs::read_to_string(path).unwrap(), format!("{}\n", std::process::id()));
+        }
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -7,15 +7,18 @@ mod linux;
 mod halving;
 mod linux_version;
 
+use crate::scheduler::{self, Scheduler, when_due};
+use crate::status;
+
 use chrono::{DateTime, Utc};
 
-use log::{info, warn};
+use log::info;
 use std::time::Instant;
 
 use async_std::future;
 use std::time::Duration as stdDuration;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Stats {
     /* Uses a sneaky way to scrap financial data from FRED */
     pub d_spx500: Option<f64>,
@@ -27,22 +30,23 @@ pub struct Stats {
     pub kernel_version: Option<String>
 }
 
-pub async fn fetch_stats() -> Result<Stats, Box<dyn std::error::Error>> {
-    
+pub async fn fetch_stats(previous: Option<Stats>, scheduler: &mut Scheduler) -> Stats {
+    /*
+        Each statistic has its own refresh interval (see scheduler.rs), so only the due
+        ones are fetched and the rest are kept from the previous cycle.
+    */
+
     info!("Fetching statistics...");
     let now = Instant::now();
+    let at = scheduler::now();
+    let previous = previous.unwrap_or_default();
     
     let timeout = stdDuration::from_secs(25);
 
-    //let a = spx::fetch();
-    //let d = linux::fetch();
-    //let e = halving::fetch();
-    //let f = linux_version::fetch();
-
-    let a = future::timeout(timeout, spx::fetch());
-    let d = future::timeout(timeout, linux::fetch());
-    let e = future::timeout(timeout, halving::fetch());
-    let f = future::timeout(timeout, linux_version::fetch());
+    let a = when_due(scheduler.is_due("spx", at), status::timed(future::timeout(timeout, spx::fetch())));
+    let d = when_due(scheduler.is_due("linux_share", at), status::timed(future::timeout(timeout, linux::fetch())));
+    let e = when_due(scheduler.is_due("halving", at), status::timed(future::timeout(timeout, halving::fetch())));
+    let f = when_due(scheduler.is_due("kernel_version", at), status::timed(future::timeout(timeout, linux_version::fetch())));
 
     let (a, d, e, f) = (
         a.await, 
@@ -51,42 +55,24 @@ pub async fn fetch_stats() -> Result<Stats, Box<dyn std::error::Error>> {
         f.await
     );
 
-    let a = match a {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-    let d = match d {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-    let e = match e {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-    let f = match f {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-
-    match &a {Ok(_) => {}, Err(e) => warn!("SPX stats failed: {e}")}
-    match &d {Ok(_) => {}, Err(e) => warn!("Linux Share stats failed: {e}")}
-    match &e {Ok(_) => {}, Err(e) => warn!("Halving stats failed: {e}")}
-    match &f {Ok(_) => {}, Err(e) => warn!("Linux Version stats failed: {e}")}
-
     // staggered fetching to spread out fred.com requests 
-    let b = future::timeout(timeout, btc::fetch());
-    let c = future::timeout(timeout, yield_spread::fetch());
+    let b = when_due(scheduler.is_due("btc", at), status::timed(future::timeout(timeout, btc::fetch())));
+    let c = when_due(scheduler.is_due("yield_spread", at), status::timed(future::timeout(timeout, yield_spread::fetch())));
 
     let (b, c) = (
         b.await, 
         c.await, 
     );
 
-    let b = match b {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-    let c = match c {Ok(r) => {r}, Err(e) => Err(format!("Timeout: {e}").into())};
-
-    match &b {Ok(_) => {}, Err(e) => warn!("BTC stats failed: {e}")}
-    match &c {Ok(_) => {}, Err(e) => warn!("Yield Spread Version stats failed: {e}")}
-
     let elapsed = format!("{:.2?}", now.elapsed());
     info!("Statistics took {elapsed}");
     
-    Ok(
-        Stats{
-            d_spx500: a.ok(),
-            d_btc: b.ok(),
-            yield_spread: c.ok(),
-            linux_share: d.ok(),
-            btc_halving: e.ok(),
-            kernel_version: f.ok()
-        }
-    )
-}
\ No newline at end of file
+    Stats{
+        d_spx500: scheduler.settle("spx", at, a).unwrap_or(previous.d_spx500),
+        d_btc: scheduler.settle("btc", at, b).unwrap_or(previous.d_btc),
+        yield_spread: scheduler.settle("yield_spread", at, c).unwrap_or(previous.yield_spread),
+        linux_share: scheduler.settle("linux_share", at, d).unwrap_or(previous.linux_share),
+        btc_halving: scheduler.settle("halving", at, e).unwrap_or(previous.btc_halving),
+        kernel_version: scheduler.settle("kernel_version", at, f).unwrap_or(previous.kernel_version)
+    }
+}
@@ -0,0 +1,116 @@
+use crate::metrics;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+/*
+    Shared state of the last cycle, so it can be inspected over http (see server.rs)
+    rather than having to ssh in and attach to tmux.
+*/
+
+#[derive(Debug, Default, Clone)]
+pub struct SourceStatus {
+    pub ok: bool,
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_duration: Duration,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Status {
+    pub sources: BTreeMap<String, SourceStatus>,
+    pub timings: BTreeMap<String, Duration>,
+    pub last_svg: Option<String>,
+    pub last_png: Option<Vec<u8>>,
+    pub last_render: Option<DateTime<Utc>>,
+}
+
+static STATUS: LazyLock<Mutex<Status>> = LazyLock::new(|| Mutex::new(Status::default()));
+static REFRESH: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+pub fn get() -> MutexGuard<'static, Status> {
+    // A panic while holding the lock should not take the status page down with it
+    STATUS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub async fn timed<F: Future>(future: F) -> (F::Output, Duration) {
+    let now = Instant::now();
+    let result = future.await;
+    (result, now.elapsed())
+}
+
+pub fn record<T, E: Display>(source: &str, duration: Duration, result: &Result<T, E>) {
+    metrics::observe_fetch(source, duration, result.is_ok());
+
+    let now = Utc::now();
+    let mut status = get();
+    let source = status.sources.entry(source.to_string()).or_default();
+
+    source.last_attempt = Some(now);
+    source.last_duration = duration;
+
+    match result {
+        Ok(_) => {
+            source.ok = true;
+            source.last_success = Some(now);
+        },
+        Err(e) => {
+            source.ok = false;
+            source.last_error = Some(e.to_string());
+        }
+    }
+}
+
+pub fn record_timing(stage: &str, duration: Duration) {
+    metrics::observe_stage(stage, duration);
+    get().timings.insert(stage.to_string(), duration);
+}
+
+pub fn record_frame(svg: String, png: Vec<u8>) {
+    let mut status = get();
+    status.last_svg = Some(svg);
+    status.last_png = Some(png);
+    status.last_render = Some(Utc::now());
+}
+
+pub fn request_refresh() {
+    // Stores a permit if nobody is waiting yet, so a request made mid-cycle is not lost
+    REFRESH.notify_one();
+}
+
+pub async fn refresh_requested() {
+    REFRESH.notified().await;
+}
+
+pub fn to_json() -> Value {
+    let status = get();
+
+    let sources: serde_json::Map<String, Value> = status.sources.iter().map(|(name, source)| {
+        (name.clone(), json!({
+            "ok": source.ok,
+            "last_attempt": source.last_attempt.map(|t| t.to_rfc3339()),
+            "last_success": source.last_success.map(|t| t.to_rfc3339()),
+            "last_duration_ms": source.last_duration.as_millis() as u64,
+            "last_error": source.last_error,
+        }))
+    }).collect();
+
+    let timings: serde_json::Map<String, Value> = status.timings.iter().map(|(stage, duration)| {
+        (format!("{stage}_ms"), json!(duration.as_millis() as u64))
+    }).collect();
+
+    json!({
+        "last_render": status.last_render.map(|t| t.to_rfc3339()),
+        "sources": sources,
+        "timings": timings,
+    })
+}
@@ -36,10 +36,7 @@ pub fn check_eips() -> Result<(), String> {
 
 pub async fn check_internet() -> bool {
     info!("Checking for internet...");
-    match get("http://www.google.com").await {
-        Ok(_) => true,
-        Err(_) => false,
-    }
+    get("http://www.google.com").await.is_ok()
 }
 
 pub async fn check_internet_with_retries(max_retries: u32, delay: Duration) -> Result<(), ()> {
@@ -0,0 +1,311 @@
+use serde::Deserialize;
+use serde_json::from_reader;
+use std::fs::File;
+
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use reqwest::header::USER_AGENT;
+use roxmltree::Node;
+use std::collections::HashSet;
+use std::error::Error;
+
+use log::{info, warn};
+use std::time::Instant;
+
+/*
+    Severe weather warnings (thunderstorms, fire weather, heat, floods...) from the BOM, shown in a
+    banner over the radar while any are active.
+
+    Only enabled when sensitive/warnings.json exists, eg {"state": "nsw", "district": "Sydney Metropolitan"}
+    which reads the state's warnings RSS. Any other feed, such as a CAP-AU file, can be given as "url"
+    instead. Only warnings mentioning the district (in their title, or a CAP alert's areas) are kept.
+
+    CAP alerts say when they expire. RSS items do not, so they are taken as expired "max_age_hours"
+    (24 by default) after they were issued, if the BOM has not already taken them off the feed.
+*/
+
+#[derive(Deserialize, Debug)]
+struct WarningsConfig {
+    state: Option<String>,
+    url: Option<String>,
+    district: Option<String>,
+    #[serde(default = "default_max_age")]
+    max_age_hours: i64,
+}
+
+fn default_max_age() -> i64 {24}
+
+fn read_config() -> Option<WarningsConfig> {
+    let file = File::open("sensitive/warnings.json").ok()?;
+
+    match from_reader(file) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            warn!("Could not parse sensitive/warnings.json: {e}");
+            None
+        }
+    }
+}
+
+impl WarningsConfig {
+    fn feed(&self) -> Result<String, String> {
+        if let Some(url) = &self.url {
+            return Ok(url.clone())
+        }
+
+        let state = self.state.as_deref().ok_or("sensitive/warnings.json needs a state or a url")?;
+        let product = match state.to_lowercase().as_str() {
+            "nsw" | "act" => "IDZ00054.warnings_nsw",
+            "nt" => "IDZ00055.warnings_nt",
+            "qld" => "IDZ00056.warnings_qld",
+            "sa" => "IDZ00057.warnings_sa",
+            "tas" => "IDZ00058.warnings_tas",
+            "vic" => "IDZ00059.warnings_vic",
+            "wa" => "IDZ00060.warnings_wa",
+            _ => return Err(format!("Unknown state {state} in sensitive/warnings.json"))
+        };
+        Ok(format!("https://www.bom.gov.au/fwo/{product}.xml"))
+    }
+}
+
+/* CAP's severities, least to most severe. RSS items are all Unknown */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Severity {
+    #[default]
+    Unknown,
+    Minor,
+    Moderate,
+    Severe,
+    Extreme,
+}
+
+impl Severity {
+    fn parse(severity: &str) -> Severity {
+        match severity {
+            "Minor" => Severity::Minor,
+            "Moderate" => Severity::Moderate,
+            "Severe" => Severity::Severe,
+            "Extreme" => Severity::Extreme,
+            _ => Severity::Unknown
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub title: String,
+    pub area: Option<String>,
+    pub severity: Severity,
+    pub issued: Option<DateTime<Utc>>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl Warning {
+    fn is_active(&self, now: DateTime<Utc>, max_age: Duration) -> bool {
+        match (self.expires, self.issued) {
+            (Some(expires), _) => expires > now,
+            (None, Some(issued)) => issued + max_age > now,
+            (None, None) => true
+        }
+    }
+
+    fn mentions(&self, district: &str) -> bool {
+        let district = district.to_lowercase();
+        self.title.to_lowercase().contains(&district) || self.area.as_ref().is_some_and(|area| area.to_lowercase().contains(&district))
+    }
+}
+
+fn child<'a>(node: Node<'a, 'a>, name: &str) -> Option<Node<'a, 'a>> {
+    // By local name, as RSS has no namespace and CAP has had a few
+    node.children().find(|child| child.tag_name().name() == name)
+}
+
+fn child_text<'a>(node: Node<'a, 'a>, name: &str) -> Option<&'a str> {
+    child(node, name).and_then(|child| child.text()).map(str::trim)
+}
+
+fn rss_warnings(document: &roxmltree::Document) -> Vec<Warning> {
+    // Titles start with when they were issued in local time, eg "30/13:40 EST", which pubDate already has
+    let issued = Regex::new(r"^\d{2}/\d{2}:\d{2} [A-Z]{3,4}\s+").expect("Invalid regex");
+
+    document.descendants()
+        .filter(|node| node.has_tag_name("item"))
+        .filter_map(|item| {
+            let title = child_text(item, "title")?;
+            Some(Warning {
+                title: issued.replace(title, "").to_string(),
+                area: None,
+                severity: Severity::Unknown,
+                issued: child_text(item, "pubDate").and_then(|date| DateTime::parse_from_rfc2822(date).ok()).map(|date| date.to_utc()),
+                expires: None,
+            })
+        })
+        // Cancelled warnings and final (flood) warnings stay on the feed a while, but are over
+        .filter(|warning| !warning.title.starts_with("Cancellation") && !warning.title.starts_with("Final"))
+        .collect()
+}
+
+fn cap_warnings(document: &roxmltree::Document) -> Vec<Warning> {
+    let time = |node: Node, name: &str| child_text(node, name).and_then(|time| DateTime::parse_from_rfc3339(time).ok()).map(|time| time.to_utc());
+
+    let alerts: Vec<Node> = document.descendants()
+        .filter(|node| node.tag_name().name() == "alert")
+        .filter(|alert| child_text(*alert, "status") == Some("Actual"))
+        .collect();
+
+    // Updates and cancellations list the alerts they replace as "sender,identifier,sent"
+    let replaced: HashSet<&str> = alerts.iter()
+        .filter_map(|alert| child_text(*alert, "references"))
+        .flat_map(|references| references.split_whitespace())
+        .filter_map(|reference| reference.split(',').nth(1))
+        .collect();
+
+    alerts.iter()
+        .filter(|alert| child_text(**alert, "msgType") != Some("Cancel"))
+        .filter(|alert| child_text(**alert, "identifier").is_none_or(|identifier| !replaced.contains(identifier)))
+        .flat_map(|alert| alert.children().filter(|node| node.tag_name().name() == "info").map(move |info| (alert, info)))
+        .filter_map(|(alert, info)| {
+            let areas: Vec<&str> = info.children()
+                .filter(|node| node.tag_name().name() == "area")
+                .filter_map(|area| child_text(area, "areaDesc"))
+                .collect();
+
+            Some(Warning {
+                title: child_text(info, "headline").or(child_text(info, "event"))?.to_string(),
+                area: if areas.is_empty() {None} else {Some(areas.join(", "))},
+                severity: child_text(info, "severity").map_or(Severity::Unknown, Severity::parse),
+                issued: time(*alert, "sent"),
+                expires: time(info, "expires"),
+            })
+        })
+        .collect()
+}
+
+fn active(document: &str, now: DateTime<Utc>, config: &WarningsConfig) -> Result<Vec<Warning>, String> {
+    let document = roxmltree::Document::parse(document).map_err(|e| format!("Invalid warnings feed: {e}"))?;
+
+    let warnings = match document.root_element().tag_name().name() {
+        "rss" => rss_warnings(&document),
+        _ => cap_warnings(&document)
+    };
+
+    let max_age = Duration::hours(config.max_age_hours);
+    let mut warnings: Vec<Warning> = warnings.into_iter()
+        .filter(|warning| warning.is_active(now, max_age))
+        .filter(|warning| config.district.as_ref().is_none_or(|district| warning.mentions(district)))
+        .collect();
+
+    // Most severe first, then the newest
+    warnings.sort_by(|a, b| b.severity.cmp(&a.severity).then(b.issued.cmp(&a.issued)));
+    let mut seen = HashSet::new();
+    warnings.retain(|warning| seen.insert(warning.title.clone()));
+
+    Ok(warnings)
+}
+
+async fn fetch(config: &WarningsConfig, now: DateTime<Utc>) -> Result<Vec<Warning>, Box<dyn Error>> {
+    let client = reqwest::Client::new();
+    let response = client
+        .get(config.feed()?)
+        .header(USER_AGENT, "Mozilla/5.0 (Android 4.4; Mobile; rv:41.0) Gecko/41.0 Firefox/41.0")
+        .send()
+        .await?;
+    let response = response.error_for_status()?;
+    let body = response.text().await?;
+
+    Ok(active(&body, now, config)?)
+}
+
+pub async fn fetch_warnings() -> Result<Vec<Warning>, Box<dyn Error>> {
+    let config = match read_config() {
+        Some(config) => config,
+        None => return Ok(vec![])
+    };
+
+    info!("Fetching warnings...");
+    let now = Instant::now();
+
+    let warnings = fetch(&config, Utc::now()).await?;
+    for warning in &warnings {
+        info!("Warning: {} ({:?}, until {:?})", warning.title, warning.severity, warning.expires);
+    }
+
+    let elapsed = format!("{:.2?}", now.elapsed());
+    info!("Warnings took {elapsed}");
+
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, Reply};
+
+    fn config(district: Option<&str>) -> WarningsConfig {
+        WarningsConfig { state: Some("nsw".to_string()), url: None, district: district.map(str::to_string), max_age_hours: default_max_age() }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-30T14:00:00+10:00").unwrap().to_utc()
+    }
+
+    fn titles(warnings: &[Warning]) -> Vec<&str> {
+        warnings.iter().map(|warning| warning.title.as_str()).collect()
+    }
+
+    #[test]
+    fn reads_current_rss_items_for_the_district() {
+        let warnings = active(include_str!("fixtures/warnings/bom_nsw.xml"), now(), &config(Some("sydney metropolitan"))).unwrap();
+
+        // Not the cancellation, the flood watch elsewhere, or the surf warning from three days ago
+        assert_eq!(titles(&warnings), [
+            "Severe Thunderstorm Warning for people in Sydney Metropolitan, Illawarra and parts of Hunter Forecast Districts.",
+            "Fire Weather Warning for Greater Sydney Region (Sydney Metropolitan)"
+        ]);
+        assert_eq!(warnings[0].issued.map(|issued| issued.to_rfc3339()).as_deref(), Some("2024-05-30T03:40:00+00:00"));
+
+        let everywhere = active(include_str!("fixtures/warnings/bom_nsw.xml"), now(), &config(None)).unwrap();
+        assert_eq!(everywhere.len(), 3);
+    }
+
+    #[test]
+    fn reads_cap_alerts_by_severity() {
+        let warnings = active(include_str!("fixtures/warnings/cap.xml"), now(), &config(Some("Sydney Metropolitan"))).unwrap();
+
+        // The thunderstorm's update replaces it, the winds have expired and the tsunami is an exercise
+        assert_eq!(titles(&warnings), ["Fire Weather Warning for Greater Sydney", "Severe Thunderstorm Warning for Sydney Metropolitan and Illawarra"]);
+        assert_eq!(warnings[0].severity, Severity::Extreme);
+        assert_eq!(warnings[1].area.as_deref(), Some("Sydney Metropolitan, Illawarra"));
+        assert_eq!(warnings[1].expires.map(|expires| expires.to_rfc3339()).as_deref(), Some("2024-05-30T07:00:00+00:00"));
+
+        // Once the storm has passed
+        let later = active(include_str!("fixtures/warnings/cap.xml"), now() + Duration::hours(4), &config(Some("Sydney Metropolitan"))).unwrap();
+        assert_eq!(titles(&later), ["Fire Weather Warning for Greater Sydney"]);
+    }
+
+    #[test]
+    fn picks_the_state_feed() {
+        assert_eq!(config(None).feed().unwrap(), "https://www.bom.gov.au/fwo/IDZ00054.warnings_nsw.xml");
+
+        let vic = WarningsConfig { state: Some("VIC".to_string()), ..config(None) };
+        assert_eq!(vic.feed().unwrap(), "https://www.bom.gov.au/fwo/IDZ00059.warnings_vic.xml");
+
+        let neither = WarningsConfig { state: None, ..config(None) };
+        assert!(neither.feed().is_err());
+    }
+
+    #[tokio::test]
+    async fn fetches_a_configured_feed() {
+        let server = mock::serve(|request| match request.path.as_str() {
+            "/cap.xml" => Reply::ok(include_str!("fixtures/warnings/cap.xml")),
+            _ => Reply::new(404, "")
+        }).await;
+
+        let config = WarningsConfig { url: Some(format!("{}/cap.xml", server.url)), ..config(Some("Hunter")) };
+        let warnings = fetch(&config, now()).await.unwrap();
+        assert_eq!(titles(&warnings), ["Heatwave Warning for the Hunter"]);
+
+        let missing = WarningsConfig { url: Some(format!("{}/nope.xml", server.url)), ..config };
+        assert!(fetch(&missing, now()).await.is_err());
+    }
+}
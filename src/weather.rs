@@ -1,121 +1,344 @@
-use reqwest;
+mod bom;
+mod met_norway;
+mod open_meteo;
+mod owm;
+mod sun;
+
+pub use owm::read_key as openweathermap_key;
+
 use serde::Deserialize;
+use serde_json::from_reader;
 use chrono::prelude::*;
+use chrono_tz::Tz;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
 
-use log::info;
+use log::{info, warn};
 use std::time::Instant;
 
+/*
+    Where the forecast comes from, from sensitive/weather.json, eg
+    {"provider": "open-meteo", "latitude": -33.8679, "longitude": 151.2073}
+
+    Providers are "openweathermap" (the default, with its key in sensitive/openweatherkey.json),
+    "open-meteo", "met-norway" and "bom", which goes by geohash instead, eg {"provider": "bom", "geohash": "r3gx2f"}.
+    Each turns its own forecast into the points below, condition codes and all.
+
+    met-norway and bom give times in UTC, which are put into "timezone" (eg "Australia/Sydney", the
+    kindle's own by default) to split the days. MET Norway also asks for a "contact" (an email or
+    website) to send along with requests.
+*/
 
 #[derive(Deserialize, Debug)]
-struct OpenWeatherMapKey {
-    key: String,
+struct WeatherConfig {
+    #[serde(default = "default_provider")]
+    provider: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    geohash: Option<String>,
+    timezone: Option<String>,
+    contact: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-struct WeatherData {
-    list: Vec<Data>,
+fn default_provider() -> String {"openweathermap".to_string()}
+// Sydney, where this started
+const DEFAULT_COORDINATES: (f64, f64) = (-33.8679, 151.2073);
+
+impl Default for WeatherConfig {
+    fn default() -> WeatherConfig {
+        WeatherConfig { provider: default_provider(), latitude: None, longitude: None, geohash: None, timezone: None, contact: None }
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct Data {
-    dt: i64,
-    main: Main,
-    rain: Option<Rain>,
-    cloud: Option<Cloud>
+impl WeatherConfig {
+    fn configured_coordinates(&self) -> Option<(f64, f64)> {
+        // A bom config only has the geohash, which is as good
+        match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => Some((latitude, longitude)),
+            _ => self.geohash.as_deref().and_then(bom::decode_geohash)
+        }
+    }
+
+    fn coordinates(&self) -> (f64, f64) {
+        self.configured_coordinates().unwrap_or(DEFAULT_COORDINATES)
+    }
+
+    fn zone(&self) -> Option<Tz> {
+        let timezone = self.timezone.as_ref()?;
+        match timezone.parse() {
+            Ok(zone) => Some(zone),
+            Err(_) => {
+                warn!("Unknown timezone {timezone} in sensitive/weather.json, using the kindle's");
+                None
+            }
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct Main {
-    temp_min: f64,
-    temp_max: f64,
+fn read_config() -> WeatherConfig {
+    let file = match File::open("sensitive/weather.json") {
+        Ok(file) => file,
+        Err(_) => return WeatherConfig::default()
+    };
+
+    match from_reader(file) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Could not parse sensitive/weather.json, using openweathermap: {e}");
+            WeatherConfig::default()
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct Rain {
-    #[serde(rename = "3h")]
-    three_h: f64,
+pub trait WeatherProvider {
+    fn name(&self) -> &'static str;
+
+    async fn forecast(&self) -> Result<Forecast, Box<dyn Error>>;
+
+    async fn current(&self) -> Result<CurrentWeather, Box<dyn Error>>;
 }
 
-#[derive(Deserialize, Debug)]
-struct Cloud {
-    all: f64,
+/* One point of a provider's forecast, before it is summarised by day */
+#[derive(Debug, Clone)]
+pub struct Point {
+    /* In the location's own time zone, which decides the days */
+    pub time: DateTime<FixedOffset>,
+    pub temp_c: f64,
+    /* The same as temp_c, except for providers that give a range over the period */
+    pub temp_min_c: f64,
+    pub temp_max_c: f64,
+    /* Rain and snow since the previous point, in mm */
+    pub precipitation: f64,
+    /* Percent */
+    pub cloud_cover: f64,
+    /* As an OWM condition code, whatever the provider's own codes are */
+    pub condition: Option<u32>,
+    /* Metres per second */
+    pub wind: f64,
+    /* Chance of precipitation, 0 to 1 */
+    pub pop: f64,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct DayData {
-    pub data_points: i8,
+    pub data_points: usize,
     pub date: u32,
     pub day: String,
-    pub rain_sum: f64,
-    pub cloud_sum: f64,
-    pub max_c: f64,
     pub min_c: f64,
+    pub max_c: f64,
+    pub mean_c: f64,
+    /* Rain and snow, in mm */
+    pub precipitation: f64,
+    /* Percent */
+    pub cloud_cover: f64,
+    /* The most common OWM condition code (https://openweathermap.org/weather-conditions) */
+    pub condition: Option<u32>,
+    /* Metres per second */
+    pub max_wind: f64,
+    /* Chance of precipitation, 0 to 1 */
+    pub max_pop: f64,
 }
 
-// Async function to fetch weather data
-pub async fn fetch_weather() -> Result<Vec<DayData>, Box<dyn std::error::Error>> {
+#[derive(Debug, Clone)]
+pub struct HourData {
+    /* In the location's own time zone */
+    pub time: DateTime<FixedOffset>,
+    pub temp_c: f64,
+    /* Rain and snow since the previous point, in mm */
+    pub precipitation: f64,
+}
 
-    info!("Fetching weather...");
-    let now = Instant::now();
+/* The provider's points as they came, and summarised by day */
+#[derive(Default, Debug, Clone)]
+pub struct Forecast {
+    pub days: Vec<DayData>,
+    pub hours: Vec<HourData>,
+}
 
-    let file = std::fs::File::open("sensitive/openweatherkey.json")?;
-    let json_key:OpenWeatherMapKey = serde_json::from_reader(file)?;
-    let key = json_key.key;
-    let url = format!("http://api.openweathermap.org/data/2.5/forecast?lat=-33.8679&lon=151.2073&units=metric&appid={key}");
-    
-    let response = reqwest::get(&url).await?;
-    let response = response.error_for_status()?;
-    let weather_data: WeatherData = response.json().await?;
-    let mut result = vec![];
+#[derive(Debug, Clone)]
+pub struct CurrentWeather {
+    pub temp_c: f64,
+    pub feels_like_c: f64,
+    /* Percent */
+    pub humidity: f64,
+    /* Not in the BOM's observations */
+    pub pressure_hpa: Option<f64>,
+    /* Metres per second, and the compass bearing it blows from */
+    pub wind_speed: f64,
+    pub wind_deg: Option<f64>,
+    pub condition: Option<u32>,
+    /* Whether the sun is down, for the icon */
+    pub night: bool,
+    /* In the location's own time zone */
+    pub sunrise: DateTime<FixedOffset>,
+    pub sunset: DateTime<FixedOffset>,
+}
 
-    let mut current_day = chrono::offset::Utc::now().day();
+const POINTS: [&str; 16] = ["N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW", "NNW"];
 
-    for point in weather_data.list {
+pub fn compass(deg: f64) -> &'static str {
+    POINTS[((deg.rem_euclid(360.0) / 22.5).round() as usize) % 16]
+}
 
-        let point_day = DateTime::from_timestamp(point.dt, 0).ok_or("Invalid datetime")?;
+pub fn bearing(point: &str) -> Option<f64> {
+    // The other way, for providers that only give eg "SW"
+    POINTS.iter().position(|p| p.eq_ignore_ascii_case(point)).map(|i| i as f64 * 22.5)
+}
 
-        if result.len() == 0 {
-            let mut data = DayData::default();
-            current_day = point_day.day();
+fn severity(code: u32) -> u32 {
+    // Breaks ties between equally common conditions in favour of the worse one
+    match code / 100 {
+        2 => 6,
+        6 => 5,
+        5 => 4,
+        3 => 3,
+        7 => 2,
+        8 if code > 800 => 1,
+        _ => 0
+    }
+}
 
-            data.date = current_day;
-            data.day = point_day.weekday().to_string();
-            data.min_c = point.main.temp_min;
-            data.max_c = point.main.temp_max;
-            result.push(data);
+pub fn daily(points: &[Point]) -> Vec<DayData> {
+    let mut days: Vec<(NaiveDate, Vec<&Point>)> = vec![];
+    for point in points {
+        let date = point.time.date_naive();
+        match days.last_mut() {
+            Some((day, points)) if *day == date => points.push(point),
+            _ => days.push((date, vec![point]))
         }
+    }
 
-        if point_day.day() == current_day {
-            let current = result.pop();
-
-            match current {
-                Some(mut current) => {
-                    current.data_points += 1;
-                    if point.main.temp_min < current.min_c {current.min_c = point.main.temp_min}
-                    
-
-                    current.max_c = f64::max(current.max_c, point.main.temp_max);
-                    current.rain_sum += match point.rain { Some(rain) => rain.three_h, None => 0.0 };
-                    current.cloud_sum += match point.cloud { Some(cloud) => cloud.all, None => 0.0 };
-                    result.push(current);
-                },
-                None => {
-                    panic!("No weather data - This should be impossible to reach...")
-                }
-            }
-        } else {
-            let mut data = DayData::default();
-            data.date = point_day.day();
-            data.day = point_day.weekday().to_string();
-            data.min_c = f64::INFINITY;
-            data.max_c = f64::NEG_INFINITY;
-            result.push(data);
-            current_day += 1;
+    days.into_iter().map(|(date, points)| {
+        let count = points.len() as f64;
+
+        let mut conditions: HashMap<u32, usize> = HashMap::new();
+        for condition in points.iter().filter_map(|point| point.condition) {
+            *conditions.entry(condition).or_default() += 1;
+        }
+
+        DayData {
+            data_points: points.len(),
+            date: date.day(),
+            day: date.weekday().to_string(),
+            min_c: points.iter().map(|point| point.temp_min_c).fold(f64::INFINITY, f64::min),
+            max_c: points.iter().map(|point| point.temp_max_c).fold(f64::NEG_INFINITY, f64::max),
+            mean_c: points.iter().map(|point| point.temp_c).sum::<f64>() / count,
+            precipitation: points.iter().map(|point| point.precipitation).sum(),
+            cloud_cover: points.iter().map(|point| point.cloud_cover).sum::<f64>() / count,
+            condition: conditions.into_iter().max_by_key(|(code, times)| (*times, severity(*code))).map(|(code, _)| code),
+            max_wind: points.iter().map(|point| point.wind).fold(0.0, f64::max),
+            max_pop: points.iter().map(|point| point.pop).fold(0.0, f64::max),
         }
+    }).collect()
+}
+
+pub fn hourly(points: &[Point]) -> Vec<HourData> {
+    points.iter().map(|point| HourData { time: point.time, temp_c: point.temp_c, precipitation: point.precipitation }).collect()
+}
+
+pub fn summarise(points: &[Point]) -> Forecast {
+    Forecast { days: daily(points), hours: hourly(points) }
+}
+
+pub fn local(time: DateTime<Utc>, zone: Option<Tz>) -> DateTime<FixedOffset> {
+    match zone {
+        Some(zone) => time.with_timezone(&zone).fixed_offset(),
+        None => time.with_timezone(&Local).fixed_offset()
+    }
+}
+
+pub fn timestamp(seconds: i64, offset: FixedOffset) -> Result<DateTime<FixedOffset>, String> {
+    DateTime::from_timestamp(seconds, 0).map(|time| time.with_timezone(&offset)).ok_or(format!("Invalid timestamp {seconds}"))
+}
+
+pub fn coordinates() -> (f64, f64) {
+    // For the other sources that go by location
+    read_config().configured_coordinates().unwrap_or_else(|| {
+        warn!("No latitude and longitude (or geohash) in sensitive/weather.json, using Sydney's");
+        DEFAULT_COORDINATES
+    })
+}
+
+async fn forecast_from(provider: &impl WeatherProvider) -> Result<Forecast, Box<dyn Error>> {
+    info!("Fetching weather from {}...", provider.name());
+    let now = Instant::now();
+
+    let result = provider.forecast().await?;
+
+    for day in &result.days {
+        info!(
+            "Weather for {} {}: {:.1} to {:.1}°C (mean {:.1}), {:.1}mm ({:.0}% chance), {:.0}% cloud, wind up to {:.1}m/s, condition {:?}",
+            day.day, day.date, day.min_c, day.max_c, day.mean_c, day.precipitation, day.max_pop * 100.0, day.cloud_cover, day.max_wind, day.condition
+        );
     }
 
     let elapsed = format!("{:.2?}", now.elapsed());
     info!("Weather took {elapsed}");
 
-    Ok(result)    
-}
\ No newline at end of file
+    Ok(result)
+}
+
+async fn current_from(provider: &impl WeatherProvider) -> Result<CurrentWeather, Box<dyn Error>> {
+    info!("Fetching current conditions from {}...", provider.name());
+    let now = Instant::now();
+
+    let result = provider.current().await?;
+
+    let elapsed = format!("{:.2?}", now.elapsed());
+    info!("Current conditions took {elapsed}");
+
+    Ok(result)
+}
+
+pub async fn fetch_weather() -> Result<Forecast, Box<dyn Error>> {
+    let config = read_config();
+
+    match config.provider.as_str() {
+        "openweathermap" => forecast_from(&owm::OpenWeatherMap::new(&config)?).await,
+        "open-meteo" => forecast_from(&open_meteo::OpenMeteo::new(&config)).await,
+        "met-norway" => forecast_from(&met_norway::MetNorway::new(&config)).await,
+        "bom" => forecast_from(&bom::Bom::new(&config)?).await,
+        provider => Err(format!("Unknown weather provider {provider} in sensitive/weather.json").into())
+    }
+}
+
+pub async fn fetch_current() -> Result<CurrentWeather, Box<dyn Error>> {
+    let config = read_config();
+
+    match config.provider.as_str() {
+        "openweathermap" => current_from(&owm::OpenWeatherMap::new(&config)?).await,
+        "open-meteo" => current_from(&open_meteo::OpenMeteo::new(&config)).await,
+        "met-norway" => current_from(&met_norway::MetNorway::new(&config)).await,
+        "bom" => current_from(&bom::Bom::new(&config)?).await,
+        provider => Err(format!("Unknown weather provider {provider} in sensitive/weather.json").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_compass_points() {
+        assert_eq!(compass(0.0), "N");
+        assert_eq!(compass(225.0), "SW");
+        assert_eq!(compass(350.0), "N");
+        assert_eq!(compass(100.0), "E");
+        assert_eq!(compass(-22.5), "NNW");
+        assert_eq!((bearing("SW"), bearing("nnw"), bearing("CALM")), (Some(225.0), Some(337.5), None));
+    }
+
+    #[test]
+    fn reads_provider_config() {
+        let config: WeatherConfig = serde_json::from_str(r#"{"provider": "bom", "geohash": "r3gx2f"}"#).unwrap();
+        assert_eq!((config.provider.as_str(), config.geohash.as_deref()), ("bom", Some("r3gx2f")));
+        let (latitude, longitude) = config.coordinates();
+        assert!((latitude + 33.8681).abs() < 1e-4 && (longitude - 151.2103).abs() < 1e-4);
+
+        let config: WeatherConfig = serde_json::from_str(r#"{"provider": "open-meteo", "latitude": -37.8136, "longitude": 144.9631}"#).unwrap();
+        assert_eq!(config.coordinates(), (-37.8136, 144.9631));
+        assert_eq!(WeatherConfig::default().configured_coordinates(), None);
+        assert_eq!(WeatherConfig::default().coordinates(), DEFAULT_COORDINATES);
+    }
+}
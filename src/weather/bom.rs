@@ -0,0 +1,312 @@
+use super::sun;
+use super::{bearing, hourly, local, CurrentWeather, DayData, Forecast, Point, WeatherConfig, WeatherProvider};
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use chrono_tz::Tz;
+use reqwest::header::USER_AGENT;
+use serde::Deserialize;
+use std::error::Error;
+
+/*
+    The BOM's own forecasts (the api behind the weather.bom.gov.au pages), for a location's geohash.
+    Days come from its daily forecasts, which have the proper min/max, and the chart from its hourly
+    ones, which run a few days ahead.
+
+    Current conditions are the nearest station's latest observations, with the icon from the hourly
+    forecast and sunrise/sunset worked out from the geohash, as neither is observed.
+*/
+
+#[derive(Deserialize, Debug)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Deserialize, Debug)]
+struct Day {
+    /* The location's midnight, in UTC */
+    date: String,
+    temp_max: Option<f64>,
+    /* Missing for today once the morning is over */
+    temp_min: Option<f64>,
+    rain: Option<Rain>,
+    icon_descriptor: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Hour {
+    time: String,
+    temp: f64,
+    rain: Option<Rain>,
+    wind: Option<Wind>,
+    icon_descriptor: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Rain {
+    amount: Option<Amount>,
+    /* Percent */
+    chance: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Amount {
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Wind {
+    speed_kilometre: Option<f64>,
+    /* A compass point, eg "SW", or "CALM" */
+    direction: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Observations {
+    temp: Option<f64>,
+    temp_feels_like: Option<f64>,
+    humidity: Option<f64>,
+    wind: Option<Wind>,
+}
+
+pub struct Bom {
+    base: String,
+    geohash: String,
+    zone: Option<Tz>,
+}
+
+const BASE32: &str = "0123456789bcdefghjkmnpqrstuvwxyz";
+
+pub fn decode_geohash(geohash: &str) -> Option<(f64, f64)> {
+    // The middle of the cell, as (latitude, longitude)
+    let (mut latitude, mut longitude) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut is_longitude = true;
+
+    for c in geohash.chars() {
+        let bits = BASE32.find(c)?;
+        for i in (0..5).rev() {
+            let range: &mut (f64, f64) = if is_longitude {&mut longitude} else {&mut latitude};
+            let middle = (range.0 + range.1) / 2.0;
+            if bits >> i & 1 == 1 {range.0 = middle} else {range.1 = middle}
+            is_longitude = !is_longitude;
+        }
+    }
+
+    Some(((latitude.0 + latitude.1) / 2.0, (longitude.0 + longitude.1) / 2.0))
+}
+
+impl Bom {
+    pub fn new(config: &WeatherConfig) -> Result<Bom, String> {
+        // Hourly forecasts only take 6 characters, the BOM's search gives 7
+        let geohash = config.geohash.as_ref().ok_or("The bom weather provider needs a geohash in sensitive/weather.json")?;
+
+        Ok(Bom {
+            base: "https://api.weather.bom.gov.au".to_string(),
+            geohash: geohash.chars().take(6).collect(),
+            zone: config.zone(),
+        })
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<T, Box<dyn Error>> {
+        let url = format!("{}/v1/locations/{}/{path}", self.base, self.geohash);
+
+        let client = reqwest::Client::new();
+        let response = client
+            .get(url)
+            .header(USER_AGENT, "Mozilla/5.0 (Android 4.4; Mobile; rv:41.0) Gecko/41.0 Firefox/41.0")
+            .send()
+            .await?;
+        let response = response.error_for_status()?;
+        let response: Response<T> = response.json().await?;
+        Ok(response.data)
+    }
+}
+
+pub fn condition(icon: &str) -> u32 {
+    // The BOM's icons, as the nearest OWM condition
+    match icon {
+        "sunny" | "clear" | "frost" => 800,
+        "mostly_sunny" => 801,
+        "partly_cloudy" => 802,
+        "hazy" => 721,
+        "fog" => 741,
+        "dusty" => 761,
+        "wind" => 771,
+        "cyclone" => 781,
+        "light_rain" => 500,
+        "rain" => 501,
+        "light_shower" => 520,
+        "shower" => 521,
+        "heavy_shower" => 522,
+        "snow" => 601,
+        "storm" => 211,
+        _ => 804
+    }
+}
+
+fn parse_time(time: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(time).map(|time| time.to_utc()).map_err(|e| format!("Invalid time {time}: {e}"))
+}
+
+fn points(hours: &[Hour], zone: Option<Tz>) -> Result<Vec<Point>, String> {
+    let amount = |hour: &Hour| hour.rain.as_ref().and_then(|rain| rain.amount.as_ref()).and_then(|amount| amount.min).unwrap_or(0.0);
+
+    hours.iter().enumerate().map(|(i, hour)| Ok(Point {
+        time: local(parse_time(&hour.time)?, zone),
+        temp_c: hour.temp,
+        temp_min_c: hour.temp,
+        temp_max_c: hour.temp,
+        // Each hour's rain is for the hour after it, which is the hour before the next one
+        precipitation: i.checked_sub(1).map_or(0.0, |previous| amount(&hours[previous])),
+        cloud_cover: 0.0,
+        condition: hour.icon_descriptor.as_deref().map(condition),
+        wind: hour.wind.as_ref().and_then(|wind| wind.speed_kilometre).unwrap_or(0.0) / 3.6,
+        pop: hour.rain.as_ref().and_then(|rain| rain.chance).unwrap_or(0.0) / 100.0,
+    })).collect()
+}
+
+fn current(observations: &Observations, hours: &[Hour], geohash: &str, zone: Option<Tz>, now: DateTime<Utc>) -> Result<CurrentWeather, String> {
+    let (latitude, longitude) = decode_geohash(geohash).ok_or_else(|| format!("Invalid geohash {geohash}"))?;
+    let temp_c = observations.temp.ok_or("No temperature observed")?;
+    let wind = observations.wind.as_ref();
+
+    let now = local(now, zone);
+    let (sunrise, sunset) = sun::daylight(latitude, longitude, now)?;
+
+    Ok(CurrentWeather {
+        temp_c,
+        feels_like_c: observations.temp_feels_like.unwrap_or(temp_c),
+        humidity: observations.humidity.unwrap_or(0.0),
+        pressure_hpa: None,
+        wind_speed: wind.and_then(|wind| wind.speed_kilometre).unwrap_or(0.0) / 3.6,
+        wind_deg: wind.and_then(|wind| wind.direction.as_deref()).and_then(bearing),
+        condition: hours.first().and_then(|hour| hour.icon_descriptor.as_deref()).map(condition),
+        night: sun::is_night(now, sunrise, sunset),
+        sunrise,
+        sunset,
+    })
+}
+
+fn day(day: &Day, points: &[Point], zone: Option<Tz>) -> Result<DayData, String> {
+    let date: NaiveDate = local(parse_time(&day.date)?, zone).date_naive();
+    let hours: Vec<&Point> = points.iter().filter(|point| point.time.date_naive() == date).collect();
+    let temps = || hours.iter().map(|point| point.temp_c);
+
+    let min_c = day.temp_min.or(temps().reduce(f64::min)).or(day.temp_max).ok_or("No temperatures")?;
+    let max_c = day.temp_max.or(temps().reduce(f64::max)).unwrap_or(min_c);
+
+    // Rain comes as a likely range, eg 1 to 5mm
+    let amount = day.rain.as_ref().and_then(|rain| rain.amount.as_ref());
+    let low = amount.and_then(|amount| amount.min).unwrap_or(0.0);
+    let high = amount.and_then(|amount| amount.max).unwrap_or(low);
+
+    Ok(DayData {
+        data_points: hours.len(),
+        date: date.day(),
+        day: date.weekday().to_string(),
+        min_c,
+        max_c,
+        mean_c: if hours.is_empty() {(min_c + max_c) / 2.0} else {temps().sum::<f64>() / hours.len() as f64},
+        precipitation: (low + high) / 2.0,
+        cloud_cover: 0.0,
+        condition: day.icon_descriptor.as_deref().map(condition),
+        max_wind: hours.iter().map(|point| point.wind).fold(0.0, f64::max),
+        max_pop: day.rain.as_ref().and_then(|rain| rain.chance).unwrap_or(0.0) / 100.0,
+    })
+}
+
+impl WeatherProvider for Bom {
+    fn name(&self) -> &'static str {"bom"}
+
+    async fn forecast(&self) -> Result<Forecast, Box<dyn Error>> {
+        let days: Vec<Day> = self.get("forecasts/daily").await?;
+        let hours: Vec<Hour> = self.get("forecasts/hourly").await?;
+
+        let points = points(&hours, self.zone)?;
+        Ok(Forecast {
+            days: days.iter().map(|forecast| day(forecast, &points, self.zone)).collect::<Result<_, _>>()?,
+            hours: hourly(&points),
+        })
+    }
+
+    async fn current(&self) -> Result<CurrentWeather, Box<dyn Error>> {
+        let observations: Observations = self.get("observations").await?;
+        let hours: Vec<Hour> = self.get("forecasts/hourly").await?;
+        Ok(current(&observations, &hours, &self.geohash, self.zone, Utc::now())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, Reply};
+
+    fn provider(base: &str) -> Bom {
+        Bom { base: base.to_string(), geohash: "r3gx2f".to_string(), zone: Some(chrono_tz::Australia::Sydney) }
+    }
+
+    #[test]
+    fn maps_icons_to_owm_conditions() {
+        assert_eq!(["sunny", "mostly_sunny", "cloudy", "hazy", "shower", "storm", "cyclone"].map(condition), [800, 801, 804, 721, 521, 211, 781]);
+    }
+
+    #[tokio::test]
+    async fn fetches_daily_and_hourly_forecasts() {
+        let server = mock::serve(|request| match request.path.as_str() {
+            "/v1/locations/r3gx2f/forecasts/daily" => Reply::ok(include_str!("../fixtures/weather/bom_daily.json")),
+            "/v1/locations/r3gx2f/forecasts/hourly" => Reply::ok(include_str!("../fixtures/weather/bom_hourly.json")),
+            _ => Reply::new(404, "")
+        }).await;
+
+        let forecast = provider(&server.url).forecast().await.unwrap();
+
+        // Today's minimum has passed, so it comes from the hours left
+        let days: Vec<_> = forecast.days.iter().map(|day| (day.date, day.day.as_str(), day.data_points, day.min_c, day.max_c, day.condition)).collect();
+        assert_eq!(days, [(30, "Thu", 24, 10.0, 21.0, Some(801)), (31, "Fri", 6, 11.0, 19.0, Some(521)), (1, "Sat", 0, 9.0, 17.0, Some(802))]);
+
+        let (thursday, friday, saturday) = (&forecast.days[0], &forecast.days[1], &forecast.days[2]);
+        assert_eq!((thursday.mean_c, thursday.max_wind, thursday.max_pop), (15.5, 10.0, 0.05));
+        assert_eq!((friday.precipitation, friday.max_pop), (3.0, 0.8));
+        assert_eq!((saturday.mean_c, saturday.precipitation), (13.0, 0.2));
+
+        assert_eq!(forecast.hours.len(), 30);
+        assert_eq!(forecast.hours[0].time.format("%a %H:%M").to_string(), "Thu 00:00");
+        let rain: Vec<f64> = forecast.hours[25..].iter().map(|hour| hour.precipitation).collect();
+        assert_eq!(rain, [0.0, 0.0, 1.2, 1.2, 0.0]);
+
+        let agent = server.requests()[0].header("User-Agent").map(str::to_string);
+        assert!(agent.is_some_and(|agent| agent.starts_with("Mozilla")));
+    }
+
+    #[test]
+    fn decodes_geohashes() {
+        let (latitude, longitude) = decode_geohash("r3gx2f").unwrap();
+        assert!((latitude + 33.8681).abs() < 1e-4 && (longitude - 151.2103).abs() < 1e-4);
+        assert_eq!(decode_geohash("r3gx2a"), None);
+    }
+
+    #[tokio::test]
+    async fn fetches_current_conditions() {
+        let server = mock::serve(|request| match request.path.as_str() {
+            "/v1/locations/r3gx2f/observations" => Reply::ok(include_str!("../fixtures/weather/bom_observations.json")),
+            "/v1/locations/r3gx2f/forecasts/hourly" => Reply::ok(include_str!("../fixtures/weather/bom_hourly.json")),
+            _ => Reply::new(404, "")
+        }).await;
+
+        let now = provider(&server.url).current().await.unwrap();
+        assert_eq!((now.temp_c, now.feels_like_c, now.humidity, now.pressure_hpa), (11.2, 8.9, 81.0, None));
+        assert_eq!((now.wind_deg, now.condition), (Some(247.5), Some(801)));
+        assert!((now.wind_speed - 15.0 / 3.6).abs() < 1e-9);
+
+        let observations: Observations = serde_json::from_str::<Response<Observations>>(include_str!("../fixtures/weather/bom_observations.json")).unwrap().data;
+        let night = DateTime::parse_from_rfc3339("2024-05-29T14:30:00Z").unwrap().to_utc();
+        let now = current(&observations, &[], "r3gx2f", Some(chrono_tz::Australia::Sydney), night).unwrap();
+        assert_eq!((now.night, now.condition), (true, None));
+        assert_eq!(now.sunrise.format("%a %H:%M").to_string(), "Thu 06:51");
+    }
+
+    #[test]
+    fn needs_a_geohash() {
+        assert!(Bom::new(&WeatherConfig::default()).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use super::sun;
+use super::{local, summarise, CurrentWeather, Forecast, Point, WeatherConfig, WeatherProvider};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::error::Error;
+
+/*
+    MET Norway's locationforecast (https://api.met.no/weatherapi/locationforecast/2.0/documentation),
+    hourly for the first couple of days and 6 hourly after. It turns away requests without a user
+    agent saying who is asking.
+
+    Current conditions are the forecast's first step, which is the hour under way.
+*/
+
+#[derive(Deserialize, Debug)]
+struct ForecastData {
+    properties: Properties,
+}
+
+#[derive(Deserialize, Debug)]
+struct Properties {
+    timeseries: Vec<Step>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Step {
+    /* RFC 3339, in UTC */
+    time: String,
+    data: StepData,
+}
+
+#[derive(Deserialize, Debug)]
+struct StepData {
+    instant: Instant,
+    next_1_hours: Option<Period>,
+    next_6_hours: Option<Period>,
+    next_12_hours: Option<Period>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Instant {
+    details: InstantDetails,
+}
+
+#[derive(Deserialize, Debug)]
+struct InstantDetails {
+    air_temperature: f64,
+    cloud_area_fraction: Option<f64>,
+    wind_speed: Option<f64>,
+    wind_from_direction: Option<f64>,
+    relative_humidity: Option<f64>,
+    air_pressure_at_sea_level: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Period {
+    summary: Option<Summary>,
+    details: Option<PeriodDetails>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Summary {
+    symbol_code: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct PeriodDetails {
+    precipitation_amount: Option<f64>,
+    /* Only in the complete forecast, not the compact one */
+    probability_of_precipitation: Option<f64>,
+}
+
+pub struct MetNorway {
+    base: String,
+    latitude: f64,
+    longitude: f64,
+    user_agent: String,
+    zone: Option<Tz>,
+}
+
+impl MetNorway {
+    pub fn new(config: &WeatherConfig) -> MetNorway {
+        MetNorway {
+            base: "https://api.met.no".to_string(),
+            latitude: config.coordinates().0,
+            longitude: config.coordinates().1,
+            user_agent: format!("kindle-dashboard/{} {}", env!("CARGO_PKG_VERSION"), config.contact.as_deref().unwrap_or("")).trim().to_string(),
+            zone: config.zone(),
+        }
+    }
+}
+
+pub fn condition(symbol: &str) -> u32 {
+    // Symbols are eg "lightrainshowers_day", as the nearest OWM condition
+    let symbol = symbol.split('_').next().unwrap_or(symbol);
+    let light = symbol.starts_with("light");
+    let heavy = symbol.starts_with("heavy");
+    let showers = symbol.contains("showers");
+
+    if symbol.contains("thunder") {
+        if light {200} else if heavy {202} else {201}
+    } else if symbol.contains("sleet") {
+        if showers {613} else {611}
+    } else if symbol.contains("snow") {
+        if showers {621} else if light {600} else if heavy {602} else {601}
+    } else if symbol.contains("rain") {
+        match (showers, light, heavy) {
+            (true, true, _) => 520,
+            (true, _, true) => 522,
+            (true, _, _) => 521,
+            (false, true, _) => 500,
+            (false, _, true) => 502,
+            _ => 501
+        }
+    } else {
+        match symbol {
+            "clearsky" => 800,
+            "fair" => 801,
+            "partlycloudy" => 802,
+            "fog" => 741,
+            _ => 804
+        }
+    }
+}
+
+pub fn apparent_temperature(temp_c: f64, humidity: f64, wind: f64) -> f64 {
+    // The BOM's "feels like", from the temperature, humidity and wind (m/s)
+    let vapour_pressure = humidity / 100.0 * 6.105 * (17.27 * temp_c / (237.7 + temp_c)).exp();
+    temp_c + 0.33 * vapour_pressure - 0.70 * wind - 4.00
+}
+
+fn current(forecast: &ForecastData, latitude: f64, longitude: f64, zone: Option<Tz>, now: DateTime<Utc>) -> Result<CurrentWeather, String> {
+    let step = forecast.properties.timeseries.first().ok_or("Empty forecast")?;
+    let details = &step.data.instant.details;
+    let humidity = details.relative_humidity.ok_or("No humidity in the forecast")?;
+    let wind = details.wind_speed.unwrap_or(0.0);
+
+    let now = local(now, zone);
+    let (sunrise, sunset) = sun::daylight(latitude, longitude, now)?;
+
+    Ok(CurrentWeather {
+        temp_c: details.air_temperature,
+        feels_like_c: apparent_temperature(details.air_temperature, humidity, wind),
+        humidity,
+        pressure_hpa: details.air_pressure_at_sea_level,
+        wind_speed: wind,
+        wind_deg: details.wind_from_direction,
+        condition: step.data.next_1_hours.as_ref().and_then(|period| period.summary.as_ref()).map(|summary| condition(&summary.symbol_code)),
+        night: sun::is_night(now, sunrise, sunset),
+        sunrise,
+        sunset,
+    })
+}
+
+fn points(forecast: &ForecastData, zone: Option<Tz>) -> Result<Vec<Point>, String> {
+    let steps = &forecast.properties.timeseries;
+    let times = steps.iter()
+        .map(|step| DateTime::parse_from_rfc3339(&step.time).map(|time| time.to_utc()).map_err(|e| format!("Invalid time {}: {e}", step.time)))
+        .collect::<Result<Vec<DateTime<Utc>>, String>>()?;
+
+    Ok(steps.iter().enumerate().map(|(i, step)| {
+        let data = &step.data;
+        let period = data.next_1_hours.as_ref().or(data.next_6_hours.as_ref()).or(data.next_12_hours.as_ref());
+
+        // Amounts are for the hours after each step, which are the hours before the next one
+        let fell = i.checked_sub(1).and_then(|previous| match (times[i] - times[previous]).num_hours() {
+            1 => steps[previous].data.next_1_hours.as_ref(),
+            6 => steps[previous].data.next_6_hours.as_ref(),
+            _ => None
+        });
+
+        Point {
+            time: local(times[i], zone),
+            temp_c: data.instant.details.air_temperature,
+            temp_min_c: data.instant.details.air_temperature,
+            temp_max_c: data.instant.details.air_temperature,
+            precipitation: fell.and_then(|period| period.details.as_ref()?.precipitation_amount).unwrap_or(0.0),
+            cloud_cover: data.instant.details.cloud_area_fraction.unwrap_or(0.0),
+            condition: period.and_then(|period| period.summary.as_ref()).map(|summary| condition(&summary.symbol_code)),
+            wind: data.instant.details.wind_speed.unwrap_or(0.0),
+            pop: period.and_then(|period| period.details.as_ref()?.probability_of_precipitation).unwrap_or(0.0) / 100.0,
+        }
+    }).collect())
+}
+
+impl MetNorway {
+    async fn get(&self) -> Result<ForecastData, Box<dyn Error>> {
+        // Coordinates past 4 decimals are refused
+        let url = format!("{}/weatherapi/locationforecast/2.0/compact?lat={:.4}&lon={:.4}", self.base, self.latitude, self.longitude);
+
+        let client = reqwest::Client::builder().user_agent(&self.user_agent).build()?;
+        let response = client.get(&url).send().await?;
+        let response = response.error_for_status()?;
+        Ok(response.json().await?)
+    }
+}
+
+impl WeatherProvider for MetNorway {
+    fn name(&self) -> &'static str {"met-norway"}
+
+    async fn forecast(&self) -> Result<Forecast, Box<dyn Error>> {
+        let forecast = self.get().await?;
+        Ok(summarise(&points(&forecast, self.zone)?))
+    }
+
+    async fn current(&self) -> Result<CurrentWeather, Box<dyn Error>> {
+        let forecast = self.get().await?;
+        Ok(current(&forecast, self.latitude, self.longitude, self.zone, Utc::now())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, Reply};
+
+    fn provider(base: &str) -> MetNorway {
+        MetNorway {
+            base: base.to_string(),
+            latitude: -33.86789,
+            longitude: 151.2073,
+            user_agent: "kindle-dashboard/0.1.0 me@example.com".to_string(),
+            zone: Some(chrono_tz::Australia::Sydney),
+        }
+    }
+
+    #[test]
+    fn maps_symbols_to_owm_conditions() {
+        let symbols = ["clearsky_night", "fair_day", "cloudy", "fog", "lightrainshowers_day", "heavyrain", "sleet", "lightsnow", "rainandthunder"];
+        assert_eq!(symbols.map(condition), [800, 801, 804, 741, 520, 502, 611, 600, 201]);
+    }
+
+    #[tokio::test]
+    async fn fetches_hourly_then_six_hourly_steps() {
+        let server = mock::serve(|request| match request.header("User-Agent") {
+            Some(agent) if agent.contains("me@example.com") => Reply::ok(include_str!("../fixtures/weather/met_norway.json")),
+            _ => Reply::new(403, "Identify yourself")
+        }).await;
+
+        let forecast = provider(&server.url).forecast().await.unwrap();
+        assert_eq!(forecast.hours.len(), 28);
+        assert_eq!(forecast.hours[0].time.format("%a %d %H:%M").to_string(), "Thu 30 00:00");
+
+        // Each hour's rain lands on the step after it, and six hours of it on the six hourly steps
+        let rain: Vec<f64> = forecast.hours.iter().map(|hour| hour.precipitation).collect();
+        assert_eq!(rain[12..20], [0.0, 0.4, 0.4, 0.4, 0.4, 0.4, 0.4, 0.0]);
+        assert_eq!(rain[24..], [0.0, 1.5, 0.0, 0.6]);
+
+        let days: Vec<_> = forecast.days.iter().map(|day| (day.date, day.data_points, day.min_c, day.max_c, day.condition)).collect();
+        assert_eq!(days, [(30, 24, 10.0, 21.5, Some(500)), (31, 4, 13.0, 19.0, Some(802))]);
+        assert!((forecast.days[0].precipitation - 2.4).abs() < 1e-9);
+
+        assert_eq!(server.requests()[0].path, "/weatherapi/locationforecast/2.0/compact?lat=-33.8679&lon=151.2073");
+    }
+
+    #[test]
+    fn works_out_current_conditions() {
+        let forecast: ForecastData = serde_json::from_str(include_str!("../fixtures/weather/met_norway.json")).unwrap();
+        let now = DateTime::parse_from_rfc3339("2024-05-29T14:30:00Z").unwrap().to_utc();
+        let now = current(&forecast, -33.8679, 151.2073, Some(chrono_tz::Australia::Sydney), now).unwrap();
+
+        assert_eq!((now.temp_c, now.humidity, now.pressure_hpa), (10.0, 80.1, Some(1021.3)));
+        assert_eq!((now.wind_speed, now.wind_deg, now.condition, now.night), (3.0, Some(210.4), Some(800), true));
+        assert!((now.feels_like_c - 7.14).abs() < 0.01, "{}", now.feels_like_c);
+        assert_eq!(now.sunrise.format("%a %H:%M").to_string(), "Thu 06:51");
+        assert_eq!(now.sunset.format("%a %H:%M").to_string(), "Thu 16:56");
+    }
+
+    #[test]
+    fn feels_colder_in_the_wind() {
+        assert!((apparent_temperature(25.0, 50.0, 0.0) - 26.2).abs() < 0.1);
+        assert!((apparent_temperature(10.0, 50.0, 10.0) - 1.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,220 @@
+use super::{summarise, timestamp, CurrentWeather, Forecast, Point, WeatherConfig, WeatherProvider};
+
+use chrono::FixedOffset;
+use serde::Deserialize;
+use std::error::Error;
+
+/* Open-Meteo (https://open-meteo.com), hourly points for 6 days without needing a key */
+
+const HOURLY: &str = "temperature_2m,precipitation,precipitation_probability,cloud_cover,weather_code,wind_speed_10m";
+const CURRENT: &str = "temperature_2m,apparent_temperature,relative_humidity_2m,pressure_msl,wind_speed_10m,wind_direction_10m,weather_code,is_day";
+
+#[derive(Deserialize, Debug)]
+struct ForecastData {
+    utc_offset_seconds: i32,
+    hourly: Hourly,
+}
+
+/* Each a column of the same length as time, with gaps where the model has no value */
+#[derive(Deserialize, Debug)]
+struct Hourly {
+    time: Vec<i64>,
+    temperature_2m: Vec<Option<f64>>,
+    precipitation: Vec<Option<f64>>,
+    precipitation_probability: Vec<Option<f64>>,
+    cloud_cover: Vec<Option<f64>>,
+    weather_code: Vec<Option<u32>>,
+    wind_speed_10m: Vec<Option<f64>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CurrentData {
+    utc_offset_seconds: i32,
+    current: Current,
+    daily: Daily,
+}
+
+#[derive(Deserialize, Debug)]
+struct Current {
+    temperature_2m: f64,
+    apparent_temperature: f64,
+    relative_humidity_2m: f64,
+    pressure_msl: f64,
+    wind_speed_10m: f64,
+    wind_direction_10m: Option<f64>,
+    weather_code: Option<u32>,
+    is_day: u8,
+}
+
+#[derive(Deserialize, Debug)]
+struct Daily {
+    sunrise: Vec<i64>,
+    sunset: Vec<i64>,
+}
+
+pub struct OpenMeteo {
+    base: String,
+    latitude: f64,
+    longitude: f64,
+}
+
+impl OpenMeteo {
+    pub fn new(config: &WeatherConfig) -> OpenMeteo {
+        let (latitude, longitude) = config.coordinates();
+        OpenMeteo { base: "https://api.open-meteo.com".to_string(), latitude, longitude }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, query: &str) -> Result<T, Box<dyn Error>> {
+        let url = format!(
+            "{}/v1/forecast?latitude={}&longitude={}&{query}&wind_speed_unit=ms&timeformat=unixtime&timezone=auto",
+            self.base, self.latitude, self.longitude
+        );
+
+        let response = reqwest::get(&url).await?;
+        let response = response.error_for_status()?;
+        Ok(response.json().await?)
+    }
+}
+
+pub fn condition(wmo: u32) -> u32 {
+    // WMO weather interpretation codes, as the nearest OWM condition
+    match wmo {
+        0 => 800,
+        1 => 801,
+        2 => 802,
+        3 => 804,
+        45 | 48 => 741,
+        51 => 300,
+        53 => 301,
+        55 => 302,
+        56 | 57 | 66 | 67 => 511,
+        61 => 500,
+        63 => 501,
+        65 => 502,
+        71 | 77 => 600,
+        73 => 601,
+        75 => 602,
+        80 => 520,
+        81 => 521,
+        82 => 522,
+        85 => 620,
+        86 => 621,
+        95 => 211,
+        96 | 99 => 202,
+        _ => 804
+    }
+}
+
+fn points(forecast: &ForecastData) -> Result<Vec<Point>, String> {
+    let offset = FixedOffset::east_opt(forecast.utc_offset_seconds).ok_or("Invalid offset")?;
+    let hourly = &forecast.hourly;
+    let at = |column: &Vec<Option<f64>>, i: usize| column.get(i).copied().flatten();
+
+    let mut points = vec![];
+    for (i, time) in hourly.time.iter().enumerate() {
+        // The end of the forecast is sometimes only partly filled in
+        let temp = match at(&hourly.temperature_2m, i) {
+            Some(temp) => temp,
+            None => continue
+        };
+
+        points.push(Point {
+            time: timestamp(*time, offset)?,
+            temp_c: temp,
+            temp_min_c: temp,
+            temp_max_c: temp,
+            precipitation: at(&hourly.precipitation, i).unwrap_or(0.0),
+            cloud_cover: at(&hourly.cloud_cover, i).unwrap_or(0.0),
+            condition: hourly.weather_code.get(i).copied().flatten().map(condition),
+            wind: at(&hourly.wind_speed_10m, i).unwrap_or(0.0),
+            pop: at(&hourly.precipitation_probability, i).unwrap_or(0.0) / 100.0,
+        });
+    }
+    Ok(points)
+}
+
+fn current(data: &CurrentData) -> Result<CurrentWeather, String> {
+    let offset = FixedOffset::east_opt(data.utc_offset_seconds).ok_or("Invalid offset")?;
+    let now = &data.current;
+
+    Ok(CurrentWeather {
+        temp_c: now.temperature_2m,
+        feels_like_c: now.apparent_temperature,
+        humidity: now.relative_humidity_2m,
+        pressure_hpa: Some(now.pressure_msl),
+        wind_speed: now.wind_speed_10m,
+        wind_deg: now.wind_direction_10m,
+        condition: now.weather_code.map(condition),
+        night: now.is_day == 0,
+        sunrise: timestamp(*data.daily.sunrise.first().ok_or("No sunrise")?, offset)?,
+        sunset: timestamp(*data.daily.sunset.first().ok_or("No sunset")?, offset)?,
+    })
+}
+
+impl WeatherProvider for OpenMeteo {
+    fn name(&self) -> &'static str {"open-meteo"}
+
+    async fn forecast(&self) -> Result<Forecast, Box<dyn Error>> {
+        let forecast: ForecastData = self.get(&format!("hourly={HOURLY}&forecast_days=6")).await?;
+        Ok(summarise(&points(&forecast)?))
+    }
+
+    async fn current(&self) -> Result<CurrentWeather, Box<dyn Error>> {
+        let data: CurrentData = self.get(&format!("current={CURRENT}&daily=sunrise,sunset&forecast_days=1")).await?;
+        Ok(current(&data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, Reply};
+
+    fn provider(base: &str) -> OpenMeteo {
+        OpenMeteo { base: base.to_string(), latitude: -33.8679, longitude: 151.2073 }
+    }
+
+    async fn serve() -> mock::MockServer {
+        mock::serve(|request| match request.path.split_once("&current=") {
+            Some(_) => Reply::ok(include_str!("../fixtures/weather/open_meteo_current.json")),
+            None => Reply::ok(include_str!("../fixtures/weather/open_meteo_forecast.json"))
+        }).await
+    }
+
+    #[test]
+    fn maps_wmo_codes_to_owm_conditions() {
+        assert_eq!([0, 2, 3, 45, 53, 63, 66, 75, 81, 95, 99].map(condition), [800, 802, 804, 741, 301, 501, 511, 602, 521, 211, 202]);
+    }
+
+    #[tokio::test]
+    async fn fetches_hourly_points_by_local_day() {
+        let server = serve().await;
+        let forecast = provider(&server.url).forecast().await.unwrap();
+
+        assert_eq!(forecast.hours.len(), 48);
+        assert_eq!(forecast.hours[0].time.format("%a %d %H:%M").to_string(), "Thu 30 00:00");
+
+        let days: Vec<_> = forecast.days.iter().map(|day| (day.date, day.data_points, day.min_c, day.max_c, day.condition)).collect();
+        // Clear, partly cloudy, rain and overcast for six hours each, and the rain wins the tie
+        assert_eq!(days, [(30, 24, 10.0, 21.5, Some(500)), (31, 24, 14.25, 20.0, Some(801))]);
+
+        let thursday = &forecast.days[0];
+        assert!((thursday.precipitation - 2.4).abs() < 1e-9);
+        assert_eq!((thursday.max_pop, thursday.max_wind, thursday.cloud_cover), (0.8, 8.2, 50.0));
+
+        let path = &server.requests()[0].path;
+        assert!(path.starts_with("/v1/forecast?latitude=-33.8679&longitude=151.2073&hourly="), "{path}");
+        assert!(path.contains("wind_speed_unit=ms&timeformat=unixtime&timezone=auto"), "{path}");
+    }
+
+    #[tokio::test]
+    async fn fetches_current_conditions() {
+        let server = serve().await;
+        let now = provider(&server.url).current().await.unwrap();
+
+        assert_eq!((now.temp_c, now.feels_like_c, now.humidity, now.pressure_hpa), (13.4, 11.9, 78.0, Some(1021.3)));
+        assert_eq!((now.wind_speed, now.wind_deg, now.condition, now.night), (4.1, Some(212.0), Some(804), true));
+        assert_eq!(now.sunrise.format("%H:%M").to_string(), "06:43");
+        assert_eq!(now.sunset.format("%H:%M").to_string(), "17:00");
+    }
+}
@@ -0,0 +1,289 @@
+use super::sun;
+use super::{summarise, timestamp, CurrentWeather, Forecast, Point, WeatherConfig, WeatherProvider};
+
+use chrono::FixedOffset;
+use serde::Deserialize;
+use std::error::Error;
+
+/* OpenWeatherMap's free 2.5 api: 5 days of 3 hourly points, and the weather right now */
+
+#[derive(Deserialize, Debug)]
+struct OpenWeatherMapKey {
+    key: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct WeatherData {
+    list: Vec<Data>,
+    city: Option<City>,
+}
+
+#[derive(Deserialize, Debug)]
+struct City {
+    /* Seconds east of UTC, days are bucketed by the forecast location's own midnight */
+    timezone: i32,
+}
+
+#[derive(Deserialize, Debug)]
+struct Data {
+    dt: i64,
+    main: Main,
+    rain: Option<Precipitation>,
+    snow: Option<Precipitation>,
+    clouds: Option<Clouds>,
+    #[serde(default)]
+    weather: Vec<Condition>,
+    wind: Option<Wind>,
+    pop: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Main {
+    temp: f64,
+    temp_min: f64,
+    temp_max: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Precipitation {
+    #[serde(rename = "3h")]
+    three_h: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Clouds {
+    all: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Condition {
+    id: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct Wind {
+    speed: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct CurrentData {
+    dt: i64,
+    main: CurrentMain,
+    #[serde(default)]
+    weather: Vec<Condition>,
+    wind: Option<CurrentWind>,
+    sys: Sun,
+    timezone: i32,
+}
+
+#[derive(Deserialize, Debug)]
+struct CurrentMain {
+    temp: f64,
+    feels_like: f64,
+    humidity: f64,
+    pressure: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct CurrentWind {
+    speed: f64,
+    deg: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Sun {
+    sunrise: i64,
+    sunset: i64,
+}
+
+pub struct OpenWeatherMap {
+    base: String,
+    key: String,
+    latitude: f64,
+    longitude: f64,
+}
+
+pub fn read_key() -> Result<String, Box<dyn Error>> {
+    let file = std::fs::File::open("sensitive/openweatherkey.json")?;
+    let json_key: OpenWeatherMapKey = serde_json::from_reader(file)?;
+    Ok(json_key.key)
+}
+
+impl OpenWeatherMap {
+    pub fn new(config: &WeatherConfig) -> Result<OpenWeatherMap, Box<dyn Error>> {
+        Ok(OpenWeatherMap {
+            base: "http://api.openweathermap.org".to_string(),
+            key: read_key()?,
+            latitude: config.coordinates().0,
+            longitude: config.coordinates().1,
+        })
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, endpoint: &str) -> Result<T, Box<dyn Error>> {
+        let url = format!(
+            "{}/data/2.5/{endpoint}?lat={}&lon={}&units=metric&appid={}",
+            self.base, self.latitude, self.longitude, self.key
+        );
+
+        let response = reqwest::get(&url).await?;
+        let response = response.error_for_status()?;
+        Ok(response.json().await?)
+    }
+}
+
+fn points(forecast: &WeatherData) -> Result<Vec<Point>, String> {
+    let offset = forecast.city.as_ref()
+        .and_then(|city| FixedOffset::east_opt(city.timezone))
+        .map_or(FixedOffset::east_opt(0).ok_or("Invalid offset".to_string()), Ok)?;
+
+    forecast.list.iter().map(|point| Ok(Point {
+        time: timestamp(point.dt, offset)?,
+        temp_c: point.main.temp,
+        temp_min_c: point.main.temp_min,
+        temp_max_c: point.main.temp_max,
+        precipitation: [&point.rain, &point.snow].into_iter()
+            .filter_map(|precipitation| precipitation.as_ref()?.three_h)
+            .sum(),
+        cloud_cover: point.clouds.as_ref().map_or(0.0, |clouds| clouds.all),
+        condition: point.weather.first().map(|condition| condition.id),
+        wind: point.wind.as_ref().map_or(0.0, |wind| wind.speed),
+        pop: point.pop.unwrap_or(0.0),
+    })).collect()
+}
+
+fn current(data: &CurrentData) -> Result<CurrentWeather, String> {
+    let offset = FixedOffset::east_opt(data.timezone).ok_or("Invalid offset")?;
+
+    let sunrise = timestamp(data.sys.sunrise, offset)?;
+    let sunset = timestamp(data.sys.sunset, offset)?;
+    let now = timestamp(data.dt, offset)?;
+
+    Ok(CurrentWeather {
+        temp_c: data.main.temp,
+        feels_like_c: data.main.feels_like,
+        humidity: data.main.humidity,
+        pressure_hpa: Some(data.main.pressure),
+        wind_speed: data.wind.as_ref().map_or(0.0, |wind| wind.speed),
+        wind_deg: data.wind.as_ref().and_then(|wind| wind.deg),
+        condition: data.weather.first().map(|condition| condition.id),
+        night: sun::is_night(now, sunrise, sunset),
+        sunrise,
+        sunset,
+    })
+}
+
+impl WeatherProvider for OpenWeatherMap {
+    fn name(&self) -> &'static str {"openweathermap"}
+
+    async fn forecast(&self) -> Result<Forecast, Box<dyn Error>> {
+        let forecast: WeatherData = self.get("forecast").await?;
+        Ok(summarise(&points(&forecast)?))
+    }
+
+    async fn current(&self) -> Result<CurrentWeather, Box<dyn Error>> {
+        let data: CurrentData = self.get("weather").await?;
+        Ok(current(&data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{daily, DayData};
+    use crate::mock::{self, Reply};
+
+    fn forecast() -> Vec<DayData> {
+        let data: WeatherData = serde_json::from_str(include_str!("../fixtures/weather/owm_forecast.json")).unwrap();
+        daily(&points(&data).unwrap())
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn provider(base: &str) -> OpenWeatherMap {
+        OpenWeatherMap { base: base.to_string(), key: "KEY".to_string(), latitude: -33.8679, longitude: 151.2073 }
+    }
+
+    #[test]
+    fn buckets_by_local_day_across_months() {
+        let days = forecast();
+
+        // 10pm in Sydney on the 30th is a day of its own, not dropped into the 31st
+        let dates: Vec<_> = days.iter().map(|day| (day.date, day.day.as_str(), day.data_points)).collect();
+        assert_eq!(dates, [(30, "Thu", 1), (31, "Fri", 8), (1, "Sat", 8), (2, "Sun", 8), (3, "Mon", 8), (4, "Tue", 7)]);
+        assert_eq!(days.iter().map(|day| day.data_points).sum::<usize>(), 40);
+    }
+
+    #[test]
+    fn summarises_each_day() {
+        let days = forecast();
+
+        let first = &days[0];
+        assert_eq!((first.min_c, first.max_c, first.mean_c), (11.7, 12.4, 12.1));
+
+        let friday = &days[1];
+        assert_eq!((friday.min_c, friday.max_c), (9.8, 17.5));
+        assert!(close(friday.mean_c, 13.475));
+        assert!(close(friday.precipitation, 2.88));
+        assert!(close(friday.cloud_cover, 61.25));
+        assert_eq!((friday.max_wind, friday.max_pop), (7.4, 0.82));
+    }
+
+    #[test]
+    fn counts_snow_and_prefers_the_worse_of_tied_conditions() {
+        let days = forecast();
+
+        // Sunday's 3.2 + 2.4 + 0.3mm of rain and 0.6 + 0.9mm of snow
+        assert!(close(days[3].precipitation, 7.4));
+        // Snow, overcast and broken clouds twice each
+        assert_eq!(days[3].condition, Some(600));
+        // Broken clouds and light rain twice each
+        assert_eq!(days[1].condition, Some(500));
+        assert_eq!(days[2].condition, Some(800));
+    }
+
+    #[tokio::test]
+    async fn fetches_the_forecast_in_local_time() {
+        let server = mock::serve(|request| match request.path.starts_with("/data/2.5/forecast?") {
+            true => Reply::ok(include_str!("../fixtures/weather/owm_forecast.json")),
+            false => Reply::new(404, "")
+        }).await;
+
+        let forecast = provider(&server.url).forecast().await.unwrap();
+        assert_eq!(forecast.days.len(), 6);
+        assert_eq!(forecast.hours.len(), 40);
+        assert_eq!(forecast.hours[0].time.format("%a %H:%M").to_string(), "Thu 22:00");
+        assert_eq!(forecast.hours[1].time - forecast.hours[0].time, chrono::Duration::hours(3));
+
+        // The same rain and snow as the days they make up
+        let by_day: f64 = forecast.days.iter().map(|day| day.precipitation).sum();
+        assert!(close(forecast.hours.iter().map(|hour| hour.precipitation).sum(), by_day));
+
+        let path = &server.requests()[0].path;
+        assert!(path.contains("lat=-33.8679&lon=151.2073&units=metric&appid=KEY"), "{path}");
+    }
+
+    #[tokio::test]
+    async fn fetches_current_conditions() {
+        let server = mock::serve(|request| match request.path.starts_with("/data/2.5/weather?") {
+            true => Reply::ok(include_str!("../fixtures/weather/owm_current.json")),
+            false => Reply::new(404, "")
+        }).await;
+
+        let now = provider(&server.url).current().await.unwrap();
+        assert_eq!((now.temp_c, now.feels_like_c, now.humidity, now.pressure_hpa), (13.42, 12.81, 77.0, Some(1021.0)));
+        assert_eq!((now.wind_speed, now.wind_deg, now.condition), (4.63, Some(225.0), Some(803)));
+
+        // 10pm in Sydney, with the sun times in Sydney's time too
+        assert!(now.night);
+        assert_eq!(now.sunrise.format("%H:%M").to_string(), "06:43");
+        assert_eq!(now.sunset.format("%H:%M").to_string(), "17:00");
+    }
+
+    #[tokio::test]
+    async fn reports_a_bad_key() {
+        let server = mock::serve(|_| Reply::new(401, r#"{"cod": 401, "message": "Invalid API key"}"#)).await;
+        assert!(provider(&server.url).forecast().await.is_err());
+    }
+}
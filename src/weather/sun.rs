@@ -0,0 +1,77 @@
+use chrono::{DateTime, FixedOffset, NaiveDate};
+
+/*
+    Sunrise and sunset worked out from the location, for the providers that do not give them
+    (https://en.wikipedia.org/wiki/Sunrise_equation, good to a minute or two).
+*/
+
+const J2000: f64 = 2451545.0;
+const UNIX_EPOCH: f64 = 2440587.5;
+
+fn julian_to_utc(julian: f64) -> Option<DateTime<chrono::Utc>> {
+    DateTime::from_timestamp(((julian - UNIX_EPOCH) * 86400.0).round() as i64, 0)
+}
+
+pub fn times(latitude: f64, longitude: f64, date: NaiveDate) -> Option<(DateTime<chrono::Utc>, DateTime<chrono::Utc>)> {
+    let days = (date - NaiveDate::from_ymd_opt(2000, 1, 1)?).num_days() as f64 + 0.0008;
+    let noon = days - longitude / 360.0;
+
+    let anomaly = (357.5291 + 0.98560028 * noon).rem_euclid(360.0).to_radians();
+    let centre = 1.9148 * anomaly.sin() + 0.0200 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic = (anomaly.to_degrees() + centre + 180.0 + 102.9372).rem_euclid(360.0).to_radians();
+    let transit = J2000 + noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic).sin();
+
+    let declination = (ecliptic.sin() * 23.4397_f64.to_radians().sin()).asin();
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = ((-0.833_f64).to_radians().sin() - latitude.sin() * declination.sin()) / (latitude.cos() * declination.cos());
+
+    // Past the polar circles the sun can stay up (or down) all day, taken as rising and setting 12 hours either side of noon (or at noon)
+    let hour_angle = cos_hour_angle.clamp(-1.0, 1.0).acos().to_degrees();
+    Some((julian_to_utc(transit - hour_angle / 360.0)?, julian_to_utc(transit + hour_angle / 360.0)?))
+}
+
+pub fn daylight(latitude: f64, longitude: f64, now: DateTime<FixedOffset>) -> Result<(DateTime<FixedOffset>, DateTime<FixedOffset>), String> {
+    // Sunrise and sunset on the local day of `now`, in its offset
+    let (sunrise, sunset) = times(latitude, longitude, now.date_naive()).ok_or("Could not work out sunrise and sunset")?;
+    Ok((sunrise.with_timezone(now.offset()), sunset.with_timezone(now.offset())))
+}
+
+pub fn is_night(now: DateTime<FixedOffset>, sunrise: DateTime<FixedOffset>, sunset: DateTime<FixedOffset>) -> bool {
+    now < sunrise || now >= sunset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sydney(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    #[test]
+    fn works_out_sunrise_and_sunset() {
+        // The BOM had 06:51 and 16:54 that day, and 04:41 and 19:05 (in standard time) at the solstice
+        let (sunrise, sunset) = daylight(-33.8679, 151.2073, sydney("2024-05-30T12:00:00+10:00")).unwrap();
+        assert_eq!((sunrise.format("%d %H:%M").to_string(), sunset.format("%d %H:%M").to_string()), ("30 06:51".to_string(), "30 16:56".to_string()));
+        assert!(is_night(sydney("2024-05-30T06:45:00+10:00"), sunrise, sunset));
+        assert!(!is_night(sydney("2024-05-30T07:00:00+10:00"), sunrise, sunset));
+
+        let (sunrise, sunset) = daylight(-33.8679, 151.2073, sydney("2024-12-21T00:30:00+10:00")).unwrap();
+        assert_eq!((sunrise.format("%d %H:%M").to_string(), sunset.format("%d %H:%M").to_string()), ("21 04:42".to_string(), "21 19:06".to_string()));
+
+        assert!(!is_night(sydney("2024-12-21T19:00:00+10:00"), sunrise, sunset));
+        assert!(is_night(sydney("2024-12-21T19:30:00+10:00"), sunrise, sunset));
+    }
+
+    #[test]
+    fn copes_with_the_midnight_sun() {
+        // Tromsø in midsummer and midwinter
+        let summer = DateTime::parse_from_rfc3339("2024-06-21T12:00:00+02:00").unwrap();
+        let (sunrise, sunset) = daylight(69.6492, 18.9553, summer).unwrap();
+        assert!(!is_night(summer, sunrise, sunset));
+
+        let winter = DateTime::parse_from_rfc3339("2024-12-21T12:00:00+01:00").unwrap();
+        let (sunrise, sunset) = daylight(69.6492, 18.9553, winter).unwrap();
+        assert!(is_night(winter, sunrise, sunset));
+    }
+}